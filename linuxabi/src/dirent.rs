@@ -0,0 +1,23 @@
+//! from linux/include/linux/fs_types.h
+
+/// unknown file type
+pub const DT_UNKNOWN: u8 = 0;
+/// FIFO
+pub const DT_FIFO: u8 = 1;
+/// character device
+pub const DT_CHR: u8 = 2;
+/// directory
+pub const DT_DIR: u8 = 4;
+/// block device
+pub const DT_BLK: u8 = 6;
+/// regular file
+pub const DT_REG: u8 = 8;
+/// symbolic link
+pub const DT_LNK: u8 = 10;
+/// socket
+pub const DT_SOCK: u8 = 12;
+
+/// The offset of `d_name` in struct linux_dirent64 of getdents64, after
+/// `u64 d_ino`, `i64 d_off`, `u16 d_reclen` and `u8 d_type`. Each record
+/// holds the nul-terminated name and is padded to 8 bytes.
+pub const DIRENT64_NAME_OFFSET: usize = 19;
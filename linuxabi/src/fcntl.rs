@@ -0,0 +1,87 @@
+//! from linux/include/uapi/asm-generic/fcntl.h and linux/fcntl.h
+
+/// mask for the access mode
+pub const O_ACCMODE: u32 = 0o3;
+/// open for reading only
+pub const O_RDONLY: u32 = 0o0;
+/// open for writing only
+pub const O_WRONLY: u32 = 0o1;
+/// open for reading and writing
+pub const O_RDWR: u32 = 0o2;
+/// create the file if it does not exist
+pub const O_CREAT: u32 = 0o100;
+/// fail if the file exists, with O_CREAT
+pub const O_EXCL: u32 = 0o200;
+/// do not make the terminal the controlling terminal
+pub const O_NOCTTY: u32 = 0o400;
+/// truncate a regular file to length 0
+pub const O_TRUNC: u32 = 0o1000;
+/// every write appends to the end of the file
+pub const O_APPEND: u32 = 0o2000;
+/// non-blocking I/O
+pub const O_NONBLOCK: u32 = 0o4000;
+/// synchronized I/O data integrity
+pub const O_DSYNC: u32 = 0o10000;
+/// signal-driven I/O
+pub const FASYNC: u32 = 0o20000;
+/// direct disk access
+pub const O_DIRECT: u32 = 0o40000;
+/// allow files whose sizes do not fit in off_t
+pub const O_LARGEFILE: u32 = 0o100000;
+/// fail if not a directory
+pub const O_DIRECTORY: u32 = 0o200000;
+/// do not follow a symlink in the last component
+pub const O_NOFOLLOW: u32 = 0o400000;
+/// do not update the access time
+pub const O_NOATIME: u32 = 0o1000000;
+/// set close-on-exec on the new fd
+pub const O_CLOEXEC: u32 = 0o2000000;
+/// synchronized I/O file integrity, with O_DSYNC
+pub const O_SYNC: u32 = 0o4000000 | O_DSYNC;
+/// obtain an fd only usable for path operations
+pub const O_PATH: u32 = 0o10000000;
+/// create an unnamed temporary file, with O_DIRECTORY
+pub const O_TMPFILE: u32 = 0o20000000 | O_DIRECTORY;
+
+/// duplicate to the lowest fd not less than arg
+pub const F_DUPFD: u32 = 0;
+/// get the fd flags
+pub const F_GETFD: u32 = 1;
+/// set the fd flags
+pub const F_SETFD: u32 = 2;
+/// get the file status flags
+pub const F_GETFL: u32 = 3;
+/// set the file status flags
+pub const F_SETFL: u32 = 4;
+/// F_DUPFD with close-on-exec set
+pub const F_DUPFD_CLOEXEC: u32 = 1030;
+
+/// fd flag: close on exec
+pub const FD_CLOEXEC: u32 = 1;
+
+/// dirfd of *at() syscalls meaning the current working directory
+pub const AT_FDCWD: i32 = -100;
+/// do not follow symbolic links
+pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+/// remove a directory instead of a file, for unlinkat
+pub const AT_REMOVEDIR: u32 = 0x200;
+/// follow symbolic links
+pub const AT_SYMLINK_FOLLOW: u32 = 0x400;
+/// suppress terminal automount traversal
+pub const AT_NO_AUTOMOUNT: u32 = 0x800;
+/// operate on dirfd itself if the path is empty
+pub const AT_EMPTY_PATH: u32 = 0x1000;
+
+/// seek to an absolute offset
+pub const SEEK_SET: u32 = 0;
+/// seek relative to the current offset
+pub const SEEK_CUR: u32 = 1;
+/// seek relative to the end of the file
+pub const SEEK_END: u32 = 2;
+
+/// do not overwrite the target of renameat2, from linux/fs.h
+pub const RENAME_NOREPLACE: u32 = 1 << 0;
+/// exchange the source and the target of renameat2
+pub const RENAME_EXCHANGE: u32 = 1 << 1;
+/// leave a whiteout at the source of renameat2, for overlay filesystems
+pub const RENAME_WHITEOUT: u32 = 1 << 2;
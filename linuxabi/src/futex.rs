@@ -0,0 +1,29 @@
+//! from linux/include/uapi/linux/futex.h
+
+/// wait while the word holds a value
+pub const FUTEX_WAIT: u32 = 0;
+/// wake waiters
+pub const FUTEX_WAKE: u32 = 1;
+pub const FUTEX_FD: u32 = 2;
+/// wake waiters and move the others to another futex
+pub const FUTEX_REQUEUE: u32 = 3;
+/// FUTEX_REQUEUE if the word holds a value
+pub const FUTEX_CMP_REQUEUE: u32 = 4;
+pub const FUTEX_WAKE_OP: u32 = 5;
+pub const FUTEX_LOCK_PI: u32 = 6;
+pub const FUTEX_UNLOCK_PI: u32 = 7;
+pub const FUTEX_TRYLOCK_PI: u32 = 8;
+/// FUTEX_WAIT with a bitset and an absolute timeout
+pub const FUTEX_WAIT_BITSET: u32 = 9;
+/// FUTEX_WAKE of waiters sharing a bit of a bitset
+pub const FUTEX_WAKE_BITSET: u32 = 10;
+
+/// the futex is not shared with other processes
+pub const FUTEX_PRIVATE_FLAG: u32 = 128;
+/// the timeout of FUTEX_WAIT_BITSET is on CLOCK_REALTIME
+pub const FUTEX_CLOCK_REALTIME: u32 = 256;
+/// mask for the operation, without the flags
+pub const FUTEX_CMD_MASK: u32 = !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+
+/// the bitset matching every waiter
+pub const FUTEX_BITSET_MATCH_ANY: u32 = 0xffff_ffff;
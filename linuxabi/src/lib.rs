@@ -5,3 +5,31 @@
 pub mod syscall_id;
 
 pub mod time;
+
+pub mod auxv;
+
+pub mod errno;
+
+pub mod mman;
+
+pub mod resource;
+
+pub mod uio;
+
+pub mod limits;
+
+pub mod fcntl;
+
+pub mod stat;
+
+pub mod dirent;
+
+pub mod socket;
+
+pub mod termios;
+
+pub mod signal;
+
+pub mod sched;
+
+pub mod futex;
@@ -0,0 +1,6 @@
+//! from linux/include/uapi/linux/limits.h
+
+/// chars in a file name
+pub const NAME_MAX: usize = 255;
+/// chars in a path name including nul
+pub const PATH_MAX: usize = 4096;
@@ -0,0 +1,50 @@
+//! from linux/include/uapi/asm-generic/mman-common.h and linux/mman.h
+
+/// page can not be accessed
+pub const PROT_NONE: u32 = 0x0;
+/// page can be read
+pub const PROT_READ: u32 = 0x1;
+/// page can be written
+pub const PROT_WRITE: u32 = 0x2;
+/// page can be executed
+pub const PROT_EXEC: u32 = 0x4;
+
+/// share changes
+pub const MAP_SHARED: u32 = 0x01;
+/// changes are private
+pub const MAP_PRIVATE: u32 = 0x02;
+/// share changes and validate extension flags
+pub const MAP_SHARED_VALIDATE: u32 = 0x03;
+/// mask for type of mapping
+pub const MAP_TYPE: u32 = 0x0f;
+/// interpret addr exactly
+pub const MAP_FIXED: u32 = 0x10;
+/// don't use a file
+pub const MAP_ANONYMOUS: u32 = 0x20;
+/// stack-like segment
+pub const MAP_GROWSDOWN: u32 = 0x0100;
+/// ETXTBSY
+pub const MAP_DENYWRITE: u32 = 0x0800;
+/// mark it as an executable
+pub const MAP_EXECUTABLE: u32 = 0x1000;
+/// pages are locked
+pub const MAP_LOCKED: u32 = 0x2000;
+/// don't check for reservations
+pub const MAP_NORESERVE: u32 = 0x4000;
+/// populate (prefault) pagetables
+pub const MAP_POPULATE: u32 = 0x8000;
+/// do not block on IO
+pub const MAP_NONBLOCK: u32 = 0x10000;
+/// give out an address that is best suited for process/thread stacks
+pub const MAP_STACK: u32 = 0x20000;
+/// create a huge page mapping
+pub const MAP_HUGETLB: u32 = 0x40000;
+/// MAP_FIXED which doesn't unmap underlying mapping
+pub const MAP_FIXED_NOREPLACE: u32 = 0x100000;
+
+/// the mapping may be moved to a new address
+pub const MREMAP_MAYMOVE: u32 = 1;
+/// the mapping is moved to the given new address
+pub const MREMAP_FIXED: u32 = 2;
+/// the old mapping is kept after moving
+pub const MREMAP_DONTUNMAP: u32 = 4;
@@ -0,0 +1,35 @@
+//! from linux/include/uapi/asm-generic/resource.h
+
+/// CPU time in sec
+pub const RLIMIT_CPU: u32 = 0;
+/// maximum filesize
+pub const RLIMIT_FSIZE: u32 = 1;
+/// max data size
+pub const RLIMIT_DATA: u32 = 2;
+/// max stack size
+pub const RLIMIT_STACK: u32 = 3;
+/// max core file size
+pub const RLIMIT_CORE: u32 = 4;
+/// max resident set size
+pub const RLIMIT_RSS: u32 = 5;
+/// max number of processes
+pub const RLIMIT_NPROC: u32 = 6;
+/// max number of open files
+pub const RLIMIT_NOFILE: u32 = 7;
+/// max locked-in-memory address space
+pub const RLIMIT_MEMLOCK: u32 = 8;
+/// address space limit
+pub const RLIMIT_AS: u32 = 9;
+/// the number of resource limits
+pub const RLIM_NLIMITS: u32 = 16;
+
+/// no limit
+pub const RLIM_INFINITY: u64 = !0;
+
+/// struct rlimit64 of prlimit64
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RLimit {
+    pub rlim_cur: u64,
+    pub rlim_max: u64,
+}
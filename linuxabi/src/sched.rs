@@ -0,0 +1,31 @@
+//! from linux/include/uapi/linux/sched.h
+
+/// the signal sent to the parent when the child exits
+pub const CSIGNAL: u32 = 0xff;
+/// share the address space
+pub const CLONE_VM: u32 = 0x100;
+/// share the working directory and umask
+pub const CLONE_FS: u32 = 0x200;
+/// share the file descriptor table
+pub const CLONE_FILES: u32 = 0x400;
+/// share the signal handlers
+pub const CLONE_SIGHAND: u32 = 0x800;
+pub const CLONE_PIDFD: u32 = 0x1000;
+pub const CLONE_PTRACE: u32 = 0x2000;
+/// suspend the parent until the child execs or exits
+pub const CLONE_VFORK: u32 = 0x4000;
+pub const CLONE_PARENT: u32 = 0x8000;
+/// be a thread of the same process
+pub const CLONE_THREAD: u32 = 0x10000;
+pub const CLONE_NEWNS: u32 = 0x20000;
+pub const CLONE_SYSVSEM: u32 = 0x40000;
+/// set the thread pointer of the child
+pub const CLONE_SETTLS: u32 = 0x80000;
+/// store the tid of the child at `parent_tid`
+pub const CLONE_PARENT_SETTID: u32 = 0x100000;
+/// clear the tid at `child_tid` and wake a futex there when the child exits
+pub const CLONE_CHILD_CLEARTID: u32 = 0x200000;
+pub const CLONE_DETACHED: u32 = 0x400000;
+pub const CLONE_UNTRACED: u32 = 0x800000;
+/// store the tid of the child at `child_tid`
+pub const CLONE_CHILD_SETTID: u32 = 0x1000000;
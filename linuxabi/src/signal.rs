@@ -0,0 +1,137 @@
+//! from linux/include/uapi/asm-generic/signal.h and siginfo.h
+
+pub const SIGHUP: u32 = 1;
+/// interrupt from the terminal, as with ^C
+pub const SIGINT: u32 = 2;
+/// quit from the terminal, as with ^\
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGSTKFLT: u32 = 16;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+/// stop from the terminal, as with ^Z
+pub const SIGTSTP: u32 = 20;
+pub const SIGTTIN: u32 = 21;
+pub const SIGTTOU: u32 = 22;
+pub const SIGURG: u32 = 23;
+pub const SIGXCPU: u32 = 24;
+pub const SIGXFSZ: u32 = 25;
+pub const SIGVTALRM: u32 = 26;
+pub const SIGPROF: u32 = 27;
+pub const SIGWINCH: u32 = 28;
+pub const SIGIO: u32 = 29;
+pub const SIGPWR: u32 = 30;
+pub const SIGSYS: u32 = 31;
+
+/// the number of signals, which count from 1
+pub const NSIG: usize = 64;
+
+/// a set of signals, where signal n is bit n - 1
+pub type SigSet = u64;
+
+// the hows of rt_sigprocmask
+pub const SIG_BLOCK: u32 = 0;
+pub const SIG_UNBLOCK: u32 = 1;
+pub const SIG_SETMASK: u32 = 2;
+
+// the handlers that are not functions
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+// sa_flags
+pub const SA_NOCLDSTOP: u64 = 0x1;
+pub const SA_NOCLDWAIT: u64 = 0x2;
+/// the handler takes the siginfo_t and the ucontext_t too
+pub const SA_SIGINFO: u64 = 0x4;
+/// the handler runs on the alternate signal stack
+pub const SA_ONSTACK: u64 = 0x0800_0000;
+/// syscalls the signal interrupts restart instead of failing with EINTR
+pub const SA_RESTART: u64 = 0x1000_0000;
+/// the signal is not blocked while its handler runs
+pub const SA_NODEFER: u64 = 0x4000_0000;
+/// the action is reset to the default once the handler is entered
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+/// struct sigaction of rt_sigaction, as on RISC-V, which has no sa_restorer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SigAction {
+    pub sa_handler: usize,
+    pub sa_flags: u64,
+    pub sa_mask: SigSet,
+}
+
+// ss_flags
+/// the thread runs on the alternate signal stack
+pub const SS_ONSTACK: i32 = 1;
+pub const SS_DISABLE: i32 = 2;
+/// the least size of an alternate signal stack
+pub const MINSIGSTKSZ: usize = 2048;
+
+/// stack_t of sigaltstack
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SignalStack {
+    pub ss_sp: usize,
+    pub ss_flags: i32,
+    pub ss_size: usize,
+}
+
+impl Default for SignalStack {
+    fn default() -> Self {
+        Self { ss_sp: 0, ss_flags: SS_DISABLE, ss_size: 0 }
+    }
+}
+
+// si_code of any signal
+/// sent by kill(2)
+pub const SI_USER: i32 = 0;
+/// sent by the kernel
+pub const SI_KERNEL: i32 = 0x80;
+/// sent by tkill(2) or tgkill(2)
+pub const SI_TKILL: i32 = -6;
+
+// si_code of SIGILL
+pub const ILL_ILLOPC: i32 = 1;
+// si_code of SIGSEGV
+/// no mapping at the address
+pub const SEGV_MAPERR: i32 = 1;
+/// the mapping does not allow the access
+pub const SEGV_ACCERR: i32 = 2;
+// si_code of SIGBUS
+pub const BUS_ADRALN: i32 = 1;
+/// no backing for the address, like a file that fails to read
+pub const BUS_ADRERR: i32 = 2;
+// si_code of SIGTRAP
+pub const TRAP_BRKPT: i32 = 1;
+
+/// siginfo_t, 128 bytes
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    _pad: i32,
+    /// The union of the fields for each kind of signal, which start with
+    /// the pid and uid of the sender for kill(2), or the address at fault
+    pub si_fields: [usize; 14],
+}
+
+impl SigInfo {
+    pub fn new(signo: u32, code: i32) -> Self {
+        Self { si_signo: signo as i32, si_errno: 0, si_code: code, _pad: 0, si_fields: [0; 14] }
+    }
+}
@@ -0,0 +1,56 @@
+//! from linux/include/linux/socket.h, linux/net.h and uapi/linux/in.h
+
+/// IPv4
+pub const AF_INET: u16 = 2;
+
+/// stream socket, like TCP
+pub const SOCK_STREAM: u32 = 1;
+/// datagram socket, like UDP
+pub const SOCK_DGRAM: u32 = 2;
+/// mask for the type of socket(2), without the flags
+pub const SOCK_TYPE_MASK: u32 = 0xf;
+/// non-blocking socket, as O_NONBLOCK
+pub const SOCK_NONBLOCK: u32 = 0o4000;
+/// set close-on-exec on the new fd, as O_CLOEXEC
+pub const SOCK_CLOEXEC: u32 = 0o2000000;
+
+pub const IPPROTO_IP: u32 = 0;
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+
+/// the level of socket options
+pub const SOL_SOCKET: u32 = 1;
+pub const SO_REUSEADDR: u32 = 2;
+pub const SO_TYPE: u32 = 3;
+pub const SO_ERROR: u32 = 4;
+pub const SO_BROADCAST: u32 = 6;
+pub const SO_SNDBUF: u32 = 7;
+pub const SO_RCVBUF: u32 = 8;
+pub const SO_KEEPALIVE: u32 = 9;
+pub const SO_REUSEPORT: u32 = 15;
+/// the TCP option to send small segments without waiting
+pub const TCP_NODELAY: u32 = 1;
+
+/// stop receiving
+pub const SHUT_RD: u32 = 0;
+/// stop sending
+pub const SHUT_WR: u32 = 1;
+/// stop both
+pub const SHUT_RDWR: u32 = 2;
+
+/// look at data without taking it
+pub const MSG_PEEK: u32 = 0x2;
+/// do not block, as if the socket were non-blocking
+pub const MSG_DONTWAIT: u32 = 0x40;
+/// do not raise SIGPIPE
+pub const MSG_NOSIGNAL: u32 = 0x4000;
+
+/// struct sockaddr_in, an IPv4 address and port in network byte order
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SockAddrIn {
+    pub sin_family: u16,
+    pub sin_port: u16,
+    pub sin_addr: [u8; 4],
+    pub sin_zero: [u8; 8],
+}
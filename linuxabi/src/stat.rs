@@ -0,0 +1,57 @@
+//! from linux/include/uapi/linux/stat.h and asm-generic/stat.h
+
+/// mask for the file type
+pub const S_IFMT: u32 = 0o170000;
+/// socket
+pub const S_IFSOCK: u32 = 0o140000;
+/// symbolic link
+pub const S_IFLNK: u32 = 0o120000;
+/// regular file
+pub const S_IFREG: u32 = 0o100000;
+/// block device
+pub const S_IFBLK: u32 = 0o060000;
+/// directory
+pub const S_IFDIR: u32 = 0o040000;
+/// character device
+pub const S_IFCHR: u32 = 0o020000;
+/// FIFO
+pub const S_IFIFO: u32 = 0o010000;
+
+/// struct stat of fstat and newfstatat
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stat {
+    /// device
+    pub st_dev: u64,
+    /// inode number
+    pub st_ino: u64,
+    /// file type and permissions
+    pub st_mode: u32,
+    /// number of hard links
+    pub st_nlink: u32,
+    /// user id of the owner
+    pub st_uid: u32,
+    /// group id of the owner
+    pub st_gid: u32,
+    /// device of a device file
+    pub st_rdev: u64,
+    pub __pad1: u64,
+    /// length in bytes
+    pub st_size: i64,
+    /// block size for I/O
+    pub st_blksize: i32,
+    pub __pad2: i32,
+    /// number of 512-byte blocks allocated
+    pub st_blocks: i64,
+    /// last access
+    pub st_atime: i64,
+    pub st_atime_nsec: u64,
+    /// last modification
+    pub st_mtime: i64,
+    pub st_mtime_nsec: u64,
+    /// last status change
+    pub st_ctime: i64,
+    pub st_ctime_nsec: u64,
+    pub __unused4: u32,
+    pub __unused5: u32,
+}
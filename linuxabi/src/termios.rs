@@ -0,0 +1,166 @@
+//! from linux/include/uapi/asm-generic/termbits.h and ioctls.h
+
+/// get the termios
+pub const TCGETS: u32 = 0x5401;
+/// set the termios now
+pub const TCSETS: u32 = 0x5402;
+/// set the termios once output is sent
+pub const TCSETSW: u32 = 0x5403;
+/// set the termios once output is sent, discarding pending input
+pub const TCSETSF: u32 = 0x5404;
+/// discard pending input or output
+pub const TCFLSH: u32 = 0x540b;
+/// make the terminal the controlling terminal
+pub const TIOCSCTTY: u32 = 0x540e;
+/// get the foreground process group
+pub const TIOCGPGRP: u32 = 0x540f;
+/// set the foreground process group
+pub const TIOCSPGRP: u32 = 0x5410;
+/// get the window size
+pub const TIOCGWINSZ: u32 = 0x5413;
+/// set the window size
+pub const TIOCSWINSZ: u32 = 0x5414;
+/// get the number of bytes a read can take
+pub const FIONREAD: u32 = 0x541b;
+/// set or clear non-blocking I/O
+pub const FIONBIO: u32 = 0x5421;
+
+// the queues of TCFLSH
+pub const TCIFLUSH: usize = 0;
+pub const TCOFLUSH: usize = 1;
+pub const TCIOFLUSH: usize = 2;
+
+/// the number of control characters
+pub const NCCS: usize = 19;
+
+// indices of the control characters in c_cc
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSWTC: usize = 7;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VREPRINT: usize = 12;
+pub const VDISCARD: usize = 13;
+pub const VWERASE: usize = 14;
+pub const VLNEXT: usize = 15;
+pub const VEOL2: usize = 16;
+
+// input modes of c_iflag
+pub const IGNBRK: u32 = 0o1;
+pub const BRKINT: u32 = 0o2;
+pub const IGNPAR: u32 = 0o4;
+pub const PARMRK: u32 = 0o10;
+pub const INPCK: u32 = 0o20;
+/// strip the eighth bit
+pub const ISTRIP: u32 = 0o40;
+/// translate NL to CR
+pub const INLCR: u32 = 0o100;
+/// ignore CR
+pub const IGNCR: u32 = 0o200;
+/// translate CR to NL
+pub const ICRNL: u32 = 0o400;
+pub const IUCLC: u32 = 0o1000;
+pub const IXON: u32 = 0o2000;
+pub const IXANY: u32 = 0o4000;
+pub const IXOFF: u32 = 0o10000;
+pub const IMAXBEL: u32 = 0o20000;
+/// input is UTF-8, so erasing takes whole characters
+pub const IUTF8: u32 = 0o40000;
+
+// output modes of c_oflag
+/// process output
+pub const OPOST: u32 = 0o1;
+pub const OLCUC: u32 = 0o2;
+/// translate NL to CR NL
+pub const ONLCR: u32 = 0o4;
+/// translate CR to NL
+pub const OCRNL: u32 = 0o10;
+
+// control modes of c_cflag
+pub const B38400: u32 = 0o17;
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+pub const HUPCL: u32 = 0o2000;
+pub const CLOCAL: u32 = 0o4000;
+
+// local modes of c_lflag
+/// generate signals for INTR, QUIT and SUSP
+pub const ISIG: u32 = 0o1;
+/// canonical mode, reading by lines
+pub const ICANON: u32 = 0o2;
+/// echo input
+pub const ECHO: u32 = 0o10;
+/// erase the character ERASE erases on the screen
+pub const ECHOE: u32 = 0o20;
+/// echo NL after KILL
+pub const ECHOK: u32 = 0o40;
+/// echo NL even without ECHO
+pub const ECHONL: u32 = 0o100;
+/// do not flush input on signals
+pub const NOFLSH: u32 = 0o200;
+pub const TOSTOP: u32 = 0o400;
+/// echo control characters as `^X`
+pub const ECHOCTL: u32 = 0o1000;
+pub const ECHOPRT: u32 = 0o2000;
+/// erase the line KILL erases on the screen
+pub const ECHOKE: u32 = 0o4000;
+/// extended input processing: WERASE, LNEXT and REPRINT
+pub const IEXTEN: u32 = 0o100000;
+
+/// struct termios of TCGETS and TCSETS
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    /// The modes a terminal starts in, as for Linux
+    fn default() -> Self {
+        let mut c_cc = [0; NCCS];
+        c_cc[VINTR] = 0x03;
+        c_cc[VQUIT] = 0x1c;
+        c_cc[VERASE] = 0x7f;
+        c_cc[VKILL] = 0x15;
+        c_cc[VEOF] = 0x04;
+        c_cc[VTIME] = 0;
+        c_cc[VMIN] = 1;
+        c_cc[VSTART] = 0x11;
+        c_cc[VSTOP] = 0x13;
+        c_cc[VSUSP] = 0x1a;
+        c_cc[VREPRINT] = 0x12;
+        c_cc[VDISCARD] = 0x0f;
+        c_cc[VWERASE] = 0x17;
+        c_cc[VLNEXT] = 0x16;
+        Self {
+            c_iflag: ICRNL | IXON | IUTF8,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: B38400 | CS8 | CREAD | HUPCL,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc,
+        }
+    }
+}
+
+/// struct winsize of TIOCGWINSZ and TIOCSWINSZ
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WinSize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
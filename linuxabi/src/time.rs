@@ -1,3 +1,61 @@
-//! providing c ffi timing abi interface
+//! from linux/include/uapi/linux/time.h and time_types.h
 
+/// the time since the epoch, which can be set
+pub const CLOCK_REALTIME: i32 = 0;
+/// the time since boot, not counting suspend
+pub const CLOCK_MONOTONIC: i32 = 1;
+/// the CPU time of the process
+pub const CLOCK_PROCESS_CPUTIME_ID: i32 = 2;
+/// the CPU time of the thread
+pub const CLOCK_THREAD_CPUTIME_ID: i32 = 3;
+/// CLOCK_MONOTONIC without frequency adjustment
+pub const CLOCK_MONOTONIC_RAW: i32 = 4;
+/// CLOCK_REALTIME at the resolution of the tick
+pub const CLOCK_REALTIME_COARSE: i32 = 5;
+/// CLOCK_MONOTONIC at the resolution of the tick
+pub const CLOCK_MONOTONIC_COARSE: i32 = 6;
+/// the time since boot, counting suspend
+pub const CLOCK_BOOTTIME: i32 = 7;
 
+/// the flag of clock_nanosleep for a deadline rather than a duration
+pub const TIMER_ABSTIME: u32 = 1;
+
+/// struct timespec
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+/// struct timeval
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeVal {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+/// struct timezone, which is obsolete
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeZone {
+    pub tz_minuteswest: i32,
+    pub tz_dsttime: i32,
+}
+
+// the timers of setitimer
+/// counts down in real time, raising SIGALRM
+pub const ITIMER_REAL: i32 = 0;
+/// counts down in the user CPU time of the process, raising SIGVTALRM
+pub const ITIMER_VIRTUAL: i32 = 1;
+/// counts down in the CPU time of the process, raising SIGPROF
+pub const ITIMER_PROF: i32 = 2;
+
+/// struct itimerval
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ITimerVal {
+    pub it_interval: TimeVal,
+    pub it_value: TimeVal,
+}
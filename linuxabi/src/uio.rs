@@ -0,0 +1,14 @@
+//! from linux/include/uapi/linux/uio.h
+
+/// maximum number of iovecs in a single readv/writev
+pub const UIO_MAXIOV: usize = 1024;
+
+/// A buffer of a vectored I/O
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct IoVec {
+    /// start address
+    pub iov_base: usize,
+    /// length in bytes
+    pub iov_len: usize,
+}
@@ -1,7 +1,9 @@
-use std::fs::{read_dir, File};
+use std::fs::{self, read_dir, File, OpenOptions};
 use std::io::{Result, Write};
 use std::env;
-use std::path::PathBuf;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use std::vec::Vec;
 
 use binsa::elf_syscalls;
@@ -20,6 +22,8 @@ fn main() {
     println!("cargo:rustc-link-arg=-T{}", linker_script_path);
     println!("cargo:rerun-if-changed={}/*", TARGET_PATH);
     insert_app_data().unwrap();
+    insert_initramfs().unwrap();
+    insert_app_args();
     let app_path = PathBuf::from(&(TARGET_PATH.to_owned() + &app_name_in_dir(TARGET_PATH).unwrap()));
     let app_abs_path = app_path.canonicalize().unwrap();
     let app_abs_path_str = app_abs_path.as_os_str().to_str().unwrap();
@@ -40,6 +44,24 @@ fn app_name_in_dir(path: &str) -> Option<String> {
         .collect::<Vec<_>>().first().cloned()
 }
 
+/// Pass the command line and environment of the app to the kernel, where
+/// `RUNIK_APP_ARGS` and `RUNIK_APP_ENVS` are whitespace separated lists.
+fn insert_app_args() {
+    println!("cargo:rerun-if-env-changed=RUNIK_APP_ARGS");
+    println!("cargo:rerun-if-env-changed=RUNIK_APP_ENVS");
+    let app = app_name_in_dir(TARGET_PATH).unwrap();
+    let args = env::var("RUNIK_APP_ARGS").unwrap_or_default();
+    let envs = env::var("RUNIK_APP_ENVS").unwrap_or_default();
+    // argv[0] is always the name of the app
+    let argv = std::iter::once(app.as_str())
+        .chain(args.split_whitespace())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let envp = envs.split_whitespace().collect::<Vec<_>>().join(" ");
+    println!("cargo:rustc-env=RUNIK_APP_ARGV={}", argv);
+    println!("cargo:rustc-env=RUNIK_APP_ENVP={}", envp);
+}
+
 fn insert_app_data() -> Result<()> {
     let mut f = File::create("src/link_app.S").unwrap();
     let app = &app_name_in_dir(TARGET_PATH).unwrap();
@@ -63,3 +85,87 @@ app_end:
 
     Ok(())
 }
+
+/// Pack the directory `RUNIK_INITRAMFS` into a cpio archive and link it
+/// after the app. The archive is empty if no directory is given.
+fn insert_initramfs() -> Result<()> {
+    println!("cargo:rerun-if-env-changed=RUNIK_INITRAMFS");
+    let mut archive = Vec::new();
+    let mut ino = 0;
+    let dir = env::var("RUNIK_INITRAMFS").unwrap_or_default();
+    if !dir.is_empty() {
+        let dir = PathBuf::from(dir);
+        assert!(dir.is_dir(), "initramfs {} is not a directory", dir.display());
+        println!("cargo:rerun-if-changed={}", dir.display());
+        pack_dir(&mut archive, &dir, "", &mut ino)?;
+    }
+    push_newc_entry(&mut archive, "TRAILER!!!", 0, 0, &[], ino + 1);
+    let archive_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("initramfs.cpio");
+    fs::write(&archive_path, &archive)?;
+
+    let mut f = OpenOptions::new().append(true).open("src/link_app.S")?;
+    writeln!(
+        f,
+r#"
+    .align 3
+    .section .data
+    .global initramfs_start_addr
+    .global initramfs_end_addr
+initramfs_start_addr:
+    .quad initramfs_start
+initramfs_end_addr:
+    .quad initramfs_end
+initramfs_start:
+    .incbin "{0}"
+initramfs_end:
+"#,
+        archive_path.display()
+    )?;
+    Ok(())
+}
+
+/// Append the entries under `dir` to `archive`, named relative to the root
+/// of the archive with `prefix`, in a stable order
+fn pack_dir(archive: &mut Vec<u8>, dir: &Path, prefix: &str, ino: &mut u32) -> Result<()> {
+    let mut entries = read_dir(dir)?.collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = format!("{}{}", prefix, entry.file_name().to_str().unwrap());
+        let meta = fs::symlink_metadata(entry.path())?;
+        let data = if meta.is_dir() {
+            Vec::new()
+        } else if meta.is_symlink() {
+            fs::read_link(entry.path())?.as_os_str().as_bytes().to_vec()
+        } else if meta.is_file() {
+            fs::read(entry.path())?
+        } else {
+            println!("cargo:warning=initramfs: skipping special file {}", entry.path().display());
+            continue;
+        };
+        *ino += 1;
+        push_newc_entry(archive, &name, meta.mode(), meta.mtime() as u32, &data, *ino);
+        if meta.is_dir() {
+            pack_dir(archive, &entry.path(), &format!("{}/", name), ino)?;
+        }
+    }
+    Ok(())
+}
+
+/// Append an entry of the cpio "newc" format, whose header is the magic
+/// number followed by 13 fields of 8 hex digits, and whose name and data
+/// are padded to 4 bytes
+fn push_newc_entry(archive: &mut Vec<u8>, name: &str, mode: u32, mtime: u32, data: &[u8], ino: u32) {
+    let nlink = if mode & 0o170000 == 0o040000 { 2 } else { 1 };
+    let fields = [
+        ino, mode, 0, 0, nlink, mtime, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0,
+    ];
+    archive.extend_from_slice(b"070701");
+    for field in fields {
+        archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize((archive.len() + 3) & !3, 0);
+    archive.extend_from_slice(data);
+    archive.resize((archive.len() + 3) & !3, 0);
+}
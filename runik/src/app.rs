@@ -1,13 +1,22 @@
 //! App for binary apps
 
-use crate::kernel_stack;
+use crate::arch::trap::TrapContext;
+use crate::task;
+use alloc::vec::Vec;
 use core::arch::asm;
 use core::slice;
 use core::mem;
 use xmas_elf::ElfFile;
 
+/// The command line of the app, `argv[0]` followed by `APP_ARGS` at build time
+const APP_ARGV: &str = env!("RUNIK_APP_ARGV");
+/// The environment variables of the app, `APP_ENVS` at build time
+const APP_ENVP: &str = env!("RUNIK_APP_ENVP");
+
 pub struct App<'a> {
     pub elf_file: ElfFile<'a>,
+    pub argv: Vec<&'static str>,
+    pub envp: Vec<&'static str>,
 }
 
 impl App<'_> {
@@ -25,6 +34,8 @@ impl App<'_> {
             let elf = ElfFile::new(buf).unwrap();
             App {
                 elf_file: elf,
+                argv: APP_ARGV.split_whitespace().collect(),
+                envp: APP_ENVP.split_whitespace().collect(),
             }
         }
     }
@@ -33,18 +44,16 @@ impl App<'_> {
         self.elf_file.header.pt2.entry_point().try_into().unwrap()
     }
 
-    pub fn run(&self, user_stack_base: usize) {
+    /// Jump to the entry point in the first thread, where `user_sp` points
+    /// to `argc` of the initial process stack and `user_tp` to the TLS block.
+    pub fn run(&self, user_sp: usize, user_tp: usize) -> ! {
         let entry_point = self.get_entry_point();
         println!("[kernel] [trace] app entrypoint {:#x}", entry_point);
         unsafe {
-            extern "C" {
-                fn __restore(cx_addr: usize);
-            }
             asm!("fence.i");
-            __restore(kernel_stack::push_context(crate::arch::trap::TrapContext::app_init_context(
-                entry_point,
-                user_stack_base,
-            )) as *const _ as usize);
         }
+        let mut cx = TrapContext::app_init_context(entry_point, user_sp);
+        cx.set_tp(user_tp);
+        task::start(cx)
     }
 }
@@ -15,6 +15,12 @@ cfg_if! {
         pub mod time;
         #[path = "riscv64/syscall.rs"]
         pub mod syscall;
+        #[path = "riscv64/hwcap.rs"]
+        pub mod hwcap;
+        #[path = "riscv64/task.rs"]
+        pub mod task;
+        #[path = "riscv64/signal.rs"]
+        pub mod signal;
     } else if #[cfg(target_arch = "aarch64")] {
         compile_error!("Arch `aarch64` not supported yet");
     } else {
@@ -0,0 +1,14 @@
+//! CPU capabilities reported to the application through `AT_HWCAP`
+
+/// One bit per single-letter ISA extension, as in arch/riscv/include/asm/hwcap.h
+const fn isa_ext(letter: u8) -> usize {
+    1 << (letter - b'A')
+}
+
+/// The RV64GC machine we are running on: IMAFDC
+pub const HWCAP: usize = isa_ext(b'I')
+    | isa_ext(b'M')
+    | isa_ext(b'A')
+    | isa_ext(b'F')
+    | isa_ext(b'D')
+    | isa_ext(b'C');
@@ -1,3 +1,5 @@
+use core::arch::asm;
+
 pub const PA_WIDTH: usize = 56;
 pub const VA_WIDTH: usize = 39;
 pub const PPN_WIDTH: usize = PA_WIDTH - PAGE_SIZE_BITS;
@@ -5,3 +7,18 @@ pub const VPN_WIDTH: usize = VA_WIDTH - PAGE_SIZE_BITS;
 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
+
+/// Flush the TLB after changing mappings of the active page table
+pub fn flush_tlb() {
+    unsafe {
+        asm!("sfence.vma");
+    }
+}
+
+/// Make the instruction fetches of the hart see the stores made to memory
+/// before, as code written to pages must be
+pub fn flush_icache() {
+    unsafe {
+        asm!("fence.i");
+    }
+}
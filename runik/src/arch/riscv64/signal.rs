@@ -0,0 +1,110 @@
+//! The signal frame of RISC-V
+//!
+//! A handler runs on a frame of `rt_sigframe` as Linux builds it, with the
+//! siginfo_t and then the ucontext_t, whose `sigcontext` has the registers
+//! of the app when the signal came, the FP ones included, so that a handler
+//! may use them whatever it interrupted.
+
+use super::task::FpContext;
+use super::trap::TrapContext;
+use core::mem::offset_of;
+use linuxabi::signal::{ SigInfo, SigSet, SignalStack };
+use linuxabi::syscall_id::generic::SYSCALL_RT_SIGRETURN;
+
+/// `li a7, 139; ecall`, which handlers return to for rt_sigreturn(2), as
+/// the vDSO of Linux has it
+pub static SIGRETURN_TRAMPOLINE: [u8; 8] = {
+    let li = 0x893 | (SYSCALL_RT_SIGRETURN as u32) << 20;
+    let ecall: u32 = 0x73;
+    let (li, ecall) = (li.to_le_bytes(), ecall.to_le_bytes());
+    [li[0], li[1], li[2], li[3], ecall[0], ecall[1], ecall[2], ecall[3]]
+};
+
+/// union __riscv_fp_state, holding the D extension state in the room of
+/// the Q one, which is the largest
+#[repr(C, align(16))]
+#[derive(Copy, Clone)]
+struct FpState {
+    f: [u64; 32],
+    fcsr: u32,
+    _q_rest: [u32; 67],
+}
+
+/// struct sigcontext
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SigContext {
+    /// pc, then x1 to x31
+    sc_regs: [usize; 32],
+    sc_fpregs: FpState,
+}
+
+/// struct ucontext
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct UContext {
+    uc_flags: usize,
+    uc_link: usize,
+    uc_stack: SignalStack,
+    uc_sigmask: SigSet,
+    /// room for a larger sigset_t
+    _unused: [u8; 1024 / 8 - 8],
+    uc_mcontext: SigContext,
+}
+
+/// struct rt_sigframe, at the sp a handler starts with
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SignalFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+impl SignalFrame {
+    /// The frame saving `cx` and the FP registers on the hart, and `mask`
+    /// and `stack` to restore with them
+    pub fn new(info: SigInfo, cx: &TrapContext, mask: SigSet, stack: SignalStack) -> Self {
+        let mut sc_regs = cx.x;
+        sc_regs[0] = cx.sepc;
+        let mut fp_cx = FpContext::default();
+        fp_cx.save();
+        Self {
+            info,
+            uc: UContext {
+                uc_flags: 0,
+                uc_link: 0,
+                uc_stack: stack,
+                uc_sigmask: mask,
+                _unused: [0; 1024 / 8 - 8],
+                uc_mcontext: SigContext {
+                    sc_regs,
+                    sc_fpregs: FpState { f: fp_cx.f, fcsr: fp_cx.fcsr as u32, _q_rest: [0; 67] },
+                },
+            },
+        }
+    }
+
+    /// Have `cx` enter `handler` for `signo` with the frame at `frame_addr`,
+    /// returning to `trampoline`
+    pub fn enter(cx: &mut TrapContext, handler: usize, signo: u32, frame_addr: usize, trampoline: usize) {
+        cx.sepc = handler;
+        cx.x[1] = trampoline;
+        cx.set_sp(frame_addr);
+        cx.x[10] = signo as usize;
+        cx.x[11] = frame_addr + offset_of!(SignalFrame, info);
+        cx.x[12] = frame_addr + offset_of!(SignalFrame, uc);
+    }
+
+    /// Put the registers saved back into `cx` and the FP ones on the hart,
+    /// returning the mask and the stack saved with them
+    pub fn restore(&self, cx: &mut TrapContext) -> (SigSet, SignalStack) {
+        let regs = &self.uc.uc_mcontext.sc_regs;
+        cx.sepc = regs[0];
+        cx.x[1..].copy_from_slice(&regs[1..]);
+        let fpregs = &self.uc.uc_mcontext.sc_fpregs;
+        FpContext { f: fpregs.f, fcsr: fpregs.fcsr as usize }.restore();
+        // to be saved on switching away, as they differ from what was
+        cx.set_fp_dirty();
+        (self.uc.uc_sigmask, self.uc.uc_stack)
+    }
+}
@@ -0,0 +1,73 @@
+//! Switching between the kernel stacks of threads
+//!
+//! A thread that is not running is stopped in [`switch()`], with what it
+//! needs to go on saved in its [`TaskContext`]: the return address, the
+//! stack pointer and the callee-saved registers. The rest of its registers
+//! were saved by the Rust code that called [`switch()`].
+//!
+//! The kernel does not use the FP registers, so those of the app stay on the
+//! hart while in the kernel. They are saved in an [`FpContext`] of the thread
+//! when switching away from it, only if sstatus.FS says the app wrote them.
+
+use core::arch::global_asm;
+
+global_asm!(include_str!("switch.S"));
+
+/// The registers of a thread stopped in the kernel
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TaskContext {
+    ra: usize,
+    sp: usize,
+    s: [usize; 12],
+}
+
+impl TaskContext {
+    /// A context that starts running `entry` on the stack at `sp`
+    pub fn new(entry: usize, sp: usize) -> Self {
+        Self { ra: entry, sp, s: [0; 12] }
+    }
+}
+
+/// Save the current thread into `current` and go on with `next`, returning
+/// once switched back to
+///
+/// # Safety
+///
+/// Both must point to contexts that stay valid until the threads run again.
+pub unsafe fn switch(current: *mut TaskContext, next: *const TaskContext) {
+    extern "C" {
+        fn __switch(current: *mut TaskContext, next: *const TaskContext);
+    }
+    __switch(current, next);
+}
+
+/// The FP registers of a thread in the app, f0 to f31 and then fcsr
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FpContext {
+    pub f: [u64; 32],
+    pub fcsr: usize,
+}
+
+impl FpContext {
+    /// Save the FP registers on the hart
+    pub fn save(&mut self) {
+        extern "C" {
+            fn __save_fp(fp_cx: *mut FpContext);
+        }
+        unsafe {
+            __save_fp(self);
+        }
+    }
+
+    /// Load the FP registers on the hart
+    pub fn restore(&self) {
+        extern "C" {
+            fn __restore_fp(fp_cx: *const FpContext);
+        }
+        unsafe {
+            __restore_fp(self);
+        }
+    }
+}
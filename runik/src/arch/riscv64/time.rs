@@ -1,7 +1,42 @@
 //! the machine clock and time module
 
-use riscv::register::time;
+use crate::config::TICKS_PER_SEC;
+use crate::plat::qemu::CLOCK_FREQ;
+use crate::sbi::set_timer;
+use crate::task::{ self, signal };
+use linuxabi::errno::Errno::{ self, * };
+use riscv::register::{ sie, time };
 
 pub fn get_clock() -> usize {
     time::read()
 }
+
+/// raise the next timer interrupt one tick from now
+pub fn set_next_tick() {
+    set_timer(get_clock() + CLOCK_FREQ / TICKS_PER_SEC);
+}
+
+/// enable timer interrupts and start the ticks
+pub fn init() {
+    unsafe {
+        sie::set_stimer();
+    }
+    set_next_tick();
+}
+
+/// wait until the clock reaches `deadline`, with the timer aimed at the
+/// deadline or the next tick, whichever comes first, failing with EINTR if
+/// a signal comes first
+pub fn sleep_until(deadline: usize) -> Result<(), Errno> {
+    loop {
+        let now = get_clock();
+        if now >= deadline {
+            return Ok(());
+        }
+        if signal::interrupted() {
+            return Err(EINTR);
+        }
+        set_timer(deadline.min(now + CLOCK_FREQ / TICKS_PER_SEC));
+        task::wait_for_event();
+    }
+}
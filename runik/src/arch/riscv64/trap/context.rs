@@ -1,6 +1,7 @@
-use riscv::register::sstatus::{self, Sstatus};
+use riscv::register::sstatus::{self, Sstatus, FS};
 /// Trap Context
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct TrapContext {
     /// general regs[0..31]
     pub x: [usize; 32],
@@ -15,6 +16,27 @@ impl TrapContext {
     pub fn set_sp(&mut self, sp: usize) {
         self.x[2] = sp;
     }
+    /// set thread pointer to x_4 reg (tp)
+    pub fn set_tp(&mut self, tp: usize) {
+        self.x[4] = tp;
+    }
+    /// whether the app wrote FP registers since they were last saved
+    pub fn fp_dirty(&self) -> bool {
+        self.sstatus.fs() == FS::Dirty
+    }
+    /// mark the FP registers as saved
+    pub fn set_fp_clean(&mut self) {
+        self.set_fs(FS::Clean);
+    }
+    /// mark the FP registers as changed by the kernel, to be saved
+    pub fn set_fp_dirty(&mut self) {
+        self.set_fs(FS::Dirty);
+    }
+    /// set sstatus.FS, the state of the FP registers, to return with
+    fn set_fs(&mut self, fs: FS) {
+        let bits = unsafe { &mut *(&mut self.sstatus as *mut Sstatus as *mut usize) };
+        *bits = (*bits & !(0b11 << 13)) | (fs as usize) << 13;
+    }
     /// init app context
     pub fn app_init_context(entry: usize, sp: usize) -> Self {
         let mut sstatus = sstatus::read(); // CSR sstatus
@@ -28,6 +50,7 @@ impl TrapContext {
             sepc: entry, // entry point of app
         };
         cx.set_sp(sp); // app's user stack pointer
+        cx.set_fs(FS::Initial); // the app may use the FP registers
         cx // return initial Trap Context of app
     }
 }
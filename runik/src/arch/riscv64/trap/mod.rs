@@ -10,21 +10,36 @@
 //!
 //! It then calls different functionality based on what exactly the exception
 //! was. For example, timer interrupts trigger task preemption, and syscalls go
-//! to [`syscall()`].
+//! to [`syscall()`]. Faults of the app raise signals, and the signals pending
+//! are delivered before returning to it.
+//!
+//! Traps come from the app only, as the kernel runs with interrupts masked,
+//! and each thread traps onto its own kernel stack, whose top `sscratch`
+//! holds while the thread is in the app.
 
 mod context;
 
-use crate::sbi::shutdown;
-use crate::syscall::syscall;
+use crate::arch::syscall::ids::{ SYSCALL_CLOCK_NANOSLEEP, SYSCALL_NANOSLEEP };
+use crate::arch::time::set_next_tick;
+use crate::drivers::plic;
+use crate::mm::addr_space::{ kspace_handle_page_fault, AccessType, PageFaultError };
+use crate::syscall::{ syscall, SyscallArgs };
+use crate::task::{ self, signal };
 use core::arch::global_asm;
+use linuxabi::errno::Errno::EINTR;
+use linuxabi::signal::*;
 use riscv::register::{
     mtvec::TrapMode,
-    scause::{self, Exception, Trap},
-    stval, stvec,
+    scause::{self, Exception, Interrupt, Trap},
+    sip, stval, stvec,
 };
 
 global_asm!(include_str!("trap.S"));
 
+/// The cause of misaligned loads, which `Exception` of the riscv crate
+/// does not know
+const LOAD_MISALIGNED: usize = 4;
+
 /// initialize CSR `stvec` as the entry of `__trap_handler`
 pub fn init() {
     extern "C" {
@@ -35,31 +50,115 @@ pub fn init() {
     }
 }
 
+/// idle until an interrupt is pending, and handle it
+///
+/// The kernel runs with interrupts masked, so it waits on devices this way
+/// rather than through traps: `wfi` wakes on interrupts enabled in `sie`
+/// whether or not they are masked.
+pub fn wait_for_interrupt() {
+    unsafe {
+        riscv::asm::wfi();
+    }
+    let sip = sip::read();
+    if sip.stimer() {
+        set_next_tick();
+    }
+    if sip.sext() {
+        plic::handle_external();
+    }
+}
+
 #[no_mangle]
 /// handle an interrupt, exception, or system call from user space
 pub fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
     let scause = scause::read(); // get trap cause
     let stval = stval::read(); // get extra value
+    // the first argument of a syscall interrupted by a signal
+    let mut restart = None;
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
             cx.sepc += 4;
-            cx.x[10] = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]) as usize;
+            // a7 holds the syscall number, a0..a5 the arguments
+            let (id, a0) = (cx.x[17], cx.x[10]);
+            let args = SyscallArgs::new([cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]]);
+            let result = syscall(id, args);
+            // sleeps give the time left instead, as on Linux
+            if result == Err(EINTR) && id != SYSCALL_NANOSLEEP && id != SYSCALL_CLOCK_NANOSLEEP {
+                restart = Some(a0);
+            }
+            cx.x[10] = match result {
+                Ok(ret) => ret,
+                Err(errno) => errno.as_neg() as usize,
+            };
         }
-        Trap::Exception(Exception::LoadFault) | Trap::Exception(Exception::LoadPageFault) => {
-            println!("[kernel] Load PageFault (instr {:#x}; address {:#x}).", cx.sepc, stval);
-            shutdown(true);
+        Trap::Exception(
+            e @ (Exception::LoadPageFault | Exception::StorePageFault | Exception::InstructionPageFault),
+        ) => {
+            let access = match e {
+                Exception::LoadPageFault => AccessType::Read,
+                Exception::StorePageFault => AccessType::Write,
+                _ => AccessType::Execute,
+            };
+            match kspace_handle_page_fault(stval, access) {
+                Ok(()) => (),
+                Err(PageFaultError::StackOverflow) => {
+                    println!("[kernel] Stack overflow (instr {:#x}; address {:#x}).", cx.sepc, stval);
+                    signal::force(SIGSEGV, SEGV_MAPERR, stval);
+                }
+                Err(PageFaultError::OutOfMemory) => {
+                    println!("[kernel] Out of memory (instr {:#x}; address {:#x}).", cx.sepc, stval);
+                    signal::force(SIGKILL, SI_KERNEL, 0);
+                }
+                Err(PageFaultError::Invalid) => {
+                    println!("[kernel] {:?} (instr {:#x}; address {:#x}).", e, cx.sepc, stval);
+                    signal::force(SIGSEGV, SEGV_MAPERR, stval);
+                }
+                Err(PageFaultError::BadFile) => {
+                    println!("[kernel] Failed to read the file mapped (instr {:#x}; address {:#x}).", cx.sepc, stval);
+                    signal::force(SIGBUS, BUS_ADRERR, stval);
+                }
+                Err(PageFaultError::Denied) => {
+                    println!("[kernel] {:?} (instr {:#x}; address {:#x}).", e, cx.sepc, stval);
+                    signal::force(SIGSEGV, SEGV_ACCERR, stval);
+                }
+            }
         }
-        Trap::Exception(Exception::StoreFault) | Trap::Exception(Exception::StorePageFault) => {
-            println!("[kernel] Store PageFault (instr {:#x}; address {:#x}).", cx.sepc, stval);
-            shutdown(true);
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            set_next_tick();
+            task::wake_waiting();
+            task::yield_now();
         }
-        Trap::Exception(Exception::InstructionPageFault) => {
-            println!("[kernel] Instruction PageFault (instr {:#x}; address {:#x}).", cx.sepc, stval);
-            shutdown(true);
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            plic::handle_external();
+            task::wake_waiting();
+        }
+        Trap::Interrupt(interrupt) => {
+            println!("[kernel] [warn] Unexpected interrupt {:?}", interrupt);
+        }
+        Trap::Exception(Exception::LoadFault) => {
+            println!("[kernel] Load Fault (instr {:#x}; address {:#x}).", cx.sepc, stval);
+            signal::force(SIGSEGV, SEGV_ACCERR, stval);
+        }
+        Trap::Exception(Exception::StoreFault) => {
+            println!("[kernel] Store Fault (instr {:#x}; address {:#x}).", cx.sepc, stval);
+            signal::force(SIGSEGV, SEGV_ACCERR, stval);
+        }
+        Trap::Exception(Exception::InstructionFault) => {
+            println!("[kernel] Instruction Fault (instr {:#x}).", cx.sepc);
+            signal::force(SIGSEGV, SEGV_ACCERR, cx.sepc);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             println!("[kernel] IllegalInstruction in application.");
-            shutdown(true);
+            signal::force(SIGILL, ILL_ILLOPC, cx.sepc);
+        }
+        Trap::Exception(Exception::Breakpoint) => {
+            signal::force(SIGTRAP, TRAP_BRKPT, cx.sepc);
+        }
+        Trap::Exception(Exception::InstructionMisaligned | Exception::StoreMisaligned) => {
+            signal::force(SIGBUS, BUS_ADRALN, stval);
+        }
+        Trap::Exception(Exception::Unknown) if scause.code() == LOAD_MISALIGNED => {
+            signal::force(SIGBUS, BUS_ADRALN, stval);
         }
         _ => {
             panic!(
@@ -69,6 +168,7 @@ pub fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
             );
         }
     }
+    signal::deliver(cx, restart);
     cx
 }
 
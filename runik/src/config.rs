@@ -0,0 +1,66 @@
+//! Tunable constants of the kernel and the layout of the application
+
+use crate::arch::paging::PAGE_SIZE;
+
+/// The top of the user stack, at the high end of the lower half of sv39
+pub const USER_STACK_TOP: usize = (1 << 38) - PAGE_SIZE;
+
+/// The initial size of the user stack, which grows downwards on page faults
+pub const USER_STACK_SIZE: usize = 0x1_0000;
+
+/// The maximum size the user stack may grow to, i.e. RLIMIT_STACK
+pub const USER_STACK_RLIMIT: usize = 0x80_0000;
+
+/// The upper limit of the program break, counted from the start of the heap
+pub const USER_HEAP_LIMIT: usize = 0x400_0000;
+
+/// The area searched downwards for free pages by mmap without MAP_FIXED
+pub const USER_MMAP_BASE: usize = 0x10_0000_0000;
+pub const USER_MMAP_TOP: usize = 0x30_0000_0000;
+
+/// The sizes of the ring buffers of the UART for the bytes received and
+/// those waiting to be sent
+pub const UART_RX_BUFFER_SIZE: usize = 0x1000;
+pub const UART_TX_BUFFER_SIZE: usize = 0x1000;
+
+/// The most input the terminal holds for reads, including the line being
+/// edited
+pub const TTY_BUFFER_SIZE: usize = 0x1000;
+
+/// The number of timer interrupts per second
+pub const TICKS_PER_SEC: usize = 100;
+
+/// Print a warning when the application makes a syscall we do not support
+pub const LOG_UNSUPPORTED_SYSCALL: bool = true;
+
+/// The number of file descriptors the application may have open, i.e. RLIMIT_NOFILE
+pub const FD_LIMIT: usize = 1024;
+
+/// The size of the kernel buffer through which read and write move data
+/// between files and the application
+pub const IO_BUFFER_SIZE: usize = 0x1_0000;
+
+/// The number of bytes each tmpfs, at / and /tmp, may hold
+pub const TMPFS_QUOTA: usize = 0x40_0000;
+
+/// Where the FAT32 volume on the first block device is mounted
+pub const DISK_MOUNT_POINT: &str = "/mnt";
+
+/// The largest 9P message, which bounds the data each request to the host
+/// moves
+pub const P9_MSIZE: usize = 0x4000;
+
+/// The IPv4 address, prefix length and gateway of the network interface,
+/// which suit QEMU user-mode networking
+pub const NET_IP_ADDR: [u8; 4] = [10, 0, 2, 15];
+pub const NET_PREFIX_LEN: u8 = 24;
+pub const NET_GATEWAY: [u8; 4] = [10, 0, 2, 2];
+
+/// The size of each of the receive and send buffers of a TCP socket
+pub const TCP_BUFFER_SIZE: usize = 0x2000;
+/// The size of the receive and send buffers of a UDP socket, and the number
+/// of datagrams each holds
+pub const UDP_BUFFER_SIZE: usize = 0x2000;
+pub const UDP_PACKETS: usize = 8;
+/// The most connections waiting to be accepted on a listening socket
+pub const SOCKET_BACKLOG_MAX: usize = 4;
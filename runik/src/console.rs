@@ -1,5 +1,6 @@
-//! SBI console driver, for text output
+//! Console output, on the UART once it is set up and through SBI before
 
+use crate::drivers::uart;
 use crate::sbi::console_putchar;
 use core::fmt::{self, Write};
 
@@ -7,17 +8,37 @@ struct Stdout;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
-        }
+        write_bytes(s.as_bytes());
         Ok(())
     }
 }
 
+/// Write `bytes` as they are
+pub fn write_bytes(bytes: &[u8]) {
+    let rest = if uart::is_ready() {
+        match uart::write(bytes) {
+            Ok(()) => return,
+            Err(rest) => rest,
+        }
+    } else {
+        bytes
+    };
+    for &c in rest {
+        console_putchar(c as usize);
+    }
+}
+
 pub fn print(args: fmt::Arguments) {
     Stdout.write_fmt(args).unwrap();
 }
 
+/// Wait until all output is sent
+pub fn flush() {
+    if uart::is_ready() {
+        uart::flush();
+    }
+}
+
 /// print string macro
 #[macro_export]
 macro_rules! print {
@@ -0,0 +1,41 @@
+//! mod block: block devices
+//!
+//! Drivers register the disks they find here at boot, for filesystems to be
+//! mounted on them.
+
+use crate::sync::UPSafeCell;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use linuxabi::errno::Errno;
+
+/// The size of a block, which is also the sector size of virtio-blk
+pub const BLOCK_SIZE: usize = 512;
+
+pub trait BlockDevice: Send + Sync {
+    /// The number of blocks of the device
+    fn num_blocks(&self) -> usize;
+    fn read_only(&self) -> bool {
+        false
+    }
+    /// Read the blocks from `block` on into `buf`, whose length is a
+    /// multiple of [`BLOCK_SIZE`]
+    fn read_blocks(&self, block: usize, buf: &mut [u8]) -> Result<(), Errno>;
+    /// Write `buf`, whose length is a multiple of [`BLOCK_SIZE`], to the
+    /// blocks from `block` on
+    fn write_blocks(&self, block: usize, buf: &[u8]) -> Result<(), Errno>;
+}
+
+lazy_static! {
+    static ref BLOCK_DEVICES: UPSafeCell<Vec<Arc<dyn BlockDevice>>> =
+        unsafe { UPSafeCell::new(Vec::new()) };
+}
+
+pub fn register(device: Arc<dyn BlockDevice>) {
+    BLOCK_DEVICES.exclusive_access().push(device);
+}
+
+/// The block devices in the order they were found
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.exclusive_access().clone()
+}
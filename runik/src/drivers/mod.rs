@@ -0,0 +1,35 @@
+//! Device drivers
+
+pub mod block;
+pub mod plic;
+pub mod rtc;
+pub mod uart;
+pub mod virtio;
+
+use alloc::sync::Arc;
+use block::BlockDevice;
+use virtio::DeviceType;
+
+/// Discover the devices of the platform and set up those we drive
+pub fn init() {
+    plic::init();
+    uart::init();
+    rtc::init();
+    virtio::init();
+    while let Some(transport) = virtio::take(DeviceType::Block) {
+        let base = transport.base();
+        match virtio::blk::VirtioBlk::new(transport) {
+            Ok(blk) => {
+                println!(
+                    "[kernel] [info] virtio-blk@{:#x}: {} sectors{}",
+                    base, blk.num_blocks(),
+                    if blk.read_only() { ", read-only" } else { "" },
+                );
+                block::register(Arc::new(blk));
+            }
+            Err(e) => {
+                println!("[kernel] [warn] virtio-blk@{:#x}: failed to set up: {:?}", base, e);
+            }
+        }
+    }
+}
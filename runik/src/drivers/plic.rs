@@ -0,0 +1,123 @@
+//! mod plic: the platform-level interrupt controller
+//!
+//! External interrupts of the devices reach the hart through the PLIC.
+//! Drivers [`register`] a handler for the IRQ of their device, with a
+//! priority; the PLIC passes on interrupts of priorities above the
+//! threshold of the hart, and is claimed for the interrupt pending and told
+//! when it is handled.
+
+use crate::plat::qemu::{ PLIC_BASE, PLIC_CONTEXT };
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::ptr::{ read_volatile, write_volatile };
+use lazy_static::*;
+use riscv::register::sie;
+
+const PRIORITY: usize = 0x0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0x0;
+const CLAIM: usize = 0x4;
+
+/// The number of IRQs of the virt machine, from 1
+pub const IRQ_COUNT: u32 = 127;
+/// The highest priority, where 0 never interrupts
+pub const PRIORITY_MAX: u32 = 7;
+
+type Handler = Arc<dyn Fn() + Send + Sync>;
+
+lazy_static! {
+    /// The handlers of the IRQs
+    static ref HANDLERS: UPSafeCell<BTreeMap<u32, Handler>> = unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (PLIC_BASE + offset) as *mut u32
+}
+
+fn context_reg(offset: usize) -> *mut u32 {
+    reg(CONTEXT + PLIC_CONTEXT * CONTEXT_STRIDE + offset)
+}
+
+/// Set the priority of `irq`, from 0 to [`PRIORITY_MAX`]
+pub fn set_priority(irq: u32, priority: u32) {
+    unsafe {
+        write_volatile(reg(PRIORITY + irq as usize * 4), priority.min(PRIORITY_MAX));
+    }
+}
+
+/// Pass on only interrupts of priorities above `threshold`
+pub fn set_threshold(threshold: u32) {
+    unsafe {
+        write_volatile(context_reg(THRESHOLD), threshold.min(PRIORITY_MAX));
+    }
+}
+
+fn set_enabled(irq: u32, enabled: bool) {
+    let enable = reg(ENABLE + PLIC_CONTEXT * ENABLE_STRIDE + (irq as usize / 32) * 4);
+    unsafe {
+        let bits = read_volatile(enable);
+        let bit = 1 << (irq % 32);
+        write_volatile(enable, if enabled { bits | bit } else { bits & !bit });
+    }
+}
+
+/// Call `handler` on each interrupt of `irq`, which has `priority`
+///
+/// The handler runs with interrupts masked, and must make the device stop
+/// raising the interrupt before returning.
+pub fn register(irq: u32, priority: u32, handler: impl Fn() + Send + Sync + 'static) {
+    assert!((1..=IRQ_COUNT).contains(&irq), "no IRQ {}", irq);
+    HANDLERS.exclusive_access().insert(irq, Arc::new(handler));
+    set_priority(irq, priority);
+    set_enabled(irq, true);
+}
+
+/// Stop interrupts of `irq` and forget its handler
+pub fn unregister(irq: u32) {
+    set_enabled(irq, false);
+    HANDLERS.exclusive_access().remove(&irq);
+}
+
+/// The IRQ of the interrupt pending with the highest priority, which is
+/// then in service until [`complete`]
+pub fn claim() -> Option<u32> {
+    match unsafe { read_volatile(context_reg(CLAIM)) } {
+        0 => None,
+        irq => Some(irq),
+    }
+}
+
+/// Tell the PLIC the interrupt of `irq` is handled
+pub fn complete(irq: u32) {
+    unsafe {
+        write_volatile(context_reg(CLAIM), irq);
+    }
+}
+
+/// Take all interrupts to the hart, so that drivers only need to register
+pub fn init() {
+    set_threshold(0);
+    unsafe {
+        sie::set_sext();
+    }
+}
+
+/// Handle the external interrupts pending
+pub fn handle_external() {
+    while let Some(irq) = claim() {
+        // the handler may register others
+        let handler = HANDLERS.exclusive_access().get(&irq).cloned();
+        match handler {
+            Some(handler) => handler(),
+            None => {
+                println!("[kernel] [warn] Unexpected external interrupt {}", irq);
+                set_enabled(irq, false);
+            }
+        }
+        complete(irq);
+    }
+}
@@ -0,0 +1,45 @@
+//! mod rtc: the Goldfish real-time clock
+//!
+//! The clock counts nanoseconds since the epoch. It is read once at boot to
+//! set the realtime clock, which runs on the machine clock from then on.
+
+use crate::plat::qemu::GOLDFISH_RTC_BASE;
+use crate::time::{ self, NSEC_PER_SEC };
+use core::ptr::read_volatile;
+
+// registers, where reading the low half latches the high half
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+pub struct GoldfishRtc {
+    base: usize,
+}
+
+impl GoldfishRtc {
+    /// The clock at `base`, which must be mapped
+    pub unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// Nanoseconds since the epoch
+    pub fn read_ns(&self) -> u64 {
+        unsafe {
+            let low = read_volatile((self.base + TIME_LOW) as *const u32) as u64;
+            let high = read_volatile((self.base + TIME_HIGH) as *const u32) as u64;
+            high << 32 | low
+        }
+    }
+}
+
+/// Set the realtime clock from the RTC of the platform
+pub fn init() {
+    // the RTC is part of the MMIO of the platform
+    let rtc = unsafe { GoldfishRtc::new(GOLDFISH_RTC_BASE) };
+    let ns = rtc.read_ns();
+    if ns == 0 {
+        println!("[kernel] [warn] goldfish-rtc@{:#x}: no time", GOLDFISH_RTC_BASE);
+        return;
+    }
+    time::set_realtime_ns(ns as i64);
+    println!("[kernel] [info] goldfish-rtc@{:#x}: {} seconds since the epoch", GOLDFISH_RTC_BASE, ns / NSEC_PER_SEC);
+}
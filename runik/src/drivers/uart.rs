@@ -0,0 +1,223 @@
+//! mod uart: the ns16550a UART the console is on
+//!
+//! Received bytes are taken from the UART as they arrive, on its interrupt,
+//! into a ring buffer, and handed one at a time to the receiver, which is
+//! the terminal. Bytes written go through another ring buffer, which the
+//! UART drains as its transmitter empties.
+
+use super::plic;
+use crate::config::{ UART_RX_BUFFER_SIZE, UART_TX_BUFFER_SIZE };
+use crate::plat::qemu::{ UART0_BASE, UART0_IRQ };
+use crate::sync::UPSafeCell;
+use core::ptr::{ read_volatile, write_volatile };
+use core::sync::atomic::{ AtomicBool, Ordering };
+use lazy_static::*;
+
+// registers, where DLL and DLM take the place of RBR/THR and IER with LCR_DLAB
+const RBR: usize = 0;
+const THR: usize = 0;
+const DLL: usize = 0;
+const IER: usize = 1;
+const DLM: usize = 1;
+const FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+/// enable and clear the FIFOs
+const FCR_FIFO: u8 = 0x07;
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 0x80;
+/// DTR, RTS and OUT2, which gates the interrupt
+const MCR_DTR_RTS_OUT2: u8 = 0x0b;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TX_EMPTY: u8 = 1 << 5;
+
+/// The size of the transmit FIFO
+const TX_FIFO_SIZE: usize = 16;
+
+/// A byte queue of fixed capacity
+struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    const fn new() -> Self {
+        Self { buf: [0; N], head: 0, len: 0 }
+    }
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+    /// Add `byte` at the end, unless full
+    fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+pub struct Uart {
+    base: usize,
+    rx: RingBuffer<UART_RX_BUFFER_SIZE>,
+    tx: RingBuffer<UART_TX_BUFFER_SIZE>,
+}
+
+impl Uart {
+    fn read_reg(&self, reg: usize) -> u8 {
+        unsafe { read_volatile((self.base + reg) as *const u8) }
+    }
+
+    fn write_reg(&self, reg: usize, val: u8) {
+        unsafe { write_volatile((self.base + reg) as *mut u8, val) }
+    }
+
+    fn init(&mut self) {
+        self.write_reg(IER, 0);
+        // the divisor for 38400 baud, which QEMU ignores
+        self.write_reg(LCR, LCR_DLAB);
+        self.write_reg(DLL, 3);
+        self.write_reg(DLM, 0);
+        self.write_reg(LCR, LCR_8N1);
+        self.write_reg(FCR, FCR_FIFO);
+        self.write_reg(MCR, MCR_DTR_RTS_OUT2);
+        self.write_reg(IER, IER_RX_AVAILABLE);
+    }
+
+    /// Take the bytes the UART has received
+    fn receive(&mut self) {
+        while self.read_reg(LSR) & LSR_DATA_READY != 0 {
+            // bytes are dropped while the buffer is full
+            self.rx.push(self.read_reg(RBR));
+        }
+    }
+
+    /// Move bytes to the UART while its FIFO is empty, and have it
+    /// interrupt for more as long as there are some
+    fn transmit(&mut self) {
+        if self.read_reg(LSR) & LSR_TX_EMPTY != 0 {
+            for _ in 0..TX_FIFO_SIZE {
+                match self.tx.pop() {
+                    Some(byte) => self.write_reg(THR, byte),
+                    None => break,
+                }
+            }
+        }
+        let ier = if self.tx.is_empty() { IER_RX_AVAILABLE } else { IER_RX_AVAILABLE | IER_TX_EMPTY };
+        self.write_reg(IER, ier);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            // with interrupts masked in the kernel, a full buffer is drained
+            // by waiting on the UART
+            while !self.tx.push(byte) {
+                self.transmit();
+            }
+        }
+        self.transmit();
+    }
+
+    fn flush(&mut self) {
+        while !self.tx.is_empty() {
+            self.transmit();
+        }
+    }
+}
+
+lazy_static! {
+    static ref UART: UPSafeCell<Uart> = unsafe {
+        UPSafeCell::new(Uart {
+            base: UART0_BASE,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+        })
+    };
+}
+
+lazy_static! {
+    /// What received bytes are handed to, which may write to the UART
+    static ref RECEIVER: UPSafeCell<Option<fn(u8)>> = unsafe { UPSafeCell::new(None) };
+}
+
+/// Whether the UART is set up, before which the console goes through SBI
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Set up the UART, and take over the console from SBI
+pub fn init() {
+    UART.exclusive_access().init();
+    plic::register(UART0_IRQ, 1, handle_irq);
+    READY.store(true, Ordering::Release);
+}
+
+pub fn is_ready() -> bool {
+    READY.load(Ordering::Acquire)
+}
+
+/// Handle the interrupt of the UART
+fn handle_irq() {
+    let mut uart = UART.exclusive_access();
+    uart.receive();
+    uart.transmit();
+    drop(uart);
+    deliver();
+}
+
+/// Hand the bytes received to the receiver, if there is one yet
+fn deliver() {
+    let Some(receiver) = *RECEIVER.exclusive_access() else {
+        return;
+    };
+    loop {
+        // the UART is let go of for each byte, for echoes to be written
+        let byte = UART.exclusive_access().rx.pop();
+        let Some(byte) = byte else {
+            break;
+        };
+        receiver(byte);
+    }
+}
+
+/// Have `receiver` take the bytes received from now on, and those received
+/// so far
+pub fn set_receiver(receiver: fn(u8)) {
+    *RECEIVER.exclusive_access() = Some(receiver);
+    deliver();
+}
+
+/// Write `bytes`, or give them back if the UART is in use, as when a
+/// panic interrupts a write
+pub fn write<'a>(bytes: &'a [u8]) -> Result<(), &'a [u8]> {
+    match UART.try_exclusive_access() {
+        Some(mut uart) => {
+            uart.write(bytes);
+            Ok(())
+        }
+        None => Err(bytes),
+    }
+}
+
+/// Wait until all bytes written are sent
+pub fn flush() {
+    if let Some(mut uart) = UART.try_exclusive_access() {
+        uart.flush();
+    }
+}
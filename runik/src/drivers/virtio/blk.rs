@@ -0,0 +1,108 @@
+//! mod blk: the virtio block device
+//!
+//! Requests are served one at a time, waiting for the device to complete
+//! each before returning.
+
+use super::{ VirtioMmio, VirtQueue };
+use crate::drivers::block::{ BlockDevice, BLOCK_SIZE };
+use crate::sync::UPSafeCell;
+use core::mem::size_of;
+use linuxabi::errno::Errno::{ self, * };
+
+/// The device is read-only
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+
+// types of request
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+const QUEUE_SIZE: u16 = 16;
+
+/// The header of a request, which the device reads
+#[repr(C)]
+struct BlkReqHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+impl BlkReqHeader {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+}
+
+struct BlkInner {
+    transport: VirtioMmio,
+    queue: VirtQueue,
+}
+
+pub struct VirtioBlk {
+    inner: UPSafeCell<BlkInner>,
+    capacity: usize,
+    read_only: bool,
+}
+
+impl VirtioBlk {
+    /// Set up the block device on `transport`
+    pub fn new(mut transport: VirtioMmio) -> Result<Self, Errno> {
+        let features = transport.begin_init(VIRTIO_BLK_F_RO)?;
+        let queue = transport.setup_queue(0, QUEUE_SIZE)?;
+        transport.finish_init();
+        // the capacity is in 512-byte sectors whatever the block size
+        let capacity = transport.read_config::<u64>(0) as usize;
+        Ok(Self {
+            inner: unsafe { UPSafeCell::new(BlkInner { transport, queue }) },
+            capacity,
+            read_only: features & VIRTIO_BLK_F_RO != 0,
+        })
+    }
+
+    fn check_range(&self, block: usize, len: usize) -> Result<(), Errno> {
+        if len % BLOCK_SIZE != 0 {
+            return Err(EINVAL);
+        }
+        match block.checked_add(len / BLOCK_SIZE) {
+            Some(end) if end <= self.capacity => Ok(()),
+            _ => Err(EIO),
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn num_blocks(&self) -> usize {
+        self.capacity
+    }
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+    fn read_blocks(&self, block: usize, buf: &mut [u8]) -> Result<(), Errno> {
+        self.check_range(block, buf.len())?;
+        let header = BlkReqHeader { kind: VIRTIO_BLK_T_IN, reserved: 0, sector: block as u64 };
+        let mut status = [0xffu8];
+        let inner = &mut *self.inner.exclusive_access();
+        inner.queue.add_notify_wait_pop(&inner.transport, &[header.as_bytes()], &mut [buf, &mut status])?;
+        inner.transport.ack_interrupt();
+        match status[0] {
+            VIRTIO_BLK_S_OK => Ok(()),
+            _ => Err(EIO),
+        }
+    }
+    fn write_blocks(&self, block: usize, buf: &[u8]) -> Result<(), Errno> {
+        if self.read_only {
+            return Err(EROFS);
+        }
+        self.check_range(block, buf.len())?;
+        let header = BlkReqHeader { kind: VIRTIO_BLK_T_OUT, reserved: 0, sector: block as u64 };
+        let mut status = [0xffu8];
+        let inner = &mut *self.inner.exclusive_access();
+        inner.queue.add_notify_wait_pop(&inner.transport, &[header.as_bytes(), buf], &mut [&mut status])?;
+        inner.transport.ack_interrupt();
+        match status[0] {
+            VIRTIO_BLK_S_OK => Ok(()),
+            _ => Err(EIO),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! mod mmio: the virtio-mmio transport
+//!
+//! Both the legacy interface (version 1), which QEMU gives by default, and
+//! the modern one (version 2) are supported. They differ in how queues are
+//! handed to the device.
+
+use super::queue::VirtQueue;
+use super::DeviceType;
+use crate::arch::paging::PAGE_SIZE;
+use crate::plat::qemu::{ VIRTIO_MMIO_BASE, VIRTIO_MMIO_IRQ, VIRTIO_MMIO_SIZE };
+use core::mem::size_of;
+use core::ptr::{ read_volatile, write_volatile };
+use linuxabi::errno::Errno::{ self, * };
+
+/// "virt" in little endian
+const MAGIC_VALUE: u32 = 0x7472_6976;
+
+// offsets of the registers
+const MAGIC: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const VENDOR_ID: usize = 0x00c;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG_GENERATION: usize = 0x0fc;
+const CONFIG: usize = 0x100;
+
+// bits of the device status
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// The device conforms to the virtio 1.0 specification, which a modern
+/// device requires the driver to accept
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// The registers of a device in one virtio-mmio slot
+pub struct VirtioMmio {
+    base: usize,
+    version: u32,
+}
+
+impl VirtioMmio {
+    /// The device at the slot `base`, if there is one
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the address of a virtio-mmio slot, mapped for the
+    /// kernel and not driven by anyone else.
+    pub unsafe fn probe(base: usize) -> Option<Self> {
+        let transport = Self { base, version: 0 };
+        if transport.read(MAGIC) != MAGIC_VALUE {
+            return None;
+        }
+        let version = transport.read(VERSION);
+        // an empty slot has device ID 0
+        if !(1..=2).contains(&version) || transport.read(DEVICE_ID) == 0 {
+            return None;
+        }
+        Some(Self { base, version })
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    /// The IRQ of the slot of the device
+    pub fn irq(&self) -> u32 {
+        VIRTIO_MMIO_IRQ + ((self.base - VIRTIO_MMIO_BASE) / VIRTIO_MMIO_SIZE) as u32
+    }
+    pub fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+    pub fn device_type(&self) -> DeviceType {
+        DeviceType::from(self.read(DEVICE_ID))
+    }
+    pub fn vendor_id(&self) -> u32 {
+        self.read(VENDOR_ID)
+    }
+
+    fn device_features(&self) -> u64 {
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let low = self.read(DEVICE_FEATURES) as u64;
+        self.write(DEVICE_FEATURES_SEL, 1);
+        let high = self.read(DEVICE_FEATURES) as u64;
+        high << 32 | low
+    }
+    fn set_driver_features(&self, features: u64) {
+        self.write(DRIVER_FEATURES_SEL, 0);
+        self.write(DRIVER_FEATURES, features as u32);
+        self.write(DRIVER_FEATURES_SEL, 1);
+        self.write(DRIVER_FEATURES, (features >> 32) as u32);
+    }
+
+    /// Reset the device and negotiate the features, accepting those of
+    /// `supported` the device offers. Queues are to be set up afterwards,
+    /// then [`Self::finish_init`] called.
+    pub fn begin_init(&mut self, supported: u64) -> Result<u64, Errno> {
+        self.write(STATUS, 0);
+        self.write(STATUS, STATUS_ACKNOWLEDGE);
+        self.write(STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let offered = self.device_features();
+        let mut features = offered & supported;
+        if !self.is_legacy() {
+            if offered & VIRTIO_F_VERSION_1 == 0 {
+                self.write(STATUS, STATUS_FAILED);
+                return Err(ENODEV);
+            }
+            features |= VIRTIO_F_VERSION_1;
+        }
+        self.set_driver_features(features);
+        if self.is_legacy() {
+            // a legacy device has no FEATURES_OK step
+            self.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            self.write(STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+            if self.read(STATUS) & STATUS_FEATURES_OK == 0 {
+                self.write(STATUS, STATUS_FAILED);
+                return Err(ENODEV);
+            }
+        }
+        Ok(features)
+    }
+
+    /// Tell the device the driver is ready to use it
+    pub fn finish_init(&mut self) {
+        let status = self.read(STATUS);
+        self.write(STATUS, status | STATUS_DRIVER_OK);
+    }
+
+    /// Create the queue `index` with at most `max_size` descriptors and
+    /// hand it to the device
+    pub fn setup_queue(&mut self, index: u16, max_size: u16) -> Result<VirtQueue, Errno> {
+        self.write(QUEUE_SEL, index as u32);
+        let ready = if self.is_legacy() { self.read(QUEUE_PFN) } else { self.read(QUEUE_READY) };
+        if ready != 0 {
+            return Err(EBUSY);
+        }
+        let num_max = self.read(QUEUE_NUM_MAX);
+        if num_max == 0 {
+            return Err(ENOENT);
+        }
+        // the size of a split queue is a power of 2
+        let size = num_max.min(max_size as u32);
+        let size = 1u16 << (u32::BITS - 1 - size.leading_zeros());
+        let queue = VirtQueue::new(index, size).ok_or(ENOMEM)?;
+        self.write(QUEUE_NUM, size as u32);
+        if self.is_legacy() {
+            self.write(QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(QUEUE_PFN, (queue.desc_addr() / PAGE_SIZE) as u32);
+        } else {
+            let write_addr = |low, high, addr: usize| {
+                self.write(low, addr as u32);
+                self.write(high, (addr as u64 >> 32) as u32);
+            };
+            write_addr(QUEUE_DESC_LOW, QUEUE_DESC_HIGH, queue.desc_addr());
+            write_addr(QUEUE_DRIVER_LOW, QUEUE_DRIVER_HIGH, queue.avail_addr());
+            write_addr(QUEUE_DEVICE_LOW, QUEUE_DEVICE_HIGH, queue.used_addr());
+            self.write(QUEUE_READY, 1);
+        }
+        Ok(queue)
+    }
+
+    /// Tell the device there are new buffers in the queue `index`
+    pub fn notify(&self, index: u16) {
+        self.write(QUEUE_NOTIFY, index as u32);
+    }
+
+    /// Acknowledge the pending interrupts, returning their causes
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(INTERRUPT_STATUS);
+        if status != 0 {
+            self.write(INTERRUPT_ACK, status);
+        }
+        status
+    }
+
+    /// Read the field at `offset` of the configuration space with accesses
+    /// of its width, where 64-bit fields take two 32-bit ones
+    fn read_config_raw<T: Copy>(&self, offset: usize) -> T {
+        let ptr = (self.base + CONFIG + offset) as *const T;
+        unsafe {
+            if size_of::<T>() == 8 {
+                let low = read_volatile(ptr as *const u32) as u64;
+                let high = read_volatile((ptr as *const u32).add(1)) as u64;
+                core::mem::transmute_copy(&(high << 32 | low))
+            } else {
+                read_volatile(ptr)
+            }
+        }
+    }
+    /// Read the field at `offset` of the configuration space of the device
+    pub fn read_config<T: Copy>(&self, offset: usize) -> T {
+        if self.is_legacy() {
+            return self.read_config_raw(offset);
+        }
+        // retry if the device changed the configuration while reading
+        loop {
+            let generation = self.read(CONFIG_GENERATION);
+            let value = self.read_config_raw(offset);
+            if self.read(CONFIG_GENERATION) == generation {
+                return value;
+            }
+        }
+    }
+    /// Write the field at `offset` of the configuration space of the device
+    pub fn write_config<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { write_volatile((self.base + CONFIG + offset) as *mut T, value) }
+    }
+}
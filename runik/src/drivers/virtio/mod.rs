@@ -0,0 +1,79 @@
+//! mod virtio: virtio devices on the virtio-mmio transport
+//!
+//! [`init`] scans the virtio-mmio slots of the platform once at boot. The
+//! drivers then [`take`] the devices of their type and set them up through
+//! [`VirtioMmio`] and [`VirtQueue`].
+
+mod mmio;
+mod queue;
+pub mod blk;
+pub mod net;
+pub mod p9;
+
+pub use mmio::{ VirtioMmio, VIRTIO_F_VERSION_1 };
+pub use queue::VirtQueue;
+
+use crate::plat::qemu::{ VIRTIO_MMIO_BASE, VIRTIO_MMIO_COUNT, VIRTIO_MMIO_SIZE };
+use crate::sync::UPSafeCell;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// The types of device, by their device IDs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    Network,
+    Block,
+    Console,
+    Entropy,
+    Balloon,
+    NineP,
+    Gpu,
+    Input,
+    Socket,
+    Unknown(u32),
+}
+
+impl From<u32> for DeviceType {
+    fn from(id: u32) -> Self {
+        match id {
+            1 => Self::Network,
+            2 => Self::Block,
+            3 => Self::Console,
+            4 => Self::Entropy,
+            5 => Self::Balloon,
+            9 => Self::NineP,
+            16 => Self::Gpu,
+            18 => Self::Input,
+            19 => Self::Socket,
+            id => Self::Unknown(id),
+        }
+    }
+}
+
+lazy_static! {
+    /// The devices found and not yet taken by a driver
+    static ref DEVICES: UPSafeCell<Vec<VirtioMmio>> = unsafe { UPSafeCell::new(Vec::new()) };
+}
+
+/// Probe the virtio-mmio slots for devices
+pub fn init() {
+    let mut devices = DEVICES.exclusive_access();
+    for slot in 0..VIRTIO_MMIO_COUNT {
+        let base = VIRTIO_MMIO_BASE + slot * VIRTIO_MMIO_SIZE;
+        // the slots are ours alone, and mapped as part of MMIO
+        if let Some(device) = unsafe { VirtioMmio::probe(base) } {
+            println!(
+                "[kernel] [info] virtio-mmio@{:#x}: {:?} device, version {}",
+                base, device.device_type(), device.version(),
+            );
+            devices.push(device);
+        }
+    }
+}
+
+/// Take the first device of type `kind` not taken yet
+pub fn take(kind: DeviceType) -> Option<VirtioMmio> {
+    let mut devices = DEVICES.exclusive_access();
+    let i = devices.iter().position(|device| device.device_type() == kind)?;
+    Some(devices.remove(i))
+}
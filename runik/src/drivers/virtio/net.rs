@@ -0,0 +1,104 @@
+//! mod net: the virtio network device
+//!
+//! The receive queue is kept full of buffers, each of which is handed back
+//! to the device once the frame in it has been copied out. Frames are sent
+//! one at a time, waiting for the device to take each.
+
+use super::{ VirtioMmio, VirtQueue };
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use linuxabi::errno::Errno;
+
+/// The device has a MAC address in its configuration space
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const QUEUE_SIZE: u16 = 16;
+
+/// The size of a receive buffer, enough for the header and a frame of the
+/// largest Ethernet MTU
+const RX_BUFFER_SIZE: usize = 2048;
+/// The largest frame sent, without a frame check sequence
+pub const MAX_FRAME_SIZE: usize = 1514;
+
+pub struct VirtioNet {
+    transport: VirtioMmio,
+    rx: VirtQueue,
+    tx: VirtQueue,
+    /// Allocated once and never resized, so the buffers do not move
+    rx_buffers: Vec<[u8; RX_BUFFER_SIZE]>,
+    /// The receive buffers in the queue by their tokens
+    rx_tokens: BTreeMap<u16, usize>,
+    mac: [u8; 6],
+    /// The size of struct virtio_net_hdr before each frame, which has
+    /// `num_buffers` for modern devices only
+    header_len: usize,
+}
+
+impl VirtioNet {
+    /// Set up the network device on `transport`
+    pub fn new(mut transport: VirtioMmio) -> Result<Self, Errno> {
+        let features = transport.begin_init(VIRTIO_NET_F_MAC)?;
+        let rx = transport.setup_queue(RX_QUEUE, QUEUE_SIZE)?;
+        let tx = transport.setup_queue(TX_QUEUE, QUEUE_SIZE)?;
+        let mut mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        if features & VIRTIO_NET_F_MAC != 0 {
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = transport.read_config::<u8>(i);
+            }
+        }
+        let header_len = if transport.is_legacy() { 10 } else { 12 };
+        let mut net = Self {
+            transport,
+            rx_buffers: vec![[0u8; RX_BUFFER_SIZE]; rx.size() as usize],
+            rx,
+            tx,
+            rx_tokens: BTreeMap::new(),
+            mac,
+            header_len,
+        };
+        for i in 0..net.rx_buffers.len() {
+            net.give_rx_buffer(i)?;
+        }
+        net.transport.finish_init();
+        net.transport.notify(RX_QUEUE);
+        Ok(net)
+    }
+
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    /// Put the receive buffer `i` in the receive queue
+    fn give_rx_buffer(&mut self, i: usize) -> Result<(), Errno> {
+        // the buffer stays put until the device is done, as `rx_buffers` is
+        // never resized
+        let token = unsafe { self.rx.add(&[], &mut [&mut self.rx_buffers[i][..]])? };
+        self.rx_tokens.insert(token, i);
+        Ok(())
+    }
+
+    /// Take a received frame, if there is one
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        self.transport.ack_interrupt();
+        let (token, len) = self.rx.pop_used()?;
+        let i = self.rx_tokens.remove(&token)?;
+        let len = (len as usize).clamp(self.header_len, RX_BUFFER_SIZE);
+        let frame = self.rx_buffers[i][self.header_len..len].to_vec();
+        if let Err(e) = self.give_rx_buffer(i) {
+            println!("[kernel] [warn] virtio-net: failed to reuse a receive buffer: {:?}", e);
+        }
+        self.transport.notify(RX_QUEUE);
+        Some(frame)
+    }
+
+    /// Send the Ethernet frame `frame`
+    pub fn send(&mut self, frame: &[u8]) -> Result<(), Errno> {
+        // a zeroed header asks for no offloading
+        let header = vec![0u8; self.header_len];
+        self.tx.add_notify_wait_pop(&self.transport, &[&header, frame], &mut [])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! mod p9: the virtio 9P transport
+//!
+//! The device carries 9P messages for a directory the host exports. Each
+//! request is a message for the device and a buffer for its reply, served
+//! one at a time.
+
+use super::{ VirtioMmio, VirtQueue };
+use crate::sync::UPSafeCell;
+use alloc::string::String;
+use alloc::vec::Vec;
+use linuxabi::errno::Errno;
+
+/// The device has a tag to tell the exported directories apart
+const VIRTIO_9P_MOUNT_TAG: u64 = 1 << 0;
+
+const QUEUE_SIZE: u16 = 16;
+
+struct P9Inner {
+    transport: VirtioMmio,
+    queue: VirtQueue,
+}
+
+pub struct Virtio9p {
+    inner: UPSafeCell<P9Inner>,
+    tag: String,
+}
+
+impl Virtio9p {
+    /// Set up the 9P device on `transport`
+    pub fn new(mut transport: VirtioMmio) -> Result<Self, Errno> {
+        let features = transport.begin_init(VIRTIO_9P_MOUNT_TAG)?;
+        let queue = transport.setup_queue(0, QUEUE_SIZE)?;
+        transport.finish_init();
+        let mut tag = Vec::new();
+        if features & VIRTIO_9P_MOUNT_TAG != 0 {
+            let len = transport.read_config::<u16>(0) as usize;
+            tag = (0..len).map(|i| transport.read_config::<u8>(2 + i)).collect();
+        }
+        Ok(Self {
+            inner: unsafe { UPSafeCell::new(P9Inner { transport, queue }) },
+            tag: String::from_utf8_lossy(&tag).into_owned(),
+        })
+    }
+
+    /// The tag the host gave the export, which may be empty
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Send the message `request` and wait for the reply in `reply`,
+    /// returning the length of the reply
+    pub fn request(&self, request: &[u8], reply: &mut [u8]) -> Result<usize, Errno> {
+        let inner = &mut *self.inner.exclusive_access();
+        let len = inner.queue.add_notify_wait_pop(&inner.transport, &[request], &mut [reply])?;
+        inner.transport.ack_interrupt();
+        Ok(len as usize)
+    }
+}
@@ -0,0 +1,203 @@
+//! mod queue: split virtqueues
+//!
+//! The descriptor table, the available ring and the used ring of a queue
+//! live in frames taken from [`crate::mm::frame`], laid out as the legacy
+//! interface wants them. The kernel maps memory at its physical addresses,
+//! so buffers in kernel memory are given to devices by their addresses.
+
+use super::mmio::VirtioMmio;
+use crate::arch::paging::PAGE_SIZE;
+use crate::mm::addr::PhysAddr;
+use crate::mm::frame::{ frame_alloc_contiguous, FrameTracker };
+use core::mem::size_of;
+use core::ptr::{ read_volatile, write_volatile };
+use core::sync::atomic::{ fence, Ordering };
+use alloc::vec::Vec;
+use linuxabi::errno::Errno::{ self, * };
+
+// flags of a descriptor
+/// The buffer continues in the descriptor `next`
+const DESC_F_NEXT: u16 = 1;
+/// The device writes the buffer instead of reading it
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// An element of the used ring: the head of a chain the device is done
+/// with, and how many bytes it wrote
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    frames: Vec<FrameTracker>,
+    desc: usize,
+    avail: usize,
+    used: usize,
+    /// The head of the list of free descriptors, linked by `next`
+    free_head: u16,
+    num_free: u16,
+    /// The index of the available ring the driver writes next
+    avail_idx: u16,
+    /// The index of the used ring the driver reads next
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    /// A queue numbered `index` with `size` descriptors, a power of 2
+    pub fn new(index: u16, size: u16) -> Option<Self> {
+        let n = size as usize;
+        let avail_offset = n * size_of::<Descriptor>();
+        let used_offset = (avail_offset + 6 + 2 * n).next_multiple_of(PAGE_SIZE);
+        let len = used_offset + 6 + n * size_of::<UsedElem>();
+        let frames = frame_alloc_contiguous(len.div_ceil(PAGE_SIZE))?;
+        let desc = usize::from(PhysAddr::from(frames[0].ppn));
+        let queue = Self {
+            index,
+            size,
+            frames,
+            desc,
+            avail: desc + avail_offset,
+            used: desc + used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size - 1 {
+            unsafe { (*queue.desc_at(i)).next = i + 1 };
+        }
+        Some(queue)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+    pub fn desc_addr(&self) -> usize {
+        self.desc
+    }
+    pub fn avail_addr(&self) -> usize {
+        self.avail
+    }
+    pub fn used_addr(&self) -> usize {
+        self.used
+    }
+
+    fn desc_at(&self, i: u16) -> *mut Descriptor {
+        (self.desc + i as usize * size_of::<Descriptor>()) as *mut Descriptor
+    }
+
+    /// Put a chain of buffers in the queue, which the device reads from
+    /// `inputs` and writes to `outputs`, returning its token
+    ///
+    /// # Safety
+    ///
+    /// The buffers must stay alive and untouched until the token is popped
+    /// by [`Self::pop_used`].
+    pub unsafe fn add(&mut self, inputs: &[&[u8]], outputs: &mut [&mut [u8]]) -> Result<u16, Errno> {
+        let count = inputs.len() + outputs.len();
+        if count == 0 {
+            return Err(EINVAL);
+        }
+        if count > self.num_free as usize {
+            return Err(ENOSPC);
+        }
+        let buffers = inputs.iter().map(|buf| (buf.as_ptr() as usize, buf.len(), 0))
+            .chain(outputs.iter_mut().map(|buf| (buf.as_mut_ptr() as usize, buf.len(), DESC_F_WRITE)));
+        let head = self.free_head;
+        for (i, (addr, len, flags)) in buffers.enumerate() {
+            let desc = self.desc_at(self.free_head);
+            (*desc).addr = addr as u64;
+            (*desc).len = len as u32;
+            (*desc).flags = if i + 1 < count { flags | DESC_F_NEXT } else { flags };
+            self.free_head = (*desc).next;
+        }
+        self.num_free -= count as u16;
+
+        let slot = self.avail_idx % self.size;
+        write_volatile((self.avail + 4 + 2 * slot as usize) as *mut u16, head);
+        // the device must see the descriptors before the new index
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        write_volatile((self.avail + 2) as *mut u16, self.avail_idx);
+        fence(Ordering::SeqCst);
+        Ok(head)
+    }
+
+    /// Whether the device is done with a chain not yet popped
+    pub fn can_pop(&self) -> bool {
+        fence(Ordering::SeqCst);
+        let used_idx = unsafe { read_volatile((self.used + 2) as *const u16) };
+        used_idx != self.last_used_idx
+    }
+
+    /// Take the next chain the device is done with, freeing its descriptors,
+    /// and return its token and the number of bytes the device wrote
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.can_pop() {
+            return None;
+        }
+        let slot = self.last_used_idx % self.size;
+        let elem = (self.used + 4 + slot as usize * size_of::<UsedElem>()) as *const UsedElem;
+        let (id, len) = unsafe { (read_volatile(&(*elem).id) as u16, read_volatile(&(*elem).len)) };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        // put the chain back at the head of the free list
+        let mut i = id;
+        loop {
+            let desc = self.desc_at(i);
+            self.num_free += 1;
+            unsafe {
+                (*desc).addr = 0;
+                (*desc).len = 0;
+                if (*desc).flags & DESC_F_NEXT == 0 {
+                    (*desc).next = self.free_head;
+                    break;
+                }
+                (*desc).flags = 0;
+                i = (*desc).next;
+            }
+        }
+        unsafe { (*self.desc_at(i)).flags = 0 };
+        self.free_head = id;
+        Some((id, len))
+    }
+
+    /// Put a chain of buffers in the queue, tell the device and wait until
+    /// it is done, returning the number of bytes the device wrote
+    pub fn add_notify_wait_pop(
+        &mut self,
+        transport: &VirtioMmio,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<u32, Errno> {
+        // the buffers outlive the request as we wait for it here
+        let token = unsafe { self.add(inputs, outputs)? };
+        transport.notify(self.index);
+        loop {
+            match self.pop_used() {
+                Some((id, len)) if id == token => return Ok(len),
+                Some(_) => {
+                    println!("[kernel] [warn] virtio: queue {} returned an unknown chain", self.index);
+                }
+                None => core::hint::spin_loop(),
+            }
+        }
+    }
+}
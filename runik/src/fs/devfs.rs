@@ -0,0 +1,72 @@
+//! mod devfs: the device files under /dev
+
+use super::{ new_ino, File, Inode, InodeType, StaticDir };
+use super::tty::Console;
+use alloc::sync::Arc;
+use lazy_static::*;
+use linuxabi::errno::Errno;
+
+/// A character device, which opens to a fresh file of the device
+struct DevNode {
+    ino: usize,
+    open: fn() -> Arc<dyn File>,
+}
+
+impl Inode for DevNode {
+    fn kind(&self) -> InodeType {
+        InodeType::CharDevice
+    }
+    fn ino(&self) -> usize {
+        self.ino
+    }
+    fn mode(&self) -> u32 {
+        0o666
+    }
+    fn device(&self) -> Option<Arc<dyn File>> {
+        Some((self.open)())
+    }
+}
+
+fn dev(open: fn() -> Arc<dyn File>) -> Arc<dyn Inode> {
+    Arc::new(DevNode { ino: new_ino(), open })
+}
+
+/// /dev/null, which reads nothing and discards what is written
+struct Null;
+
+impl File for Null {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(0)
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(buf.len())
+    }
+}
+
+/// /dev/zero, which reads zeros and discards what is written
+struct Zero;
+
+impl File for Zero {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(buf.len())
+    }
+}
+
+lazy_static! {
+    /// /dev/console, which stdin, stdout and stderr are open on
+    pub static ref CONSOLE: Arc<dyn Inode> = dev(|| Arc::new(Console::new()));
+}
+
+/// The root of devfs
+pub fn new() -> Arc<dyn Inode> {
+    StaticDir::new([
+        ("console", CONSOLE.clone()),
+        ("tty", CONSOLE.clone()),
+        ("null", dev(|| Arc::new(Null))),
+        ("zero", dev(|| Arc::new(Zero))),
+    ])
+}
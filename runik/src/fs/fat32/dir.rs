@@ -0,0 +1,334 @@
+//! mod dir: directory entries of FAT, with long file names
+//!
+//! A file is listed by a short entry holding an 8.3 name and its metadata,
+//! preceded by long name entries when its name does not fit in 8.3.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use linuxabi::errno::Errno::{ self, * };
+
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// The attributes marking a long name entry
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// The first byte of a free entry
+pub const ENTRY_FREE: u8 = 0xe5;
+/// The first byte of the free entry ending the directory
+pub const ENTRY_END: u8 = 0x00;
+/// Set in the ordinal of the last long name entry of a name
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// The UTF-16 units of a name in each long name entry
+const LONG_NAME_UNITS: usize = 13;
+/// The offsets of the units in a long name entry
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_UNITS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_LONG_NAME: usize = 255;
+
+/// Bits of the reserved byte, as Windows NT uses them, for 8.3 names whose
+/// base or extension is in lower case
+const NTRES_LOWER_BASE: u8 = 0x08;
+const NTRES_LOWER_EXT: u8 = 0x10;
+
+/// 1980-01-01, the earliest time FAT stores
+pub const FAT_EPOCH: i64 = 315532800;
+
+pub type RawEntry = [u8; DIR_ENTRY_SIZE];
+
+/// A short entry, which holds the metadata of a file
+#[derive(Clone, Copy)]
+pub struct ShortEntry(pub RawEntry);
+
+impl ShortEntry {
+    pub fn new(name: [u8; 11], ntres: u8, attr: u8, time: i64) -> Self {
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[..11].copy_from_slice(&name);
+        raw[11] = attr;
+        raw[12] = ntres;
+        let mut entry = Self(raw);
+        let (date, time) = fat_datetime(time);
+        entry.set_u16(14, time);
+        entry.set_u16(16, date);
+        entry.set_u16(18, date);
+        entry.set_mtime_raw(date, time);
+        entry
+    }
+    fn u16_at(&self, i: usize) -> u16 {
+        u16::from_le_bytes([self.0[i], self.0[i + 1]])
+    }
+    fn set_u16(&mut self, i: usize, value: u16) {
+        self.0[i..i + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    pub fn name(&self) -> [u8; 11] {
+        self.0[..11].try_into().unwrap()
+    }
+    pub fn set_name(&mut self, name: [u8; 11], ntres: u8) {
+        self.0[..11].copy_from_slice(&name);
+        self.0[12] = ntres & (NTRES_LOWER_BASE | NTRES_LOWER_EXT);
+    }
+    pub fn attr(&self) -> u8 {
+        self.0[11]
+    }
+    pub fn is_dir(&self) -> bool {
+        self.attr() & ATTR_DIRECTORY != 0
+    }
+    pub fn first_cluster(&self) -> u32 {
+        ((self.u16_at(20) as u32) << 16) | self.u16_at(26) as u32
+    }
+    pub fn set_first_cluster(&mut self, cluster: u32) {
+        self.set_u16(20, (cluster >> 16) as u16);
+        self.set_u16(26, cluster as u16);
+    }
+    pub fn size(&self) -> u32 {
+        u32::from_le_bytes(self.0[28..32].try_into().unwrap())
+    }
+    pub fn set_size(&mut self, size: u32) {
+        self.0[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+    pub fn mtime(&self) -> i64 {
+        unix_time(self.u16_at(24), self.u16_at(22))
+    }
+    fn set_mtime_raw(&mut self, date: u16, time: u16) {
+        self.set_u16(22, time);
+        self.set_u16(24, date);
+    }
+    /// Stamp a modification at `time`, which marks the file for backup
+    pub fn touch(&mut self, time: i64) {
+        let (date, time) = fat_datetime(time);
+        self.set_mtime_raw(date, time);
+        self.set_u16(18, date);
+        self.0[11] |= ATTR_ARCHIVE;
+    }
+
+    /// The 8.3 name as it is displayed, in lower case where the reserved
+    /// byte says so
+    pub fn display_name(&self) -> String {
+        let mut name = self.name();
+        if name[0] == 0x05 {
+            // a name starting with 0xe5 is stored with 0x05
+            name[0] = ENTRY_FREE;
+        }
+        let part = |bytes: &[u8], lower: bool| -> String {
+            let len = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+            bytes[..len].iter()
+                .map(|&b| if lower { b.to_ascii_lowercase() } else { b } as char)
+                .collect()
+        };
+        let base = part(&name[..8], self.0[12] & NTRES_LOWER_BASE != 0);
+        let ext = part(&name[8..], self.0[12] & NTRES_LOWER_EXT != 0);
+        if ext.is_empty() { base } else { base + "." + &ext }
+    }
+}
+
+/// A file listed in a directory
+pub struct Listed {
+    pub name: String,
+    pub entry: ShortEntry,
+    /// The index of the short entry
+    pub index: usize,
+    /// The index of the first entry of the file, a long name entry if it
+    /// has any
+    pub first: usize,
+}
+
+/// Decode the files listed by the entries of a directory, except `.` and
+/// `..`
+pub fn parse(entries: &[RawEntry]) -> Vec<Listed> {
+    let mut listed = Vec::new();
+    // the long name being assembled: its units, the index of its first
+    // entry, its checksum and the ordinal expected next
+    let mut long: Option<(Vec<u16>, usize, u8, u8)> = None;
+    for (i, raw) in entries.iter().enumerate() {
+        match raw[0] {
+            ENTRY_END => break,
+            ENTRY_FREE => {
+                long = None;
+                continue;
+            }
+            _ => {},
+        }
+        if raw[11] & 0x3f == ATTR_LONG_NAME {
+            let ord = raw[0] & !LAST_LONG_ENTRY;
+            if raw[0] & LAST_LONG_ENTRY != 0 && ord != 0 {
+                long = Some((alloc::vec![0xffff; ord as usize * LONG_NAME_UNITS], i, raw[13], ord));
+            } else if !matches!(long, Some((_, _, checksum, expected)) if checksum == raw[13] && expected == ord + 1) {
+                long = None;
+                continue;
+            }
+            if let Some((units, _, _, expected)) = long.as_mut() {
+                let start = (ord as usize - 1) * LONG_NAME_UNITS;
+                for (j, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+                    units[start + j] = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+                }
+                *expected = ord;
+            }
+            continue;
+        }
+        let long_name = long.take();
+        if raw[11] & ATTR_VOLUME_ID != 0 {
+            continue;
+        }
+        let entry = ShortEntry(*raw);
+        let (name, first) = match long_name {
+            Some((units, first, checksum, 1)) if checksum == lfn_checksum(&entry.name()) => {
+                let len = units.iter().position(|&u| u == 0 || u == 0xffff).unwrap_or(units.len());
+                let name = char::decode_utf16(units[..len].iter().copied())
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+                (name, first)
+            }
+            _ => (entry.display_name(), i),
+        };
+        if name == "." || name == ".." {
+            continue;
+        }
+        listed.push(Listed { name, entry, index: i, first });
+    }
+    listed
+}
+
+/// The checksum of an 8.3 name kept in its long name entries
+pub fn lfn_checksum(name: &[u8; 11]) -> u8 {
+    name.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// The long name entries for `name`, in the order they are stored
+pub fn lfn_entries(name: &str, short: &[u8; 11]) -> Vec<RawEntry> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LONG_NAME_UNITS);
+    if units.len() < count * LONG_NAME_UNITS {
+        units.push(0);
+    }
+    units.resize(count * LONG_NAME_UNITS, 0xffff);
+    let checksum = lfn_checksum(short);
+    (1..=count).rev().map(|ord| {
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[0] = ord as u8 | if ord == count { LAST_LONG_ENTRY } else { 0 };
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = checksum;
+        for (j, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+            let unit = units[(ord - 1) * LONG_NAME_UNITS + j];
+            raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        raw
+    }).collect()
+}
+
+/// Check that `name` may be given to a file
+pub fn validate_name(name: &str) -> Result<(), Errno> {
+    if name.encode_utf16().count() > MAX_LONG_NAME {
+        return Err(ENAMETOOLONG);
+    }
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.chars().any(invalid) || name.ends_with(' ') || name.ends_with('.') {
+        return Err(EINVAL);
+    }
+    Ok(())
+}
+
+fn is_short_char(b: u8) -> bool {
+    b.is_ascii_uppercase() || b.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&b)
+}
+
+/// The 8.3 name storing `name` as it is, and the case bits of the reserved
+/// byte, if there is one
+pub fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || ext.contains('.') {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    let mut ntres = 0;
+    let (base_dst, ext_dst) = short.split_at_mut(8);
+    for (part, dst, lower_bit) in [(base, base_dst, NTRES_LOWER_BASE), (ext, ext_dst, NTRES_LOWER_EXT)] {
+        let lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        if lower && part.bytes().any(|b| b.is_ascii_uppercase()) {
+            return None;
+        }
+        for (d, b) in dst.iter_mut().zip(part.bytes()) {
+            *d = b.to_ascii_uppercase();
+            if !is_short_char(*d) {
+                return None;
+            }
+        }
+        if lower {
+            ntres |= lower_bit;
+        }
+    }
+    if short[0] == ENTRY_FREE {
+        short[0] = 0x05;
+    }
+    Some((short, ntres))
+}
+
+/// The `n`th 8.3 name made up for a name which needs long name entries,
+/// as in `LONGNA~1.TXT`
+pub fn numbered_short_name(name: &str, n: usize) -> [u8; 11] {
+    let name = name.trim_start_matches('.');
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => (base, ext),
+        _ => (name, ""),
+    };
+    let squeeze = |part: &str, len: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c.to_ascii_uppercase() {
+                c if c.is_ascii() && is_short_char(c as u8) => c as u8,
+                _ => b'_',
+            })
+            .take(len)
+            .collect()
+    };
+    let tail = alloc::format!("~{}", n);
+    let mut base = squeeze(base, 8 - tail.len());
+    base.extend_from_slice(tail.as_bytes());
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(&base);
+    let ext = squeeze(ext, 3);
+    short[8..8 + ext.len()].copy_from_slice(&ext);
+    short
+}
+
+/// Days since the Unix epoch of the date `year`-`month`-`day`
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The date `(year, month, day)` of a day since the Unix epoch
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+/// The Unix time of a FAT date and time
+fn unix_time(date: u16, time: u16) -> i64 {
+    let (year, month, day) = (1980 + (date >> 9) as i64, (date >> 5 & 0xf) as i64, (date & 0x1f) as i64);
+    let (hour, minute, second) = ((time >> 11) as i64, (time >> 5 & 0x3f) as i64, (time & 0x1f) as i64 * 2);
+    days_from_civil(year, month.max(1), day.max(1)) * 86400 + hour * 3600 + minute * 60 + second
+}
+
+/// The FAT date and time of a Unix time, clamped to what FAT stores
+fn fat_datetime(time: i64) -> (u16, u16) {
+    // the last time of 2107
+    let time = time.clamp(FAT_EPOCH, 4354819199);
+    let (year, month, day) = civil_from_days(time.div_euclid(86400));
+    let seconds = time.rem_euclid(86400);
+    let date = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    let time = ((seconds / 3600) << 11) | ((seconds / 60 % 60) << 5) | (seconds % 60 / 2);
+    (date, time as u16)
+}
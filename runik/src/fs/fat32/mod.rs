@@ -0,0 +1,348 @@
+//! mod fat32: the FAT32 filesystem on a block device
+//!
+//! Every change is written through to the device as it is made, so the disk
+//! is consistent whenever the app exits. The FAT and directories are read
+//! through a small cache of sectors, while file data goes to the device
+//! directly.
+//!
+//! FAT has no inode numbers: a file is known by the position of its short
+//! entry, and the same [`node::FatNode`] is handed out for it while in use.
+
+mod dir;
+mod node;
+
+use super::{ new_dev, Inode };
+use crate::drivers::block::{ BlockDevice, BLOCK_SIZE };
+use crate::sync::UPSafeCell;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::{ Arc, Weak };
+use alloc::vec::Vec;
+use linuxabi::errno::Errno::{ self, * };
+use node::FatNode;
+
+const SECTOR_SIZE: usize = BLOCK_SIZE;
+/// The number of sectors of metadata cached
+const CACHE_SECTORS: usize = 32;
+
+const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+const FAT_FREE: u32 = 0;
+const FAT_BAD: u32 = 0x0fff_fff7;
+/// Entries from this on end a chain
+const FAT_EOC: u32 = 0x0fff_fff8;
+const FAT_EOC_MARK: u32 = 0x0fff_ffff;
+
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+
+/// The position of a short entry: the first cluster of its directory and
+/// its index there
+type Pos = (u32, usize);
+
+/// The layout of a volume, from its boot sector
+struct Layout {
+    sectors_per_cluster: usize,
+    /// The first sector of the first FAT
+    fat_start: usize,
+    fat_sectors: usize,
+    num_fats: usize,
+    data_start: usize,
+    /// The number of data clusters, which are numbered from 2
+    cluster_count: usize,
+    root_cluster: u32,
+    fsinfo_sector: Option<usize>,
+}
+
+impl Layout {
+    fn parse(boot: &[u8], num_blocks: usize) -> Result<Self, &'static str> {
+        let u16_at = |i: usize| u16::from_le_bytes([boot[i], boot[i + 1]]) as usize;
+        let u32_at = |i: usize| u32::from_le_bytes(boot[i..i + 4].try_into().unwrap()) as usize;
+        if boot[510..512] != [0x55, 0xaa] {
+            return Err("no boot sector signature");
+        }
+        if u16_at(11) != SECTOR_SIZE {
+            return Err("sectors other than 512 bytes are not supported");
+        }
+        let sectors_per_cluster = boot[13] as usize;
+        let reserved = u16_at(14);
+        let num_fats = boot[16] as usize;
+        // FAT32 has no fixed root directory and only a 32-bit FAT size
+        if u16_at(17) != 0 || u16_at(22) != 0 {
+            return Err("not FAT32");
+        }
+        let total = if u16_at(19) != 0 { u16_at(19) } else { u32_at(32) };
+        let fat_sectors = u32_at(36);
+        if !sectors_per_cluster.is_power_of_two() || reserved == 0 || num_fats == 0 || fat_sectors == 0 {
+            return Err("bad BIOS parameter block");
+        }
+        let data_start = reserved + num_fats * fat_sectors;
+        if total > num_blocks || data_start >= total {
+            return Err("volume larger than the device");
+        }
+        let cluster_count = ((total - data_start) / sectors_per_cluster)
+            .min(fat_sectors * SECTOR_SIZE / 4 - 2);
+        let root_cluster = u32_at(44) as u32;
+        if root_cluster < 2 || root_cluster as usize >= cluster_count + 2 {
+            return Err("bad root cluster");
+        }
+        let fsinfo_sector = match u16_at(48) {
+            0 | 0xffff => None,
+            sector => Some(sector),
+        };
+        Ok(Self {
+            sectors_per_cluster,
+            fat_start: reserved,
+            fat_sectors,
+            num_fats,
+            data_start,
+            cluster_count,
+            root_cluster,
+            fsinfo_sector,
+        })
+    }
+}
+
+/// A write-through cache of sectors, the most recently used last
+struct SectorCache {
+    sectors: Vec<(usize, Box<[u8; SECTOR_SIZE]>)>,
+}
+
+impl SectorCache {
+    fn get(&mut self, device: &dyn BlockDevice, sector: usize) -> Result<&mut [u8; SECTOR_SIZE], Errno> {
+        match self.sectors.iter().position(|(s, _)| *s == sector) {
+            Some(i) => {
+                let cached = self.sectors.remove(i);
+                self.sectors.push(cached);
+            }
+            None => {
+                let mut data = Box::new([0u8; SECTOR_SIZE]);
+                device.read_blocks(sector, &mut data[..])?;
+                if self.sectors.len() == CACHE_SECTORS {
+                    self.sectors.remove(0);
+                }
+                self.sectors.push((sector, data));
+            }
+        }
+        Ok(&mut self.sectors.last_mut().unwrap().1)
+    }
+    /// Forget the sectors in `start..end`, which were written around the
+    /// cache
+    fn invalidate(&mut self, start: usize, end: usize) {
+        self.sectors.retain(|(s, _)| !(start..end).contains(s));
+    }
+}
+
+pub struct FatFs {
+    dev: usize,
+    device: Arc<dyn BlockDevice>,
+    layout: Layout,
+    cache: UPSafeCell<SectorCache>,
+    /// The cluster to look for free ones from
+    next_free: UPSafeCell<u32>,
+    /// The nodes in use by the positions of their entries
+    nodes: UPSafeCell<BTreeMap<Pos, Weak<FatNode>>>,
+}
+
+impl FatFs {
+    fn cluster_size(&self) -> usize {
+        self.layout.sectors_per_cluster * SECTOR_SIZE
+    }
+    fn cluster_sector(&self, cluster: u32) -> usize {
+        self.layout.data_start + (cluster as usize - 2) * self.layout.sectors_per_cluster
+    }
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        (2..self.layout.cluster_count + 2).contains(&(cluster as usize))
+    }
+    fn read_only(&self) -> bool {
+        self.device.read_only()
+    }
+
+    /// Read `buf` from `offset` of `sector`, through the cache
+    fn read_cached(&self, sector: usize, offset: usize, buf: &mut [u8]) -> Result<(), Errno> {
+        let mut cache = self.cache.exclusive_access();
+        let data = cache.get(&*self.device, sector)?;
+        buf.copy_from_slice(&data[offset..offset + buf.len()]);
+        Ok(())
+    }
+    /// Write `buf` at `offset` of `sector`, through the cache
+    fn write_cached(&self, sector: usize, offset: usize, buf: &[u8]) -> Result<(), Errno> {
+        let mut cache = self.cache.exclusive_access();
+        let data = cache.get(&*self.device, sector)?;
+        data[offset..offset + buf.len()].copy_from_slice(buf);
+        if let Err(e) = self.device.write_blocks(sector, &data[..]) {
+            cache.invalidate(sector, sector + 1);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn fat_get(&self, cluster: u32) -> Result<u32, Errno> {
+        let offset = cluster as usize * 4;
+        let mut entry = [0u8; 4];
+        self.read_cached(self.layout.fat_start + offset / SECTOR_SIZE, offset % SECTOR_SIZE, &mut entry)?;
+        Ok(u32::from_le_bytes(entry) & FAT_ENTRY_MASK)
+    }
+    /// Set the entry of `cluster` in every copy of the FAT
+    fn fat_set(&self, cluster: u32, value: u32) -> Result<(), Errno> {
+        let offset = cluster as usize * 4;
+        let (sector, offset) = (offset / SECTOR_SIZE, offset % SECTOR_SIZE);
+        let mut entry = [0u8; 4];
+        self.read_cached(self.layout.fat_start + sector, offset, &mut entry)?;
+        // the top 4 bits are reserved and kept
+        let value = u32::from_le_bytes(entry) & !FAT_ENTRY_MASK | value & FAT_ENTRY_MASK;
+        for fat in 0..self.layout.num_fats {
+            let fat_start = self.layout.fat_start + fat * self.layout.fat_sectors;
+            self.write_cached(fat_start + sector, offset, &value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// The clusters of the chain starting at `first`, none if it is 0
+    fn chain(&self, first: u32) -> Result<Vec<u32>, Errno> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster != FAT_FREE {
+            if !self.is_data_cluster(cluster) || chain.len() >= self.layout.cluster_count {
+                println!("[kernel] [warn] fat32: broken cluster chain from {}", first);
+                return Err(EIO);
+            }
+            chain.push(cluster);
+            cluster = match self.fat_get(cluster)? {
+                next if next >= FAT_EOC => FAT_FREE,
+                FAT_FREE | FAT_BAD => return Err(EIO),
+                next => next,
+            };
+        }
+        Ok(chain)
+    }
+
+    /// Allocate a zeroed cluster, appending it to the chain ending at `last`
+    /// if any
+    fn alloc_cluster(&self, last: Option<u32>) -> Result<u32, Errno> {
+        if self.read_only() {
+            return Err(EROFS);
+        }
+        let count = self.layout.cluster_count as u32;
+        let start = *self.next_free.exclusive_access();
+        let mut found = None;
+        for i in 0..count {
+            let cluster = 2 + (start - 2 + i) % count;
+            if self.fat_get(cluster)? == FAT_FREE {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(ENOSPC)?;
+        self.zero_cluster(cluster)?;
+        self.fat_set(cluster, FAT_EOC_MARK)?;
+        if let Some(last) = last {
+            self.fat_set(last, cluster)?;
+        }
+        *self.next_free.exclusive_access() = 2 + (cluster - 1) % count;
+        Ok(cluster)
+    }
+
+    /// Free the clusters of the chain starting at `first`
+    fn free_chain(&self, first: u32) -> Result<(), Errno> {
+        for cluster in self.chain(first)? {
+            self.fat_set(cluster, FAT_FREE)?;
+        }
+        Ok(())
+    }
+
+    fn zero_cluster(&self, cluster: u32) -> Result<(), Errno> {
+        let zeros = alloc::vec![0u8; self.cluster_size()];
+        let sector = self.cluster_sector(cluster);
+        self.cache.exclusive_access().invalidate(sector, sector + self.layout.sectors_per_cluster);
+        self.device.write_blocks(sector, &zeros)
+    }
+
+    /// Read `buf` from `offset` of `cluster`, around the cache
+    fn read_data(&self, cluster: u32, offset: usize, buf: &mut [u8]) -> Result<(), Errno> {
+        let mut sector = self.cluster_sector(cluster) + offset / SECTOR_SIZE;
+        let mut in_sector = offset % SECTOR_SIZE;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let whole = (buf.len() - pos) / SECTOR_SIZE * SECTOR_SIZE;
+            if in_sector == 0 && whole > 0 {
+                self.device.read_blocks(sector, &mut buf[pos..pos + whole])?;
+                pos += whole;
+                sector += whole / SECTOR_SIZE;
+            } else {
+                let mut data = [0u8; SECTOR_SIZE];
+                self.device.read_blocks(sector, &mut data)?;
+                let len = (SECTOR_SIZE - in_sector).min(buf.len() - pos);
+                buf[pos..pos + len].copy_from_slice(&data[in_sector..in_sector + len]);
+                pos += len;
+                sector += 1;
+                in_sector = 0;
+            }
+        }
+        Ok(())
+    }
+    /// Write `buf` at `offset` of `cluster`, around the cache
+    fn write_data(&self, cluster: u32, offset: usize, buf: &[u8]) -> Result<(), Errno> {
+        let first = self.cluster_sector(cluster) + offset / SECTOR_SIZE;
+        let end = self.cluster_sector(cluster) + (offset + buf.len()).div_ceil(SECTOR_SIZE);
+        self.cache.exclusive_access().invalidate(first, end);
+        let mut sector = first;
+        let mut in_sector = offset % SECTOR_SIZE;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let whole = (buf.len() - pos) / SECTOR_SIZE * SECTOR_SIZE;
+            if in_sector == 0 && whole > 0 {
+                self.device.write_blocks(sector, &buf[pos..pos + whole])?;
+                pos += whole;
+                sector += whole / SECTOR_SIZE;
+            } else {
+                let mut data = [0u8; SECTOR_SIZE];
+                self.device.read_blocks(sector, &mut data)?;
+                let len = (SECTOR_SIZE - in_sector).min(buf.len() - pos);
+                data[in_sector..in_sector + len].copy_from_slice(&buf[pos..pos + len]);
+                self.device.write_blocks(sector, &data)?;
+                pos += len;
+                sector += 1;
+                in_sector = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark the count of free clusters in the FSInfo sector unknown, as we
+    /// do not keep it
+    fn forget_free_count(&self) -> Result<(), Errno> {
+        let Some(sector) = self.layout.fsinfo_sector else {
+            return Ok(());
+        };
+        let mut signature = [0u8; 4];
+        self.read_cached(sector, 0, &mut signature)?;
+        if u32::from_le_bytes(signature) == FSINFO_LEAD_SIG {
+            self.write_cached(sector, FSINFO_FREE_COUNT, &u32::MAX.to_le_bytes())?;
+            self.write_cached(sector, FSINFO_NEXT_FREE, &u32::MAX.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// The root directory of the FAT32 volume on `device`
+pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<dyn Inode>, Errno> {
+    let mut boot = [0u8; SECTOR_SIZE];
+    device.read_blocks(0, &mut boot)?;
+    let layout = Layout::parse(&boot, device.num_blocks()).map_err(|e| {
+        println!("[kernel] [warn] fat32: {}", e);
+        EINVAL
+    })?;
+    let fs = Arc::new(FatFs {
+        dev: new_dev(),
+        device,
+        layout,
+        cache: unsafe { UPSafeCell::new(SectorCache { sectors: Vec::new() }) },
+        next_free: unsafe { UPSafeCell::new(2) },
+        nodes: unsafe { UPSafeCell::new(BTreeMap::new()) },
+    });
+    if !fs.read_only() {
+        fs.forget_free_count()?;
+    }
+    Ok(FatNode::root(&fs))
+}
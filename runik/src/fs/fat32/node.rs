@@ -0,0 +1,483 @@
+//! mod node: files and directories of a FAT32 volume
+
+use super::dir::*;
+use super::{ FatFs, Pos, FAT_EOC_MARK, SECTOR_SIZE };
+use crate::fs::{ DirEntry, Inode, InodeType };
+use crate::sync::UPSafeCell;
+use crate::time::{ self, NSEC_PER_SEC };
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use linuxabi::errno::Errno::{ self, * };
+
+/// The inode number of the root directory, which has no entry
+const ROOT_INO: usize = 1;
+/// The number of entries a directory may have
+const MAX_DIR_ENTRIES: usize = 65536;
+
+/// The time stamped on changed files, in seconds since the epoch
+fn now() -> i64 {
+    time::realtime_ns() / NSEC_PER_SEC as i64
+}
+
+/// The inode number of the file whose short entry is at `pos`
+fn ino_at((dir_cluster, index): Pos) -> usize {
+    ((dir_cluster as usize) << 16) | index
+}
+
+struct NodeState {
+    /// A copy of the short entry of the file
+    entry: ShortEntry,
+    /// Where the short entry is, none for the root and once unlinked
+    pos: Option<Pos>,
+    /// The clusters of the file, loaded on first use
+    chain: Option<Vec<u32>>,
+}
+
+pub struct FatNode {
+    ino: usize,
+    fs: Arc<FatFs>,
+    is_dir: bool,
+    is_root: bool,
+    state: UPSafeCell<NodeState>,
+}
+
+impl FatNode {
+    pub fn root(fs: &Arc<FatFs>) -> Arc<Self> {
+        let mut entry = ShortEntry::new([b' '; 11], 0, ATTR_DIRECTORY, FAT_EPOCH);
+        entry.set_first_cluster(fs.layout.root_cluster);
+        Arc::new(Self {
+            ino: ROOT_INO,
+            fs: fs.clone(),
+            is_dir: true,
+            is_root: true,
+            state: unsafe { UPSafeCell::new(NodeState { entry, pos: None, chain: None }) },
+        })
+    }
+
+    /// The node of the file whose short entry `entry` is at `pos`, the one
+    /// in use if there is
+    fn at(fs: &Arc<FatFs>, pos: Pos, entry: ShortEntry) -> Arc<Self> {
+        let mut nodes = fs.nodes.exclusive_access();
+        if let Some(node) = nodes.get(&pos).and_then(|node| node.upgrade()) {
+            return node;
+        }
+        nodes.retain(|_, node| node.strong_count() > 0);
+        let node = Arc::new(Self {
+            ino: ino_at(pos),
+            fs: fs.clone(),
+            is_dir: entry.is_dir(),
+            is_root: false,
+            state: unsafe { UPSafeCell::new(NodeState { entry, pos: Some(pos), chain: None }) },
+        });
+        nodes.insert(pos, Arc::downgrade(&node));
+        node
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.fs.cluster_size()
+    }
+    fn check_writable(&self) -> Result<(), Errno> {
+        if self.fs.read_only() { Err(EROFS) } else { Ok(()) }
+    }
+
+    fn chain<'a>(&self, state: &'a mut NodeState) -> Result<&'a mut Vec<u32>, Errno> {
+        if state.chain.is_none() {
+            state.chain = Some(self.fs.chain(state.entry.first_cluster())?);
+        }
+        Ok(state.chain.as_mut().unwrap())
+    }
+
+    /// Extend the chain of the file to at least `clusters` clusters
+    fn grow(&self, state: &mut NodeState, clusters: usize) -> Result<(), Errno> {
+        while self.chain(state)?.len() < clusters {
+            let last = self.chain(state)?.last().copied();
+            let cluster = self.fs.alloc_cluster(last)?;
+            if last.is_none() {
+                state.entry.set_first_cluster(cluster);
+            }
+            self.chain(state)?.push(cluster);
+        }
+        Ok(())
+    }
+
+    /// Write the short entry of the file back to its directory
+    fn save(&self, state: &NodeState) -> Result<(), Errno> {
+        let Some((dir_cluster, index)) = state.pos else {
+            return Ok(());
+        };
+        let offset = index * DIR_ENTRY_SIZE;
+        let mut cluster = dir_cluster;
+        for _ in 0..offset / self.cluster_size() {
+            cluster = self.fs.fat_get(cluster)?;
+        }
+        let offset = offset % self.cluster_size();
+        let sector = self.fs.cluster_sector(cluster) + offset / SECTOR_SIZE;
+        self.fs.write_cached(sector, offset % SECTOR_SIZE, &state.entry.0)
+    }
+
+    /// Read file content from `offset`, which is within the size
+    fn read_content(&self, state: &mut NodeState, offset: usize, buf: &mut [u8]) -> Result<(), Errno> {
+        let cluster_size = self.cluster_size();
+        let chain = self.chain(state)?;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let at = offset + pos;
+            let cluster = *chain.get(at / cluster_size).ok_or(EIO)?;
+            let len = (cluster_size - at % cluster_size).min(buf.len() - pos);
+            self.fs.read_data(cluster, at % cluster_size, &mut buf[pos..pos + len])?;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Write file content at `offset`, within the clusters of the file
+    fn write_content(&self, state: &mut NodeState, offset: usize, buf: &[u8]) -> Result<(), Errno> {
+        let cluster_size = self.cluster_size();
+        let chain = self.chain(state)?;
+        let mut pos = 0;
+        while pos < buf.len() {
+            let at = offset + pos;
+            let cluster = *chain.get(at / cluster_size).ok_or(EIO)?;
+            let len = (cluster_size - at % cluster_size).min(buf.len() - pos);
+            self.fs.write_data(cluster, at % cluster_size, &buf[pos..pos + len])?;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Zero the last cluster of the file from its end `size` on, up to `end`,
+    /// before the file grows; clusters after it are zeroed when allocated
+    fn zero_tail(&self, state: &mut NodeState, size: usize, end: usize) -> Result<(), Errno> {
+        let cluster_size = self.cluster_size();
+        if size % cluster_size == 0 || size / cluster_size >= self.chain(state)?.len() {
+            return Ok(());
+        }
+        let end = end.min(size.next_multiple_of(cluster_size));
+        self.write_content(state, size, &alloc::vec![0u8; end - size])
+    }
+
+    /// The raw entries of the directory
+    fn entries(&self, state: &mut NodeState) -> Result<Vec<RawEntry>, Errno> {
+        let sectors = self.fs.layout.sectors_per_cluster;
+        let mut entries = Vec::new();
+        for &cluster in self.chain(state)?.iter() {
+            for i in 0..sectors {
+                let mut data = [0u8; SECTOR_SIZE];
+                self.fs.read_cached(self.fs.cluster_sector(cluster) + i, 0, &mut data)?;
+                entries.extend(data.chunks_exact(DIR_ENTRY_SIZE).map(|raw| RawEntry::try_from(raw).unwrap()));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Write `bytes` at `offset` of the entry `index` of the directory
+    fn write_entry(&self, state: &mut NodeState, index: usize, offset: usize, bytes: &[u8]) -> Result<(), Errno> {
+        let cluster_size = self.cluster_size();
+        let at = index * DIR_ENTRY_SIZE + offset;
+        let cluster = *self.chain(state)?.get(at / cluster_size).ok_or(EIO)?;
+        let sector = self.fs.cluster_sector(cluster) + at % cluster_size / SECTOR_SIZE;
+        self.fs.write_cached(sector, at % SECTOR_SIZE, bytes)
+    }
+
+    /// The first of `count` consecutive free entries of the directory,
+    /// which grows if there are not enough
+    fn alloc_entries(&self, state: &mut NodeState, entries: &[RawEntry], count: usize) -> Result<usize, Errno> {
+        let mut start = 0;
+        let mut ended = false;
+        for (i, raw) in entries.iter().enumerate() {
+            ended |= raw[0] == ENTRY_END;
+            if !ended && raw[0] != ENTRY_FREE {
+                start = i + 1;
+            } else if i + 1 - start == count {
+                return Ok(start);
+            }
+        }
+        if start + count > MAX_DIR_ENTRIES {
+            return Err(ENOSPC);
+        }
+        let per_cluster = self.cluster_size() / DIR_ENTRY_SIZE;
+        self.grow(state, (start + count).div_ceil(per_cluster))?;
+        Ok(start)
+    }
+
+    /// The file named `name` in the directory, matched without case as FAT
+    /// does
+    fn find(&self, state: &mut NodeState, name: &str) -> Result<Option<Listed>, Errno> {
+        Ok(parse(&self.entries(state)?).into_iter().find(|listed| {
+            listed.name.eq_ignore_ascii_case(name) || listed.entry.display_name().eq_ignore_ascii_case(name)
+        }))
+    }
+
+    /// List `entry` in the directory as `name`, which is not there yet,
+    /// returning the position of its short entry and the entry as written
+    fn insert(&self, state: &mut NodeState, name: &str, mut entry: ShortEntry) -> Result<(Pos, ShortEntry), Errno> {
+        validate_name(name)?;
+        let entries = self.entries(state)?;
+        let listed = parse(&entries);
+        let taken = |short: &[u8; 11]| listed.iter().any(|listed| &listed.entry.name() == short);
+        let (short, ntres, long) = match exact_short_name(name) {
+            Some((short, ntres)) if !taken(&short) => (short, ntres, false),
+            _ => {
+                let short = (1..1_000_000)
+                    .map(|n| numbered_short_name(name, n))
+                    .find(|short| !taken(short))
+                    .ok_or(EEXIST)?;
+                (short, 0, true)
+            }
+        };
+        entry.set_name(short, ntres);
+        let mut raws = if long { lfn_entries(name, &short) } else { Vec::new() };
+        raws.push(entry.0);
+        let start = self.alloc_entries(state, &entries, raws.len())?;
+        for (i, raw) in raws.iter().enumerate() {
+            self.write_entry(state, start + i, 0, raw)?;
+        }
+        Ok(((state.entry.first_cluster(), start + raws.len() - 1), entry))
+    }
+
+    /// Remove the entries of `listed` from the directory, and the file with
+    /// them unless it is in use or was linked elsewhere
+    fn remove(&self, state: &mut NodeState, listed: &Listed) -> Result<(), Errno> {
+        for index in listed.first..=listed.index {
+            self.write_entry(state, index, 0, &[ENTRY_FREE])?;
+        }
+        let pos = (state.entry.first_cluster(), listed.index);
+        let node = self.fs.nodes.exclusive_access().remove(&pos);
+        match node.and_then(|node| node.upgrade()) {
+            // freed when dropped
+            Some(node) => {
+                let mut node_state = node.state.exclusive_access();
+                if node_state.pos == Some(pos) {
+                    node_state.pos = None;
+                }
+            }
+            None if listed.entry.first_cluster() != 0 => self.fs.free_chain(listed.entry.first_cluster())?,
+            None => {},
+        }
+        Ok(())
+    }
+
+    /// Point the `..` entry of this directory at `parent`
+    fn set_parent(&self, state: &mut NodeState, parent: &FatNode, parent_cluster: u32) -> Result<(), Errno> {
+        let mut dotdot = ShortEntry([0; DIR_ENTRY_SIZE]);
+        dotdot.set_first_cluster(if parent.is_root { 0 } else { parent_cluster });
+        // the high and low words of the first cluster
+        self.write_entry(state, 1, 20, &dotdot.0[20..22])?;
+        self.write_entry(state, 1, 26, &dotdot.0[26..28])
+    }
+}
+
+impl Inode for FatNode {
+    fn kind(&self) -> InodeType {
+        if self.is_dir { InodeType::Directory } else { InodeType::Regular }
+    }
+    fn ino(&self) -> usize {
+        self.ino
+    }
+    fn dev(&self) -> usize {
+        self.fs.dev
+    }
+    fn mode(&self) -> u32 {
+        let read_only = self.state.exclusive_access().entry.attr() & ATTR_READ_ONLY != 0;
+        match (self.is_dir, read_only) {
+            (true, _) => 0o755,
+            (false, false) => 0o644,
+            (false, true) => 0o444,
+        }
+    }
+    fn mtime(&self) -> i64 {
+        self.state.exclusive_access().entry.mtime()
+    }
+    fn size(&self) -> usize {
+        let mut state = self.state.exclusive_access();
+        if self.is_dir {
+            self.chain(&mut state).map_or(0, |chain| chain.len() * self.cluster_size())
+        } else {
+            state.entry.size() as usize
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.is_dir {
+            return Err(EISDIR);
+        }
+        let mut state = self.state.exclusive_access();
+        let size = state.entry.size() as usize;
+        let len = buf.len().min(size.saturating_sub(offset));
+        self.read_content(&mut state, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        if self.is_dir {
+            return Err(EISDIR);
+        }
+        self.check_writable()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.checked_add(buf.len()).filter(|&end| end <= u32::MAX as usize).ok_or(EFBIG)?;
+        let mut state = self.state.exclusive_access();
+        let size = state.entry.size() as usize;
+        if offset > size {
+            self.zero_tail(&mut state, size, offset)?;
+        }
+        // a short write if the disk fills up after some clusters
+        let grown = self.grow(&mut state, end.div_ceil(self.cluster_size()));
+        let end = end.min(self.chain(&mut state)?.len() * self.cluster_size());
+        if end <= offset {
+            return Err(grown.err().unwrap_or(ENOSPC));
+        }
+        self.write_content(&mut state, offset, &buf[..end - offset])?;
+        state.entry.set_size(size.max(end) as u32);
+        state.entry.touch(now());
+        self.save(&state)?;
+        Ok(end - offset)
+    }
+    fn truncate(&self, len: usize) -> Result<(), Errno> {
+        if self.is_dir {
+            return Err(EISDIR);
+        }
+        self.check_writable()?;
+        if len > u32::MAX as usize {
+            return Err(EFBIG);
+        }
+        let cluster_size = self.cluster_size();
+        let mut state = self.state.exclusive_access();
+        let size = state.entry.size() as usize;
+        if len < size {
+            let keep = len.div_ceil(cluster_size);
+            let chain = self.chain(&mut state)?.clone();
+            if keep < chain.len() {
+                if keep == 0 {
+                    state.entry.set_first_cluster(0);
+                } else {
+                    self.fs.fat_set(chain[keep - 1], FAT_EOC_MARK)?;
+                }
+                self.chain(&mut state)?.truncate(keep);
+                state.entry.set_size(len as u32);
+                self.save(&state)?;
+                self.fs.free_chain(chain[keep])?;
+            }
+        } else if len > size {
+            self.zero_tail(&mut state, size, len)?;
+            self.grow(&mut state, len.div_ceil(cluster_size))?;
+        }
+        state.entry.set_size(len as u32);
+        state.entry.touch(now());
+        self.save(&state)
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        if !self.is_dir {
+            return Err(ENOTDIR);
+        }
+        let mut state = self.state.exclusive_access();
+        let listed = self.find(&mut state, name)?.ok_or(ENOENT)?;
+        let pos = (state.entry.first_cluster(), listed.index);
+        Ok(FatNode::at(&self.fs, pos, listed.entry))
+    }
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> Result<Arc<dyn Inode>, Errno> {
+        if !self.is_dir {
+            return Err(ENOTDIR);
+        }
+        self.check_writable()?;
+        let mut state = self.state.exclusive_access();
+        if self.find(&mut state, name)?.is_some() {
+            return Err(EEXIST);
+        }
+        let read_only = if mode & 0o222 == 0 { ATTR_READ_ONLY } else { 0 };
+        let (pos, entry) = match kind {
+            InodeType::Regular => {
+                let entry = ShortEntry::new([b' '; 11], 0, ATTR_ARCHIVE | read_only, now());
+                self.insert(&mut state, name, entry)?
+            }
+            InodeType::Directory => {
+                let cluster = self.fs.alloc_cluster(None)?;
+                let mut entry = ShortEntry::new([b' '; 11], 0, ATTR_DIRECTORY, now());
+                entry.set_first_cluster(cluster);
+                let mut dot = entry;
+                dot.set_name(*b".          ", 0);
+                let mut dotdot = entry;
+                dotdot.set_name(*b"..         ", 0);
+                dotdot.set_first_cluster(if self.is_root { 0 } else { state.entry.first_cluster() });
+                let sector = self.fs.cluster_sector(cluster);
+                let inserted = self.fs.write_cached(sector, 0, &dot.0)
+                    .and_then(|_| self.fs.write_cached(sector, DIR_ENTRY_SIZE, &dotdot.0))
+                    .and_then(|_| self.insert(&mut state, name, entry));
+                match inserted {
+                    Ok(inserted) => inserted,
+                    Err(e) => {
+                        self.fs.free_chain(cluster)?;
+                        return Err(e);
+                    }
+                }
+            }
+            _ => return Err(EPERM),
+        };
+        Ok(FatNode::at(&self.fs, pos, entry))
+    }
+    fn link(&self, name: &str, inode: Arc<dyn Inode>) -> Result<(), Errno> {
+        if !self.is_dir {
+            return Err(ENOTDIR);
+        }
+        self.check_writable()?;
+        if inode.dev() != self.fs.dev {
+            return Err(EXDEV);
+        }
+        // FAT has no hard links: this moves a node, and only nodes of ours
+        // in use are known
+        let node = self.fs.nodes.exclusive_access().values()
+            .filter_map(|node| node.upgrade())
+            .find(|node| Arc::as_ptr(node) as *const u8 == Arc::as_ptr(&inode) as *const u8)
+            .ok_or(EPERM)?;
+        let mut state = self.state.exclusive_access();
+        if let Some(listed) = self.find(&mut state, name)? {
+            self.remove(&mut state, &listed)?;
+        }
+        let entry = node.state.exclusive_access().entry;
+        let (pos, entry) = self.insert(&mut state, name, entry)?;
+        let mut node_state = node.state.exclusive_access();
+        node_state.entry = entry;
+        node_state.pos = Some(pos);
+        drop(node_state);
+        self.fs.nodes.exclusive_access().insert(pos, Arc::downgrade(&node));
+        if node.is_dir {
+            let parent_cluster = state.entry.first_cluster();
+            drop(state);
+            node.set_parent(&mut node.state.exclusive_access(), self, parent_cluster)?;
+        }
+        Ok(())
+    }
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        if !self.is_dir {
+            return Err(ENOTDIR);
+        }
+        self.check_writable()?;
+        let mut state = self.state.exclusive_access();
+        let listed = self.find(&mut state, name)?.ok_or(ENOENT)?;
+        self.remove(&mut state, &listed)
+    }
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        if !self.is_dir {
+            return Err(ENOTDIR);
+        }
+        let mut state = self.state.exclusive_access();
+        let dir_cluster = state.entry.first_cluster();
+        Ok(parse(&self.entries(&mut state)?).into_iter().map(|listed| DirEntry {
+            ino: ino_at((dir_cluster, listed.index)),
+            kind: if listed.entry.is_dir() { InodeType::Directory } else { InodeType::Regular },
+            name: listed.name,
+        }).collect())
+    }
+}
+
+impl Drop for FatNode {
+    fn drop(&mut self) {
+        let state = self.state.exclusive_access();
+        let first = state.entry.first_cluster();
+        if self.is_root || state.pos.is_some() || first == 0 || self.fs.read_only() {
+            return;
+        }
+        if let Err(e) = self.fs.free_chain(first) {
+            println!("[kernel] [warn] fat32: failed to free clusters of an unlinked file: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! mod fd_table: the file descriptors of the application
+
+use super::{ OpenFile, OpenFlags };
+use super::devfs::CONSOLE;
+use crate::config::FD_LIMIT;
+use crate::sync::UPSafeCell;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use linuxabi::errno::Errno::{ self, * };
+
+struct FdEntry {
+    file: Arc<OpenFile>,
+    cloexec: bool,
+}
+
+/// The table from file descriptors to open file descriptions
+pub struct FdTable {
+    fds: Vec<Option<FdEntry>>,
+}
+
+impl FdTable {
+    /// A table with stdin, stdout and stderr open on the console, sharing
+    /// one description for reading and writing as if dup'ed by init
+    fn new() -> Self {
+        let console = Arc::new(OpenFile::new(
+            CONSOLE.device().unwrap(), Some(CONSOLE.clone()), "/dev/console".to_string(), OpenFlags::RDWR,
+        ));
+        let stdio = || Some(FdEntry { file: console.clone(), cloexec: false });
+        Self {
+            fds: alloc::vec![stdio(), stdio(), stdio()],
+        }
+    }
+    fn entry(&self, fd: i32) -> Result<&FdEntry, Errno> {
+        usize::try_from(fd).ok()
+            .and_then(|fd| self.fds.get(fd))
+            .and_then(|entry| entry.as_ref())
+            .ok_or(EBADF)
+    }
+    pub fn get(&self, fd: i32) -> Result<Arc<OpenFile>, Errno> {
+        self.entry(fd).map(|entry| entry.file.clone())
+    }
+    /// Install `file` at the lowest free descriptor not less than `min`
+    pub fn insert(&mut self, file: Arc<OpenFile>, cloexec: bool, min: usize) -> Result<i32, Errno> {
+        if min >= FD_LIMIT {
+            return Err(EINVAL);
+        }
+        let fd = (min..FD_LIMIT)
+            .find(|&fd| self.fds.get(fd).is_none_or(|entry| entry.is_none()))
+            .ok_or(EMFILE)?;
+        if fd >= self.fds.len() {
+            self.fds.resize_with(fd + 1, || None);
+        }
+        self.fds[fd] = Some(FdEntry { file, cloexec });
+        Ok(fd as i32)
+    }
+    /// Install `file` at `fd`, returning the file it replaces, which the
+    /// caller should drop after releasing the table
+    pub fn insert_at(&mut self, fd: i32, file: Arc<OpenFile>, cloexec: bool) -> Result<Option<Arc<OpenFile>>, Errno> {
+        let fd = usize::try_from(fd).ok().filter(|&fd| fd < FD_LIMIT).ok_or(EBADF)?;
+        if fd >= self.fds.len() {
+            self.fds.resize_with(fd + 1, || None);
+        }
+        let old = self.fds[fd].replace(FdEntry { file, cloexec });
+        Ok(old.map(|entry| entry.file))
+    }
+    pub fn remove(&mut self, fd: i32) -> Result<Arc<OpenFile>, Errno> {
+        self.entry(fd)?;
+        let entry = self.fds[fd as usize].take().unwrap();
+        while let Some(None) = self.fds.last() {
+            self.fds.pop();
+        }
+        Ok(entry.file)
+    }
+    pub fn cloexec(&self, fd: i32) -> Result<bool, Errno> {
+        self.entry(fd).map(|entry| entry.cloexec)
+    }
+    pub fn set_cloexec(&mut self, fd: i32, cloexec: bool) -> Result<(), Errno> {
+        self.entry(fd)?;
+        self.fds[fd as usize].as_mut().unwrap().cloexec = cloexec;
+        Ok(())
+    }
+}
+
+lazy_static! {
+    /// The file descriptors of the application
+    pub static ref FD_TABLE: UPSafeCell<FdTable> = unsafe { UPSafeCell::new(FdTable::new()) };
+}
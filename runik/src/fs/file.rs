@@ -0,0 +1,222 @@
+//! mod file: open files and their descriptions
+
+use super::{ Inode, InodeType };
+use crate::net::Socket;
+use crate::sync::UPSafeCell;
+use alloc::string::String;
+use alloc::sync::Arc;
+use bitflags::*;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::fcntl::*;
+use linuxabi::stat::Stat;
+
+bitflags! {
+    /// The flags of open(2); `O_RDONLY` is the absence of the access mode bits
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub struct OpenFlags: u32 {
+        const WRONLY = O_WRONLY;
+        const RDWR = O_RDWR;
+        const CREAT = O_CREAT;
+        const EXCL = O_EXCL;
+        const NOCTTY = O_NOCTTY;
+        const TRUNC = O_TRUNC;
+        const APPEND = O_APPEND;
+        const NONBLOCK = O_NONBLOCK;
+        const DSYNC = O_DSYNC;
+        const DIRECT = O_DIRECT;
+        const LARGEFILE = O_LARGEFILE;
+        const DIRECTORY = O_DIRECTORY;
+        const NOFOLLOW = O_NOFOLLOW;
+        const NOATIME = O_NOATIME;
+        const CLOEXEC = O_CLOEXEC;
+        const PATH = O_PATH;
+    }
+}
+
+impl OpenFlags {
+    /// The flags only used when opening, which F_GETFL does not report
+    const CREATION: Self = Self::CREAT.union(Self::EXCL).union(Self::NOCTTY)
+        .union(Self::TRUNC).union(Self::CLOEXEC);
+    /// The flags F_SETFL may change
+    const SETTABLE: Self = Self::APPEND.union(Self::NONBLOCK).union(Self::DIRECT)
+        .union(Self::NOATIME);
+
+    pub fn readable(&self) -> bool {
+        !self.intersects(Self::WRONLY) && !self.contains(Self::PATH)
+    }
+    pub fn writable(&self) -> bool {
+        self.intersects(Self::WRONLY | Self::RDWR) && !self.contains(Self::PATH)
+    }
+}
+
+/// The position to seek to, as in the `whence` of lseek(2)
+#[derive(Copy, Clone, Debug)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
+/// Something that can be read and written through a file descriptor
+pub trait File: Send + Sync {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno>;
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno>;
+    /// Move the offset and return the new one, for files that have one
+    fn seek(&self, _pos: SeekFrom) -> Result<usize, Errno> {
+        Err(ESPIPE)
+    }
+    /// Read at `offset` without moving the offset, for files that have one
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(ESPIPE)
+    }
+    /// Write at `offset` without moving the offset, for files that have one
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(ESPIPE)
+    }
+    /// Take note of O_NONBLOCK, for files whose reads or writes may block
+    fn set_nonblocking(&self, _nonblocking: bool) {}
+    /// Carry out the device-specific request `cmd` of ioctl(2)
+    fn ioctl(&self, _cmd: u32, _arg: usize) -> Result<usize, Errno> {
+        Err(ENOTTY)
+    }
+    fn as_socket(&self) -> Option<&Socket> {
+        None
+    }
+}
+
+/// The file of a regular file or directory inode, accessed at its own offset
+pub struct InodeFile {
+    inode: Arc<dyn Inode>,
+    offset: UPSafeCell<usize>,
+}
+
+impl InodeFile {
+    pub fn new(inode: Arc<dyn Inode>) -> Self {
+        Self { inode, offset: unsafe { UPSafeCell::new(0) } }
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.inode.kind() == InodeType::Directory {
+            return Err(EISDIR);
+        }
+        let mut offset = self.offset.exclusive_access();
+        let n = self.inode.read_at(*offset, buf)?;
+        *offset += n;
+        Ok(n)
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.exclusive_access();
+        let n = self.inode.write_at(*offset, buf)?;
+        *offset += n;
+        Ok(n)
+    }
+    fn seek(&self, pos: SeekFrom) -> Result<usize, Errno> {
+        let mut offset = self.offset.exclusive_access();
+        let new = match pos {
+            SeekFrom::Start(off) => Some(off),
+            SeekFrom::Current(off) => offset.checked_add_signed(off),
+            SeekFrom::End(off) => self.inode.size().checked_add_signed(off),
+        };
+        *offset = new.filter(|&new| new <= isize::MAX as usize).ok_or(EINVAL)?;
+        Ok(*offset)
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.inode.kind() == InodeType::Directory {
+            return Err(EISDIR);
+        }
+        self.inode.read_at(offset, buf)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        self.inode.write_at(offset, buf)
+    }
+}
+
+/// An open file description, shared by the file descriptors duplicated from
+/// the one open returned
+pub struct OpenFile {
+    file: Arc<dyn File>,
+    /// The inode it was opened from, which a device inode is for its file
+    inode: Option<Arc<dyn Inode>>,
+    /// The absolute path it was opened at, which `*at()` syscalls resolve
+    /// relative paths against
+    path: String,
+    flags: UPSafeCell<OpenFlags>,
+}
+
+impl OpenFile {
+    pub fn new(file: Arc<dyn File>, inode: Option<Arc<dyn Inode>>, path: String, flags: OpenFlags) -> Self {
+        file.set_nonblocking(flags.contains(OpenFlags::NONBLOCK));
+        Self {
+            file,
+            inode,
+            path,
+            flags: unsafe { UPSafeCell::new(flags - OpenFlags::CREATION) },
+        }
+    }
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn flags(&self) -> OpenFlags {
+        *self.flags.exclusive_access()
+    }
+    /// Change the flags F_SETFL may change, ignoring the others
+    pub fn set_flags(&self, flags: OpenFlags) {
+        let mut cur = self.flags.exclusive_access();
+        *cur = (*cur - OpenFlags::SETTABLE) | (flags & OpenFlags::SETTABLE);
+        self.file.set_nonblocking(cur.contains(OpenFlags::NONBLOCK));
+    }
+    pub fn inode(&self) -> Option<Arc<dyn Inode>> {
+        self.inode.clone()
+    }
+    pub fn socket(&self) -> Result<&Socket, Errno> {
+        self.file.as_socket().ok_or(ENOTSOCK)
+    }
+    pub fn stat(&self) -> Result<Stat, Errno> {
+        self.inode.as_ref().map(|inode| inode.stat()).ok_or(EINVAL)
+    }
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if !self.flags().readable() {
+            return Err(EBADF);
+        }
+        self.file.read(buf)
+    }
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let flags = self.flags();
+        if !flags.writable() {
+            return Err(EBADF);
+        }
+        if flags.contains(OpenFlags::APPEND) {
+            match self.file.seek(SeekFrom::End(0)) {
+                Ok(_) | Err(ESPIPE) => {},
+                Err(e) => return Err(e),
+            }
+        }
+        self.file.write(buf)
+    }
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        if !self.flags().readable() {
+            return Err(EBADF);
+        }
+        self.file.read_at(offset, buf)
+    }
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        if !self.flags().writable() {
+            return Err(EBADF);
+        }
+        self.file.write_at(offset, buf)
+    }
+    pub fn seek(&self, pos: SeekFrom) -> Result<usize, Errno> {
+        if self.flags().contains(OpenFlags::PATH) {
+            return Err(EBADF);
+        }
+        self.file.seek(pos)
+    }
+    pub fn ioctl(&self, cmd: u32, arg: usize) -> Result<usize, Errno> {
+        if self.flags().contains(OpenFlags::PATH) {
+            return Err(EBADF);
+        }
+        self.file.ioctl(cmd, arg)
+    }
+}
@@ -0,0 +1,244 @@
+//! mod initramfs: the read-only filesystem of the archive built into the kernel
+//!
+//! `build.rs` packs the directory given by `INITRAMFS` into a cpio archive in
+//! the "newc" format and links it in `link_app.S`. The files are served right
+//! out of the archive, without copying.
+
+use super::{ new_ino, DirEntry, Inode, InodeType };
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::string::{ String, ToString };
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::stat::*;
+
+/// The magic number starting each header of a newc archive
+const NEWC_MAGIC: &[u8] = b"070701";
+/// The length of a newc header: the magic number and 13 fields of 8 hex digits
+const NEWC_HEADER_LEN: usize = 110;
+/// The name of the entry ending the archive
+const NEWC_TRAILER: &str = "TRAILER!!!";
+
+/// A regular file or symbolic link, whose content is in the archive
+struct RamFile {
+    ino: usize,
+    kind: InodeType,
+    mode: u32,
+    mtime: i64,
+    data: &'static [u8],
+}
+
+impl Inode for RamFile {
+    fn kind(&self) -> InodeType {
+        self.kind
+    }
+    fn ino(&self) -> usize {
+        self.ino
+    }
+    fn mode(&self) -> u32 {
+        self.mode
+    }
+    fn mtime(&self) -> i64 {
+        self.mtime
+    }
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.kind != InodeType::Regular {
+            return Err(EINVAL);
+        }
+        let start = offset.min(self.data.len());
+        let len = buf.len().min(self.data.len() - start);
+        buf[..len].copy_from_slice(&self.data[start..start + len]);
+        Ok(len)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(EROFS)
+    }
+    fn truncate(&self, _len: usize) -> Result<(), Errno> {
+        Err(EROFS)
+    }
+    fn read_link(&self) -> Result<String, Errno> {
+        if self.kind != InodeType::Symlink {
+            return Err(EINVAL);
+        }
+        core::str::from_utf8(self.data).map(|target| target.to_string()).map_err(|_| EINVAL)
+    }
+}
+
+/// A directory, whose entries are only added while unpacking the archive
+struct RamDir {
+    ino: usize,
+    mode: UPSafeCell<u32>,
+    mtime: UPSafeCell<i64>,
+    entries: UPSafeCell<BTreeMap<String, Arc<dyn Inode>>>,
+}
+
+impl RamDir {
+    fn new(mode: u32, mtime: i64) -> Arc<Self> {
+        Arc::new(Self {
+            ino: new_ino(),
+            mode: unsafe { UPSafeCell::new(mode) },
+            mtime: unsafe { UPSafeCell::new(mtime) },
+            entries: unsafe { UPSafeCell::new(BTreeMap::new()) },
+        })
+    }
+}
+
+impl Inode for RamDir {
+    fn kind(&self) -> InodeType {
+        InodeType::Directory
+    }
+    fn ino(&self) -> usize {
+        self.ino
+    }
+    fn mode(&self) -> u32 {
+        *self.mode.exclusive_access()
+    }
+    fn mtime(&self) -> i64 {
+        *self.mtime.exclusive_access()
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        self.entries.exclusive_access().get(name).cloned().ok_or(ENOENT)
+    }
+    fn create(&self, _name: &str, _kind: InodeType, _mode: u32) -> Result<Arc<dyn Inode>, Errno> {
+        Err(EROFS)
+    }
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(EROFS)
+    }
+    fn link(&self, _name: &str, _inode: Arc<dyn Inode>) -> Result<(), Errno> {
+        Err(EROFS)
+    }
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(EROFS)
+    }
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        Ok(self.entries.exclusive_access().iter().map(|(name, inode)| DirEntry {
+            name: name.clone(),
+            ino: inode.ino(),
+            kind: inode.kind(),
+        }).collect())
+    }
+}
+
+/// A header of a newc archive, of which the fields we use
+struct NewcHeader {
+    mode: u32,
+    mtime: u32,
+    filesize: usize,
+    namesize: usize,
+}
+
+impl NewcHeader {
+    fn parse(header: &[u8]) -> Option<Self> {
+        if header.len() < NEWC_HEADER_LEN || &header[..6] != NEWC_MAGIC {
+            return None;
+        }
+        let field = |i: usize| {
+            let hex = core::str::from_utf8(&header[6 + i * 8..6 + (i + 1) * 8]).ok()?;
+            u32::from_str_radix(hex, 16).ok()
+        };
+        Some(Self {
+            mode: field(1)?,
+            mtime: field(5)?,
+            filesize: field(6)? as usize,
+            namesize: field(11)? as usize,
+        })
+    }
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// The directory at `path` of the tree being unpacked, created along with
+/// its missing parents if it does not exist
+fn dir_at(dirs: &mut BTreeMap<String, Arc<RamDir>>, path: &str) -> Result<Arc<RamDir>, &'static str> {
+    if let Some(dir) = dirs.get(path) {
+        return Ok(dir.clone());
+    }
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let parent = dir_at(dirs, parent)?;
+    let mut entries = parent.entries.exclusive_access();
+    if entries.contains_key(name) {
+        return Err("path through a non-directory");
+    }
+    let dir = RamDir::new(0o755, 0);
+    entries.insert(name.to_string(), dir.clone());
+    dirs.insert(path.to_string(), dir.clone());
+    Ok(dir)
+}
+
+/// Unpack the entries of `archive` into `dirs`, the directories by their
+/// paths, where the root is ""
+fn unpack(archive: &'static [u8], dirs: &mut BTreeMap<String, Arc<RamDir>>) -> Result<(), &'static str> {
+    let mut pos = 0;
+    while pos < archive.len() {
+        let header = NewcHeader::parse(&archive[pos..]).ok_or("bad header")?;
+        let name_start = pos + NEWC_HEADER_LEN;
+        let data_start = align4(name_start + header.namesize);
+        let data_end = data_start + header.filesize;
+        if header.namesize == 0 || data_end > archive.len() {
+            return Err("truncated entry");
+        }
+        let name = core::str::from_utf8(&archive[name_start..name_start + header.namesize - 1])
+            .map_err(|_| "bad name")?;
+        if name == NEWC_TRAILER {
+            break;
+        }
+        let data = &archive[data_start..data_end];
+        pos = align4(data_end);
+
+        let path = name.split('/')
+            .filter(|part| !part.is_empty() && *part != ".")
+            .collect::<Vec<_>>()
+            .join("/");
+        let mode = header.mode & 0o7777;
+        let mtime = header.mtime as i64;
+        let kind = match header.mode & S_IFMT {
+            S_IFDIR => {
+                let dir = dir_at(dirs, &path)?;
+                *dir.mode.exclusive_access() = mode;
+                *dir.mtime.exclusive_access() = mtime;
+                continue;
+            }
+            S_IFREG => InodeType::Regular,
+            S_IFLNK => InodeType::Symlink,
+            _ => {
+                println!("[kernel] [warn] initramfs: skipping special file {}", name);
+                continue;
+            }
+        };
+        let (parent, file_name) = path.rsplit_once('/').unwrap_or(("", &path));
+        if file_name.is_empty() {
+            return Err("file at the root");
+        }
+        let file = Arc::new(RamFile { ino: new_ino(), kind, mode, mtime, data });
+        dir_at(dirs, parent)?.entries.exclusive_access().insert(file_name.to_string(), file);
+    }
+    Ok(())
+}
+
+/// The root directory of the archive built into the kernel
+pub fn new() -> Arc<dyn Inode> {
+    extern "C" {
+        fn initramfs_start_addr();
+        fn initramfs_end_addr();
+    }
+    let archive: &'static [u8] = unsafe {
+        // Read u64 addresses since we stored them in .quad values
+        let start = (initramfs_start_addr as u64 as *const u64).read_volatile() as usize;
+        let end = (initramfs_end_addr as u64 as *const u64).read_volatile() as usize;
+        core::slice::from_raw_parts(start as *const u8, end - start)
+    };
+    let root = RamDir::new(0o755, 0);
+    let mut dirs = BTreeMap::new();
+    dirs.insert(String::new(), root.clone());
+    if let Err(e) = unpack(archive, &mut dirs) {
+        println!("[kernel] [warn] initramfs: {}, ignoring the rest of the archive", e);
+    }
+    root
+}
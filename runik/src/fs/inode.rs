@@ -0,0 +1,210 @@
+//! mod inode: the nodes of filesystem trees
+
+use super::File;
+use alloc::collections::BTreeMap;
+use alloc::string::{ String, ToString };
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use linuxabi::dirent::*;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::stat::*;
+
+/// A fresh inode number
+pub fn new_ino() -> usize {
+    static NEXT_INO: AtomicUsize = AtomicUsize::new(1);
+    NEXT_INO.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A fresh device number for a filesystem, where 0 is shared by the pseudo
+/// filesystems
+pub fn new_dev() -> usize {
+    static NEXT_DEV: AtomicUsize = AtomicUsize::new(1);
+    NEXT_DEV.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The type of an inode, as in the `S_IFMT` bits of its mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InodeType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+impl InodeType {
+    /// The `S_IFMT` bits of the mode
+    pub fn mode_bits(self) -> u32 {
+        match self {
+            Self::Regular => S_IFREG,
+            Self::Directory => S_IFDIR,
+            Self::Symlink => S_IFLNK,
+            Self::CharDevice => S_IFCHR,
+            Self::BlockDevice => S_IFBLK,
+            Self::Fifo => S_IFIFO,
+            Self::Socket => S_IFSOCK,
+        }
+    }
+    /// The `d_type` of getdents64
+    pub fn dirent_type(self) -> u8 {
+        match self {
+            Self::Regular => DT_REG,
+            Self::Directory => DT_DIR,
+            Self::Symlink => DT_LNK,
+            Self::CharDevice => DT_CHR,
+            Self::BlockDevice => DT_BLK,
+            Self::Fifo => DT_FIFO,
+            Self::Socket => DT_SOCK,
+        }
+    }
+}
+
+/// An entry of a directory
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: usize,
+    pub kind: InodeType,
+}
+
+/// A node of a filesystem tree, which filesystems implement for their files
+/// and directories. Operations that make no sense for the type of the inode
+/// keep their default implementations.
+pub trait Inode: Send + Sync {
+    fn kind(&self) -> InodeType;
+    /// The inode number, unique among the inodes of all filesystems
+    fn ino(&self) -> usize;
+    /// The device number of the filesystem, which entries can only be moved
+    /// within
+    fn dev(&self) -> usize {
+        0
+    }
+    /// The permission bits of the mode
+    fn mode(&self) -> u32 {
+        if self.kind() == InodeType::Directory { 0o755 } else { 0o644 }
+    }
+    /// The time of the last modification, in seconds since the epoch
+    fn mtime(&self) -> i64 {
+        0
+    }
+    fn stat(&self) -> Stat {
+        let size = self.size();
+        Stat {
+            st_dev: self.dev() as u64,
+            st_ino: self.ino() as u64,
+            st_mode: self.kind().mode_bits() | self.mode(),
+            st_nlink: 1,
+            st_size: size as i64,
+            st_blksize: 4096,
+            st_blocks: size.div_ceil(512) as i64,
+            st_atime: self.mtime(),
+            st_mtime: self.mtime(),
+            st_ctime: self.mtime(),
+            ..Default::default()
+        }
+    }
+    /// The length of a regular file in bytes
+    fn size(&self) -> usize {
+        0
+    }
+    /// Read from `offset` into `buf`, returning the number of bytes read,
+    /// which is 0 at the end of the file
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(EINVAL)
+    }
+    /// Write `buf` at `offset`, extending the file as needed
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(EINVAL)
+    }
+    /// Change the length of a regular file, filling with zeros if it grows
+    fn truncate(&self, _len: usize) -> Result<(), Errno> {
+        Err(EINVAL)
+    }
+    /// Find the entry `name` of a directory
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(ENOTDIR)
+    }
+    /// Create the entry `name` of a directory
+    fn create(&self, _name: &str, _kind: InodeType, _mode: u32) -> Result<Arc<dyn Inode>, Errno> {
+        Err(ENOTDIR)
+    }
+    /// Create the symbolic link `name` to `target` in a directory
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(ENOTDIR)
+    }
+    /// Make `name` an entry of a directory for `inode` of the same
+    /// filesystem, replacing the entry that may be there
+    fn link(&self, _name: &str, _inode: Arc<dyn Inode>) -> Result<(), Errno> {
+        Err(ENOTDIR)
+    }
+    /// Remove the entry `name` of a directory
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(ENOTDIR)
+    }
+    /// Move the entry `old_name` of a directory to `new_name` of `new_dir`,
+    /// a directory of the same filesystem, replacing the entry that may be
+    /// there
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> Result<(), Errno> {
+        new_dir.link(new_name, self.lookup(old_name)?)?;
+        self.unlink(old_name)
+    }
+    /// The entries of a directory, without `.` and `..`
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        Err(ENOTDIR)
+    }
+    /// The target of a symbolic link
+    fn read_link(&self) -> Result<String, Errno> {
+        Err(EINVAL)
+    }
+    /// The file a device inode is accessed through instead of its content
+    fn device(&self) -> Option<Arc<dyn File>> {
+        None
+    }
+}
+
+/// A read-only directory whose entries are fixed when it is created, for
+/// pseudo filesystems
+pub struct StaticDir {
+    ino: usize,
+    entries: BTreeMap<&'static str, Arc<dyn Inode>>,
+}
+
+impl StaticDir {
+    pub fn new(entries: impl IntoIterator<Item = (&'static str, Arc<dyn Inode>)>) -> Arc<Self> {
+        Arc::new(Self { ino: new_ino(), entries: entries.into_iter().collect() })
+    }
+}
+
+impl Inode for StaticDir {
+    fn kind(&self) -> InodeType {
+        InodeType::Directory
+    }
+    fn ino(&self) -> usize {
+        self.ino
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        self.entries.get(name).cloned().ok_or(ENOENT)
+    }
+    fn create(&self, _name: &str, _kind: InodeType, _mode: u32) -> Result<Arc<dyn Inode>, Errno> {
+        Err(EPERM)
+    }
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(EPERM)
+    }
+    fn link(&self, _name: &str, _inode: Arc<dyn Inode>) -> Result<(), Errno> {
+        Err(EPERM)
+    }
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(EPERM)
+    }
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        Ok(self.entries.iter().map(|(name, inode)| DirEntry {
+            name: name.to_string(),
+            ino: inode.ino(),
+            kind: inode.kind(),
+        }).collect())
+    }
+}
@@ -0,0 +1,341 @@
+//! Virtual filesystem
+//!
+//! Filesystems implement [`Inode`] for the nodes of their trees and are
+//! mounted at absolute paths. Opening a path gives an [`OpenFile`], the open
+//! file description, which the file descriptors of [`fd_table::FD_TABLE`]
+//! refer to. Files that do not live in a filesystem, like the console,
+//! implement [`File`] directly.
+
+mod inode;
+mod file;
+mod tty;
+pub mod devfs;
+pub mod initramfs;
+pub mod tmpfs;
+pub mod fat32;
+pub mod p9;
+pub mod fd_table;
+
+pub use inode::{ new_dev, new_ino, Inode, InodeType, DirEntry, StaticDir };
+pub use file::{ File, InodeFile, OpenFile, OpenFlags, SeekFrom };
+
+use crate::config::{ DISK_MOUNT_POINT, TMPFS_QUOTA };
+use crate::drivers::block;
+use crate::drivers::virtio::{ self, DeviceType };
+use crate::drivers::virtio::p9::Virtio9p;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{ String, ToString };
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::fcntl::{ AT_FDCWD, RENAME_NOREPLACE, RENAME_EXCHANGE };
+use linuxabi::limits::{ NAME_MAX, PATH_MAX };
+
+/// The most symbolic links followed in resolving a path, as in Linux
+const MAX_SYMLINKS: usize = 40;
+
+lazy_static! {
+    /// The roots of the mounted filesystems by their mount points
+    static ref MOUNTS: UPSafeCell<BTreeMap<String, Arc<dyn Inode>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+    /// The current working directory of the application
+    static ref CWD: UPSafeCell<String> = unsafe { UPSafeCell::new("/".to_string()) };
+}
+
+/// Mount the filesystems, where the root is a tmpfs over the initramfs, the
+/// first disk if there is one, and each directory the host exports over 9P
+/// at `/` and its mount tag
+pub fn init() {
+    mount("/", tmpfs::new_over(TMPFS_QUOTA, initramfs::new()));
+    tty::init();
+    mount("/dev", devfs::new());
+    mount("/tmp", tmpfs::new(TMPFS_QUOTA, 0o1777));
+    if let Some(device) = block::devices().first() {
+        match fat32::new(device.clone()) {
+            Ok(root) => mount(DISK_MOUNT_POINT, root),
+            Err(e) => {
+                println!("[kernel] [warn] Failed to mount the disk at {}: {:?}", DISK_MOUNT_POINT, e);
+            },
+        }
+    }
+    while let Some(transport) = virtio::take(DeviceType::NineP) {
+        let base = transport.base();
+        let device = match Virtio9p::new(transport) {
+            Ok(device) => Arc::new(device),
+            Err(e) => {
+                println!("[kernel] [warn] virtio-9p@{:#x}: failed to set up: {:?}", base, e);
+                continue;
+            },
+        };
+        let tag = device.tag();
+        if tag.is_empty() || tag.contains('/') {
+            println!("[kernel] [warn] virtio-9p@{:#x}: no mount point for the tag {:?}", base, tag);
+            continue;
+        }
+        let path = format!("/{}", tag);
+        match p9::new(device.clone()) {
+            Ok(root) => mount(&path, root),
+            Err(e) => {
+                println!("[kernel] [warn] Failed to mount the host directory at {}: {:?}", path, e);
+            },
+        }
+    }
+}
+
+/// Mount the filesystem with root directory `root` at the absolute path
+/// `path`, covering what was there. The mount point is created if missing.
+pub fn mount(path: &str, root: Arc<dyn Inode>) {
+    println!("[kernel] [info] Mounting filesystem at {}", path);
+    if path != "/" {
+        match mkdir(path, 0o755) {
+            Ok(()) | Err(EEXIST) => {},
+            Err(e) => {
+                println!("[kernel] [warn] Failed to create mount point {}: {:?}", path, e);
+            },
+        }
+    }
+    MOUNTS.exclusive_access().insert(path.to_string(), root);
+}
+
+fn is_mount_point(path: &str) -> bool {
+    MOUNTS.exclusive_access().contains_key(path)
+}
+
+pub fn cwd() -> String {
+    CWD.exclusive_access().clone()
+}
+
+/// The absolute path of `path` relative to the directory `base`, with `.`,
+/// `..` and repeated slashes resolved lexically
+pub fn absolute_path(base: &str, path: &str) -> Result<String, Errno> {
+    if path.is_empty() {
+        return Err(ENOENT);
+    }
+    if path.len() >= PATH_MAX {
+        return Err(ENAMETOOLONG);
+    }
+    let mut parts: Vec<&str> = Vec::new();
+    if !path.starts_with('/') {
+        parts.extend(base.split('/').filter(|part| !part.is_empty()));
+    }
+    for part in path.split('/') {
+        match part {
+            "" | "." => {},
+            ".." => { parts.pop(); },
+            name if name.len() > NAME_MAX => return Err(ENAMETOOLONG),
+            name => parts.push(name),
+        }
+    }
+    Ok(format!("/{}", parts.join("/")))
+}
+
+/// The absolute path of `path` relative to the directory open at `dirfd`,
+/// or the working directory for `AT_FDCWD`, as `*at()` syscalls take it
+pub fn resolve_at(dirfd: i32, path: &str) -> Result<String, Errno> {
+    if path.starts_with('/') {
+        absolute_path("/", path)
+    } else if dirfd == AT_FDCWD {
+        absolute_path(&cwd(), path)
+    } else {
+        let dir = fd_table::FD_TABLE.exclusive_access().get(dirfd)?;
+        match dir.inode() {
+            Some(inode) if inode.kind() == InodeType::Directory => absolute_path(dir.path(), path),
+            _ => Err(ENOTDIR),
+        }
+    }
+}
+
+/// Whether the absolute path `path` is at or below `mount_point`
+fn is_under(path: &str, mount_point: &str) -> bool {
+    mount_point == "/" || path.strip_prefix(mount_point)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Resolve the absolute path `path`, following the symbolic links on the
+/// way, and in the last component if `follow`. Returns the path with these
+/// links replaced by their targets, and the inode there, or None if the
+/// last component is missing.
+fn resolve(path: &str, follow: bool) -> Result<(String, Option<Arc<dyn Inode>>), Errno> {
+    let mut path = path.to_string();
+    let mut links = 0;
+    'walk: loop {
+        let (mount_point, root) = MOUNTS.exclusive_access().iter()
+            .filter(|(mount_point, _)| is_under(&path, mount_point))
+            .max_by_key(|(mount_point, _)| mount_point.len())
+            .map(|(mount_point, root)| (mount_point.clone(), root.clone()))
+            .ok_or(ENOENT)?;
+        let names: Vec<&str> = path[mount_point.len()..].split('/')
+            .filter(|name| !name.is_empty())
+            .collect();
+        let mut dir_path = mount_point;
+        let mut inode = root;
+        for (i, name) in names.iter().enumerate() {
+            let last = i + 1 == names.len();
+            let next = match inode.lookup(name) {
+                Ok(next) => next,
+                Err(ENOENT) if last => return Ok((path, None)),
+                Err(e) => return Err(e),
+            };
+            if next.kind() == InodeType::Symlink && (follow || !last) {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    return Err(ELOOP);
+                }
+                // Start over from the target, which is relative to the
+                // directory of the link, with the rest of the path
+                let target = absolute_path(&dir_path, &next.read_link()?)?;
+                path = if last { target } else { absolute_path(&target, &names[i + 1..].join("/"))? };
+                continue 'walk;
+            }
+            if dir_path != "/" {
+                dir_path.push('/');
+            }
+            dir_path.push_str(name);
+            inode = next;
+        }
+        return Ok((path, Some(inode)));
+    }
+}
+
+/// The inode at the absolute path `path`, following symbolic links
+pub fn lookup(path: &str) -> Result<Arc<dyn Inode>, Errno> {
+    resolve(path, true)?.1.ok_or(ENOENT)
+}
+
+/// The inode at the absolute path `path`, which is the symbolic link itself
+/// if the last component is one
+pub fn lookup_nofollow(path: &str) -> Result<Arc<dyn Inode>, Errno> {
+    resolve(path, false)?.1.ok_or(ENOENT)
+}
+
+/// The directory containing the absolute path `path`, and the last
+/// component of the path
+pub fn lookup_parent(path: &str) -> Result<(Arc<dyn Inode>, &str), Errno> {
+    let (parent, name) = path.rsplit_once('/').ok_or(EINVAL)?;
+    if name.is_empty() {
+        // the root has no parent
+        return Err(EBUSY);
+    }
+    let parent = lookup(if parent.is_empty() { "/" } else { parent })?;
+    if parent.kind() != InodeType::Directory {
+        return Err(ENOTDIR);
+    }
+    Ok((parent, name))
+}
+
+/// Open the absolute path `path` as open(2) does. A symbolic link at the
+/// end is followed, and created through if missing, unless O_NOFOLLOW or
+/// O_EXCL is given.
+pub fn open(path: &str, flags: OpenFlags, mode: u32) -> Result<OpenFile, Errno> {
+    let follow = !flags.contains(OpenFlags::NOFOLLOW)
+        && !flags.contains(OpenFlags::CREAT | OpenFlags::EXCL);
+    let (path, inode) = resolve(path, follow)?;
+    let inode = match inode {
+        Some(_) if flags.contains(OpenFlags::CREAT | OpenFlags::EXCL) => return Err(EEXIST),
+        Some(inode) if inode.kind() == InodeType::Symlink => return Err(ELOOP),
+        Some(inode) => inode,
+        None if flags.contains(OpenFlags::CREAT) => {
+            let (parent, name) = lookup_parent(&path)?;
+            parent.create(name, InodeType::Regular, mode)?
+        },
+        None => return Err(ENOENT),
+    };
+    let kind = inode.kind();
+    if kind == InodeType::Directory && (flags.writable() || flags.contains(OpenFlags::CREAT)) {
+        return Err(EISDIR);
+    }
+    if kind != InodeType::Directory && flags.contains(OpenFlags::DIRECTORY) {
+        return Err(ENOTDIR);
+    }
+    if kind == InodeType::Regular && flags.writable() && flags.contains(OpenFlags::TRUNC) {
+        inode.truncate(0)?;
+    }
+    let file = match inode.device() {
+        Some(file) => file,
+        None => Arc::new(InodeFile::new(inode.clone())),
+    };
+    Ok(OpenFile::new(file, Some(inode), path, flags))
+}
+
+/// Create the directory at the absolute path `path`
+pub fn mkdir(path: &str, mode: u32) -> Result<(), Errno> {
+    let (parent, name) = lookup_parent(path)?;
+    parent.create(name, InodeType::Directory, mode)?;
+    Ok(())
+}
+
+/// Remove the entry at the absolute path `path`, which must be an empty
+/// directory if `remove_dir` and must not be a directory otherwise
+pub fn unlink(path: &str, remove_dir: bool) -> Result<(), Errno> {
+    let (parent, name) = lookup_parent(path)?;
+    let inode = parent.lookup(name)?;
+    if remove_dir {
+        if inode.kind() != InodeType::Directory {
+            return Err(ENOTDIR);
+        }
+        if is_mount_point(path) {
+            return Err(EBUSY);
+        }
+        if !inode.read_dir()?.is_empty() {
+            return Err(ENOTEMPTY);
+        }
+    } else if inode.kind() == InodeType::Directory {
+        return Err(EISDIR);
+    }
+    parent.unlink(name)
+}
+
+/// Move the entry at the absolute path `old` to `new` as renameat2(2) does
+pub fn rename(old: &str, new: &str, flags: u32) -> Result<(), Errno> {
+    if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0
+        || flags == RENAME_NOREPLACE | RENAME_EXCHANGE {
+        return Err(EINVAL);
+    }
+    let (old_dir, old_name) = lookup_parent(old)?;
+    let (new_dir, new_name) = lookup_parent(new)?;
+    let inode = old_dir.lookup(old_name)?;
+    if is_mount_point(old) || is_mount_point(new) {
+        return Err(EBUSY);
+    }
+    if old_dir.dev() != new_dir.dev() {
+        return Err(EXDEV);
+    }
+    // A directory cannot be moved into itself
+    if is_under(new, old) && new != old {
+        return Err(EINVAL);
+    }
+    let target = match new_dir.lookup(new_name) {
+        Ok(target) => Some(target),
+        Err(ENOENT) => None,
+        Err(e) => return Err(e),
+    };
+    if flags & RENAME_EXCHANGE != 0 {
+        let target = target.ok_or(ENOENT)?;
+        if is_under(old, new) {
+            return Err(EINVAL);
+        }
+        // Two links, so not atomic: tmpfs links cannot fail and 9P refuses
+        // the first, but on FAT an I/O error or a full directory in the
+        // second leaves `new` replaced and `target` unlinked
+        new_dir.link(new_name, inode)?;
+        return old_dir.link(old_name, target);
+    }
+    if let Some(target) = target {
+        if flags & RENAME_NOREPLACE != 0 {
+            return Err(EEXIST);
+        }
+        if Arc::ptr_eq(&target, &inode) {
+            return Ok(());
+        }
+        match (inode.kind() == InodeType::Directory, target.kind() == InodeType::Directory) {
+            (true, false) => return Err(ENOTDIR),
+            (false, true) => return Err(EISDIR),
+            (true, true) if !target.read_dir()?.is_empty() => return Err(ENOTEMPTY),
+            _ => {},
+        }
+    }
+    old_dir.rename(old_name, &new_dir, new_name)
+}
@@ -0,0 +1,229 @@
+//! mod p9: a 9P2000.L client for directories the host exports
+//!
+//! Every operation is a request to the server, so the host sees changes as
+//! they are made and the app sees changes the host makes. A fid, the
+//! server's handle for a file, is kept for each [`node::P9Node`] in use,
+//! and the same node is handed out for a file while in use.
+
+mod msg;
+mod node;
+
+use super::{ new_dev, Inode, InodeType };
+use crate::config::P9_MSIZE;
+use crate::drivers::virtio::p9::Virtio9p;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{ Arc, Weak };
+use alloc::vec;
+use alloc::vec::Vec;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::stat::*;
+use msg::*;
+use node::P9Node;
+
+const VERSION: &str = "9P2000.L";
+
+/// The fid of the root directory
+const ROOT_FID: u32 = 0;
+
+pub struct P9Fs {
+    dev: usize,
+    device: Arc<Virtio9p>,
+    /// The largest message either side sends, as agreed with Tversion
+    msize: usize,
+    /// The fid after the largest in use, and the fids given back below it
+    fids: UPSafeCell<(u32, Vec<u32>)>,
+    /// The nodes in use by the paths of their qids
+    nodes: UPSafeCell<BTreeMap<u64, Weak<P9Node>>>,
+}
+
+fn kind_of(mode: u32) -> InodeType {
+    match mode & S_IFMT {
+        S_IFDIR => InodeType::Directory,
+        S_IFLNK => InodeType::Symlink,
+        S_IFCHR => InodeType::CharDevice,
+        S_IFBLK => InodeType::BlockDevice,
+        S_IFIFO => InodeType::Fifo,
+        S_IFSOCK => InodeType::Socket,
+        _ => InodeType::Regular,
+    }
+}
+
+impl P9Fs {
+    /// Send `msg` and decode the reply with `f`
+    fn call<R>(&self, msg: &mut Msg, f: impl FnOnce(&mut Reply) -> Result<R, Errno>) -> Result<R, Errno> {
+        let request = msg.finish();
+        if request.len() > self.msize {
+            return Err(ENAMETOOLONG);
+        }
+        let kind = request[4] + 1;
+        let mut buf = vec![0u8; self.msize];
+        let len = self.device.request(request, &mut buf)?;
+        f(&mut Reply::parse(&buf[..len.min(buf.len())], kind)?)
+    }
+
+    /// The most data a read or write moves
+    fn iounit(&self) -> usize {
+        self.msize - IO_HEADER_SIZE
+    }
+
+    fn alloc_fid(&self) -> u32 {
+        let mut fids = self.fids.exclusive_access();
+        fids.1.pop().unwrap_or_else(|| {
+            fids.0 += 1;
+            fids.0 - 1
+        })
+    }
+
+    /// Tell the server `fid` is no longer needed
+    fn clunk(&self, fid: u32) {
+        if let Err(e) = self.call(Msg::new(TCLUNK).u32(fid), |_| Ok(())) {
+            println!("[kernel] [warn] 9p: failed to clunk fid {}: {:?}", fid, e);
+        }
+        self.fids.exclusive_access().1.push(fid);
+    }
+
+    /// A new fid for the file at `names` under `fid`, and its qid unless
+    /// `names` is empty, in which case the new fid is a copy of `fid`
+    fn walk(&self, fid: u32, names: &[&str]) -> Result<(u32, Option<Qid>), Errno> {
+        let newfid = self.alloc_fid();
+        let mut msg = Msg::new(TWALK);
+        msg.u32(fid).u32(newfid).u16(names.len() as u16);
+        for name in names {
+            msg.str(name);
+        }
+        let result = self.call(&mut msg, |reply| {
+            let count = reply.u16()? as usize;
+            let qids = (0..count).map(|_| reply.qid()).collect::<Result<Vec<_>, _>>()?;
+            // newfid is only made when the walk gets to the end
+            if count < names.len() {
+                return Err(ENOENT);
+            }
+            Ok(qids.last().copied())
+        });
+        match result {
+            Ok(qid) => Ok((newfid, qid)),
+            Err(e) => {
+                self.fids.exclusive_access().1.push(newfid);
+                Err(e)
+            }
+        }
+    }
+
+    fn lopen(&self, fid: u32, flags: u32) -> Result<(), Errno> {
+        self.call(Msg::new(TLOPEN).u32(fid).u32(flags), |_| Ok(()))
+    }
+
+    /// Create `name` in the directory `fid`, which becomes the new file
+    /// opened with `flags`
+    fn lcreate(&self, fid: u32, name: &str, flags: u32, mode: u32) -> Result<(), Errno> {
+        self.call(Msg::new(TLCREATE).u32(fid).str(name).u32(flags).u32(mode).u32(0), |_| Ok(()))
+    }
+
+    fn mkdir(&self, fid: u32, name: &str, mode: u32) -> Result<(), Errno> {
+        self.call(Msg::new(TMKDIR).u32(fid).str(name).u32(mode).u32(0), |_| Ok(()))
+    }
+
+    fn symlink(&self, fid: u32, name: &str, target: &str) -> Result<(), Errno> {
+        self.call(Msg::new(TSYMLINK).u32(fid).str(name).str(target).u32(0), |_| Ok(()))
+    }
+
+    fn readlink(&self, fid: u32) -> Result<String, Errno> {
+        self.call(Msg::new(TREADLINK).u32(fid), |reply| reply.str())
+    }
+
+    fn getattr(&self, fid: u32) -> Result<Attr, Errno> {
+        self.call(Msg::new(TGETATTR).u32(fid).u64(P9_GETATTR_BASIC), |reply| {
+            let _valid = reply.u64()?;
+            Ok(Attr {
+                qid: reply.qid()?,
+                mode: reply.u32()?,
+                uid: reply.u32()?,
+                gid: reply.u32()?,
+                nlink: reply.u64()?,
+                rdev: reply.u64()?,
+                size: reply.u64()?,
+                blksize: reply.u64()?,
+                blocks: reply.u64()?,
+                atime: (reply.u64()?, reply.u64()?),
+                mtime: (reply.u64()?, reply.u64()?),
+                ctime: (reply.u64()?, reply.u64()?),
+            })
+        })
+    }
+
+    fn set_size(&self, fid: u32, size: u64) -> Result<(), Errno> {
+        let mut msg = Msg::new(TSETATTR);
+        msg.u32(fid).u32(P9_SETATTR_SIZE).u32(0).u32(0).u32(0).u64(size);
+        // no times
+        msg.u64(0).u64(0).u64(0).u64(0);
+        self.call(&mut msg, |_| Ok(()))
+    }
+
+    /// Read from `offset` of the opened `fid` into `buf`, which is no
+    /// longer than the iounit
+    fn read(&self, fid: u32, offset: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.call(Msg::new(TREAD).u32(fid).u64(offset).u32(buf.len() as u32), |reply| {
+            let count = (reply.u32()? as usize).min(buf.len());
+            buf[..count].copy_from_slice(reply.bytes(count)?);
+            Ok(count)
+        })
+    }
+
+    /// Write `buf`, which is no longer than the iounit, at `offset` of the
+    /// opened `fid`
+    fn write(&self, fid: u32, offset: u64, buf: &[u8]) -> Result<usize, Errno> {
+        let mut msg = Msg::new(TWRITE);
+        msg.u32(fid).u64(offset).u32(buf.len() as u32).bytes(buf);
+        self.call(&mut msg, |reply| Ok(reply.u32()? as usize))
+    }
+
+    /// The entries from `offset` of the directory opened at `fid`, with the
+    /// offset of the next, which are none at the end
+    fn readdir(&self, fid: u32, offset: u64) -> Result<Vec<(String, u64, u8, u64)>, Errno> {
+        let count = self.iounit() as u32;
+        self.call(Msg::new(TREADDIR).u32(fid).u64(offset).u32(count), |reply| {
+            let _count = reply.u32()?;
+            let mut entries = Vec::new();
+            while !reply.is_empty() {
+                let qid = reply.qid()?;
+                let next = reply.u64()?;
+                let dtype = reply.u8()?;
+                entries.push((reply.str()?, qid.path, dtype, next));
+            }
+            Ok(entries)
+        })
+    }
+
+    fn unlinkat(&self, fid: u32, name: &str, flags: u32) -> Result<(), Errno> {
+        self.call(Msg::new(TUNLINKAT).u32(fid).str(name).u32(flags), |_| Ok(()))
+    }
+
+    fn renameat(&self, old_fid: u32, old_name: &str, new_fid: u32, new_name: &str) -> Result<(), Errno> {
+        self.call(Msg::new(TRENAMEAT).u32(old_fid).str(old_name).u32(new_fid).str(new_name), |_| Ok(()))
+    }
+}
+
+/// The root directory of the export on `device`
+pub fn new(device: Arc<Virtio9p>) -> Result<Arc<dyn Inode>, Errno> {
+    let mut fs = P9Fs {
+        dev: new_dev(),
+        device,
+        msize: P9_MSIZE,
+        fids: unsafe { UPSafeCell::new((ROOT_FID + 1, Vec::new())) },
+        nodes: unsafe { UPSafeCell::new(BTreeMap::new()) },
+    };
+    let (msize, version) = fs.call(Msg::new(TVERSION).u32(P9_MSIZE as u32).str(VERSION), |reply| {
+        Ok((reply.u32()? as usize, reply.str()?))
+    })?;
+    if version != VERSION || msize <= IO_HEADER_SIZE {
+        println!("[kernel] [warn] 9p: the server speaks {} with msize {}", version, msize);
+        return Err(EPROTONOSUPPORT);
+    }
+    fs.msize = msize.min(P9_MSIZE);
+    // as root, with no authentication
+    fs.call(Msg::new(TATTACH).u32(ROOT_FID).u32(NOFID).str("root").str("").u32(0), |reply| reply.qid())?;
+    let attr = fs.getattr(ROOT_FID)?;
+    Ok(P9Node::get(&Arc::new(fs), ROOT_FID, attr))
+}
@@ -0,0 +1,183 @@
+//! mod msg: encoding and decoding 9P2000.L messages
+//!
+//! A message is `size[4] type[1] tag[2]` and then its fields, all little
+//! endian, with strings as `len[2]` and the bytes.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use linuxabi::errno::Errno::{ self, * };
+
+// types of message, where the reply to each T-message is the next number
+pub const TLERROR: u8 = 6;
+pub const TLOPEN: u8 = 12;
+pub const TLCREATE: u8 = 14;
+pub const TSYMLINK: u8 = 16;
+pub const TREADLINK: u8 = 22;
+pub const TGETATTR: u8 = 24;
+pub const TSETATTR: u8 = 26;
+pub const TREADDIR: u8 = 40;
+pub const TMKDIR: u8 = 72;
+pub const TRENAMEAT: u8 = 74;
+pub const TUNLINKAT: u8 = 76;
+pub const TVERSION: u8 = 100;
+pub const TATTACH: u8 = 104;
+pub const TWALK: u8 = 110;
+pub const TREAD: u8 = 116;
+pub const TWRITE: u8 = 118;
+pub const TCLUNK: u8 = 120;
+
+/// The tag of Tversion, and the one used for everything else as requests
+/// are made one at a time
+pub const NOTAG: u16 = 0xffff;
+pub const TAG: u16 = 0;
+/// No fid, as the afid of Tattach without authentication
+pub const NOFID: u32 = 0xffff_ffff;
+
+/// The size of the header of a message
+pub const HEADER_SIZE: usize = 7;
+/// The bytes of Twrite before the data, which Rread also fits in
+pub const IO_HEADER_SIZE: usize = HEADER_SIZE + 4 + 8 + 4;
+
+// flags of Tlopen and Tlcreate, as for Linux open(2)
+pub const P9_RDONLY: u32 = 0o0;
+pub const P9_RDWR: u32 = 0o2;
+pub const P9_CREAT: u32 = 0o100;
+pub const P9_EXCL: u32 = 0o200;
+pub const P9_DIRECTORY: u32 = 0o200000;
+
+/// The basic fields of Tgetattr: mode, nlink, uid, gid, rdev, times, ino,
+/// size and blocks
+pub const P9_GETATTR_BASIC: u64 = 0x7ff;
+/// The valid bit of Tsetattr for the size
+pub const P9_SETATTR_SIZE: u32 = 0x8;
+
+/// The flag of Tunlinkat to remove a directory
+pub const P9_AT_REMOVEDIR: u32 = 0x200;
+
+/// The qid type bit of directories
+pub const QTDIR: u8 = 0x80;
+
+/// The server's identity for a file
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Qid {
+    pub kind: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+/// The reply to Tgetattr
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Attr {
+    pub qid: Qid,
+    pub mode: u32,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub atime: (u64, u64),
+    pub mtime: (u64, u64),
+    pub ctime: (u64, u64),
+}
+
+/// A message being built
+pub struct Msg(Vec<u8>);
+
+impl Msg {
+    pub fn new(kind: u8) -> Self {
+        let mut msg = Self(Vec::new());
+        msg.u32(0).u8(kind).u16(if kind == TVERSION { NOTAG } else { TAG });
+        msg
+    }
+    pub fn u8(&mut self, val: u8) -> &mut Self {
+        self.0.push(val);
+        self
+    }
+    pub fn u16(&mut self, val: u16) -> &mut Self {
+        self.0.extend_from_slice(&val.to_le_bytes());
+        self
+    }
+    pub fn u32(&mut self, val: u32) -> &mut Self {
+        self.0.extend_from_slice(&val.to_le_bytes());
+        self
+    }
+    pub fn u64(&mut self, val: u64) -> &mut Self {
+        self.0.extend_from_slice(&val.to_le_bytes());
+        self
+    }
+    pub fn str(&mut self, val: &str) -> &mut Self {
+        self.u16(val.len() as u16);
+        self.0.extend_from_slice(val.as_bytes());
+        self
+    }
+    pub fn bytes(&mut self, val: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(val);
+        self
+    }
+    /// The encoded message, with its size filled in
+    pub fn finish(&mut self) -> &[u8] {
+        let size = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&size.to_le_bytes());
+        &self.0
+    }
+}
+
+/// The fields of a reply, read in order
+pub struct Reply<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reply<'a> {
+    /// The fields of the reply in `buf`, which must be of type `kind`,
+    /// turning Rlerror into its error
+    pub fn parse(buf: &'a [u8], kind: u8) -> Result<Self, Errno> {
+        if buf.len() < HEADER_SIZE {
+            return Err(EIO);
+        }
+        let size = u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize;
+        if size < HEADER_SIZE || size > buf.len() {
+            return Err(EIO);
+        }
+        let mut reply = Self { buf: &buf[..size], pos: HEADER_SIZE };
+        match buf[4] {
+            kind_got if kind_got == kind => Ok(reply),
+            kind_got if kind_got == TLERROR + 1 => {
+                Err(Errno::from_raw(reply.u32()? as usize).unwrap_or(EIO))
+            }
+            _ => Err(EIO),
+        }
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Errno> {
+        let bytes = self.buf.get(self.pos..self.pos + len).ok_or(EIO)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    pub fn u8(&mut self) -> Result<u8, Errno> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn u16(&mut self) -> Result<u16, Errno> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> Result<u32, Errno> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, Errno> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn str(&mut self) -> Result<String, Errno> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], Errno> {
+        self.take(len)
+    }
+    pub fn qid(&mut self) -> Result<Qid, Errno> {
+        Ok(Qid { kind: self.u8()?, version: self.u32()?, path: self.u64()? })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+}
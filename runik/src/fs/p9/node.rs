@@ -0,0 +1,290 @@
+//! mod node: the files and directories of a 9P export
+
+use super::msg::*;
+use super::{ kind_of, P9Fs };
+use crate::fs::{ DirEntry, Inode, InodeType };
+use crate::sync::UPSafeCell;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use linuxabi::dirent::*;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::stat::Stat;
+
+pub struct P9Node {
+    fs: Arc<P9Fs>,
+    /// The fid walked to the file, which is never opened
+    fid: u32,
+    qid: Qid,
+    kind: InodeType,
+    /// A fid opened for I/O on a regular file, and whether it can write
+    io: UPSafeCell<Option<(u32, bool)>>,
+}
+
+impl P9Node {
+    /// The node for the file at `fid`, which is taken, with the attributes
+    /// `attr`
+    pub fn get(fs: &Arc<P9Fs>, fid: u32, attr: Attr) -> Arc<Self> {
+        let mut nodes = fs.nodes.exclusive_access();
+        if let Some(node) = nodes.get(&attr.qid.path).and_then(|node| node.upgrade()) {
+            drop(nodes);
+            fs.clunk(fid);
+            return node;
+        }
+        let node = Arc::new(Self {
+            fs: fs.clone(),
+            fid,
+            qid: attr.qid,
+            kind: kind_of(attr.mode),
+            io: unsafe { UPSafeCell::new(None) },
+        });
+        nodes.insert(attr.qid.path, Arc::downgrade(&node));
+        node
+    }
+
+    /// The node for the entry `name`, with a fresh fid for it
+    fn child(&self, name: &str) -> Result<Arc<Self>, Errno> {
+        let (fid, qid) = self.fs.walk(self.fid, &[name])?;
+        let known = qid.and_then(|qid| self.fs.nodes.exclusive_access().get(&qid.path)?.upgrade());
+        if let Some(node) = known {
+            self.fs.clunk(fid);
+            return Ok(node);
+        }
+        match self.fs.getattr(fid) {
+            Ok(attr) => Ok(Self::get(&self.fs, fid, attr)),
+            Err(e) => {
+                self.fs.clunk(fid);
+                Err(e)
+            }
+        }
+    }
+
+    /// Our node of this filesystem that is `inode`, if in use
+    fn find(&self, inode: &Arc<dyn Inode>) -> Option<Arc<Self>> {
+        self.fs.nodes.exclusive_access().values()
+            .filter_map(|node| node.upgrade())
+            .find(|node| Arc::as_ptr(node) as *const u8 == Arc::as_ptr(inode) as *const u8)
+    }
+
+    /// The fid for I/O, opening one able to write if `write`
+    fn io_fid(&self, write: bool) -> Result<u32, Errno> {
+        let mut io = self.io.exclusive_access();
+        match *io {
+            Some((fid, writable)) if writable || !write => return Ok(fid),
+            _ => {},
+        }
+        let (fid, _) = self.fs.walk(self.fid, &[])?;
+        // open for both if allowed, so one fid serves reads and writes
+        let opened = match self.fs.lopen(fid, P9_RDWR) {
+            Ok(()) => Ok(true),
+            Err(_) if !write => self.fs.lopen(fid, P9_RDONLY).map(|_| false),
+            Err(e) => Err(e),
+        };
+        match opened {
+            Ok(writable) => {
+                if let Some((old, _)) = io.replace((fid, writable)) {
+                    self.fs.clunk(old);
+                }
+                Ok(fid)
+            }
+            Err(e) => {
+                self.fs.clunk(fid);
+                Err(e)
+            }
+        }
+    }
+
+    fn is_dir(&self) -> Result<(), Errno> {
+        if self.kind == InodeType::Directory { Ok(()) } else { Err(ENOTDIR) }
+    }
+}
+
+impl Inode for P9Node {
+    fn kind(&self) -> InodeType {
+        self.kind
+    }
+    fn ino(&self) -> usize {
+        self.qid.path as usize
+    }
+    fn dev(&self) -> usize {
+        self.fs.dev
+    }
+    fn mode(&self) -> u32 {
+        self.fs.getattr(self.fid).map_or(0, |attr| attr.mode & 0o7777)
+    }
+    fn mtime(&self) -> i64 {
+        self.fs.getattr(self.fid).map_or(0, |attr| attr.mtime.0 as i64)
+    }
+    fn stat(&self) -> Stat {
+        let Ok(attr) = self.fs.getattr(self.fid) else {
+            return Stat { st_dev: self.dev() as u64, st_ino: self.ino() as u64, ..Default::default() };
+        };
+        Stat {
+            st_dev: self.dev() as u64,
+            st_ino: self.ino() as u64,
+            st_mode: attr.mode,
+            st_nlink: attr.nlink as u32,
+            st_uid: attr.uid,
+            st_gid: attr.gid,
+            st_rdev: attr.rdev,
+            st_size: attr.size as i64,
+            st_blksize: attr.blksize as i32,
+            st_blocks: attr.blocks as i64,
+            st_atime: attr.atime.0 as i64,
+            st_atime_nsec: attr.atime.1,
+            st_mtime: attr.mtime.0 as i64,
+            st_mtime_nsec: attr.mtime.1,
+            st_ctime: attr.ctime.0 as i64,
+            st_ctime_nsec: attr.ctime.1,
+            ..Default::default()
+        }
+    }
+    fn size(&self) -> usize {
+        self.fs.getattr(self.fid).map_or(0, |attr| attr.size as usize)
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.kind != InodeType::Regular {
+            return Err(if self.kind == InodeType::Directory { EISDIR } else { EINVAL });
+        }
+        let fid = self.io_fid(false)?;
+        let mut total = 0;
+        while total < buf.len() {
+            let want = (buf.len() - total).min(self.fs.iounit());
+            let n = self.fs.read(fid, (offset + total) as u64, &mut buf[total..total + want])?;
+            total += n;
+            if n < want {
+                break;
+            }
+        }
+        Ok(total)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        if self.kind != InodeType::Regular {
+            return Err(if self.kind == InodeType::Directory { EISDIR } else { EINVAL });
+        }
+        let fid = self.io_fid(true)?;
+        let mut total = 0;
+        while total < buf.len() {
+            let want = (buf.len() - total).min(self.fs.iounit());
+            let n = self.fs.write(fid, (offset + total) as u64, &buf[total..total + want])?;
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+        Ok(total)
+    }
+    fn truncate(&self, len: usize) -> Result<(), Errno> {
+        if self.kind != InodeType::Regular {
+            return Err(EINVAL);
+        }
+        self.fs.set_size(self.fid, len as u64)
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        self.is_dir()?;
+        Ok(self.child(name)?)
+    }
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> Result<Arc<dyn Inode>, Errno> {
+        self.is_dir()?;
+        match kind {
+            InodeType::Regular => {
+                // the created file is left open on the new fid, for I/O
+                let (fid, _) = self.fs.walk(self.fid, &[])?;
+                if let Err(e) = self.fs.lcreate(fid, name, P9_RDWR | P9_CREAT | P9_EXCL, mode) {
+                    self.fs.clunk(fid);
+                    return Err(e);
+                }
+                let node = match self.child(name) {
+                    Ok(node) => node,
+                    Err(e) => {
+                        self.fs.clunk(fid);
+                        return Err(e);
+                    }
+                };
+                if let Some((old, _)) = node.io.exclusive_access().replace((fid, true)) {
+                    self.fs.clunk(old);
+                }
+                Ok(node)
+            }
+            InodeType::Directory => {
+                self.fs.mkdir(self.fid, name, mode)?;
+                Ok(self.child(name)?)
+            }
+            _ => Err(EPERM),
+        }
+    }
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        self.is_dir()?;
+        self.fs.symlink(self.fid, name, target)?;
+        Ok(self.child(name)?)
+    }
+    fn link(&self, _name: &str, _inode: Arc<dyn Inode>) -> Result<(), Errno> {
+        // only used to swap entries, which 9P has no way to do
+        Err(EINVAL)
+    }
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        self.is_dir()?;
+        match self.fs.unlinkat(self.fid, name, 0) {
+            Err(EISDIR) => self.fs.unlinkat(self.fid, name, P9_AT_REMOVEDIR),
+            result => result,
+        }
+    }
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> Result<(), Errno> {
+        self.is_dir()?;
+        let new_dir = self.find(new_dir).ok_or(EXDEV)?;
+        self.fs.renameat(self.fid, old_name, new_dir.fid, new_name)
+    }
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        self.is_dir()?;
+        let (fid, _) = self.fs.walk(self.fid, &[])?;
+        let result = self.fs.lopen(fid, P9_RDONLY | P9_DIRECTORY).and_then(|()| {
+            let mut entries = Vec::new();
+            let mut offset = 0;
+            loop {
+                let batch = self.fs.readdir(fid, offset)?;
+                let Some(&(_, _, _, next)) = batch.last() else {
+                    break;
+                };
+                offset = next;
+                entries.extend(batch.into_iter()
+                    .filter(|(name, ..)| name != "." && name != "..")
+                    .map(|(name, path, dtype, _)| DirEntry { name, ino: path as usize, kind: dirent_kind(dtype) }));
+            }
+            Ok(entries)
+        });
+        self.fs.clunk(fid);
+        result
+    }
+    fn read_link(&self) -> Result<String, Errno> {
+        if self.kind != InodeType::Symlink {
+            return Err(EINVAL);
+        }
+        self.fs.readlink(self.fid)
+    }
+}
+
+/// The type of inode of the `d_type` of an entry
+fn dirent_kind(dtype: u8) -> InodeType {
+    match dtype {
+        DT_DIR => InodeType::Directory,
+        DT_LNK => InodeType::Symlink,
+        DT_CHR => InodeType::CharDevice,
+        DT_BLK => InodeType::BlockDevice,
+        DT_FIFO => InodeType::Fifo,
+        DT_SOCK => InodeType::Socket,
+        _ => InodeType::Regular,
+    }
+}
+
+impl Drop for P9Node {
+    fn drop(&mut self) {
+        if let Some((fid, _)) = self.io.exclusive_access().take() {
+            self.fs.clunk(fid);
+        }
+        self.fs.clunk(self.fid);
+        let mut nodes = self.fs.nodes.exclusive_access();
+        if nodes.get(&self.qid.path).is_some_and(|node| node.strong_count() == 0) {
+            nodes.remove(&self.qid.path);
+        }
+    }
+}
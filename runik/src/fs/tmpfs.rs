@@ -0,0 +1,380 @@
+//! mod tmpfs: a writable filesystem in memory
+//!
+//! The content of files is kept in frames taken from [`crate::mm::frame`] as
+//! it is written, and each tmpfs may hold at most the number of bytes it is
+//! created with. Holes in sparse files take no frames.
+//!
+//! A tmpfs may lie over a read-only tree, whose entries show through its
+//! directories until they are removed or covered. A file of the tree is
+//! copied into the tmpfs when it is first written, and a directory gets one
+//! of the tmpfs when it is first looked up.
+
+use super::{ new_dev, new_ino, DirEntry, Inode, InodeType };
+use crate::arch::paging::PAGE_SIZE;
+use crate::mm::frame::{ frame_alloc, FrameTracker };
+use crate::sync::UPSafeCell;
+use alloc::collections::{ BTreeMap, BTreeSet };
+use alloc::collections::btree_map::Entry;
+use alloc::string::{ String, ToString };
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use linuxabi::errno::Errno::{ self, * };
+
+/// The state shared by the inodes of a tmpfs
+struct TmpFs {
+    dev: usize,
+    quota_pages: usize,
+    used_pages: UPSafeCell<usize>,
+}
+
+impl TmpFs {
+    /// Take a frame for file content, counting it against the quota
+    fn alloc_page(&self) -> Result<FrameTracker, Errno> {
+        let mut used = self.used_pages.exclusive_access();
+        if *used >= self.quota_pages {
+            return Err(ENOSPC);
+        }
+        let frame = frame_alloc().ok_or(ENOSPC)?;
+        *used += 1;
+        Ok(frame)
+    }
+    fn release_pages(&self, pages: usize) {
+        *self.used_pages.exclusive_access() -= pages;
+    }
+}
+
+struct FileContent {
+    size: usize,
+    /// The frames of the pages written, by page index
+    pages: BTreeMap<usize, FrameTracker>,
+}
+
+/// A regular file, or a symbolic link whose content is its target
+struct TmpFile {
+    ino: usize,
+    kind: InodeType,
+    mode: u32,
+    fs: Arc<TmpFs>,
+    content: UPSafeCell<FileContent>,
+}
+
+impl TmpFile {
+    fn new(fs: &Arc<TmpFs>, kind: InodeType, mode: u32) -> Arc<Self> {
+        Arc::new(Self {
+            ino: new_ino(),
+            kind,
+            mode,
+            fs: fs.clone(),
+            content: unsafe { UPSafeCell::new(FileContent { size: 0, pages: BTreeMap::new() }) },
+        })
+    }
+    fn read_content(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let content = self.content.exclusive_access();
+        let end = content.size.min(offset.saturating_add(buf.len()));
+        let mut pos = offset;
+        while pos < end {
+            let (page, page_offset) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match content.pages.get(&page) {
+                Some(frame) => dst.copy_from_slice(&frame.ppn.get_bytes_array()[page_offset..page_offset + len]),
+                _ => dst.fill(0),
+            }
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+    fn write_content(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        let end = offset.checked_add(buf.len()).filter(|&end| end <= isize::MAX as usize).ok_or(EFBIG)?;
+        let mut content = self.content.exclusive_access();
+        let mut pos = offset;
+        while pos < end {
+            let (page, page_offset) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let frame = match content.pages.entry(page) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match self.fs.alloc_page() {
+                    Ok(frame) => entry.insert(frame),
+                    // a short write if some bytes made it
+                    Err(_) if pos > offset => break,
+                    Err(e) => return Err(e),
+                },
+            };
+            frame.ppn.get_bytes_array()[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        content.size = content.size.max(pos);
+        Ok(pos - offset)
+    }
+}
+
+impl Inode for TmpFile {
+    fn kind(&self) -> InodeType {
+        self.kind
+    }
+    fn ino(&self) -> usize {
+        self.ino
+    }
+    fn dev(&self) -> usize {
+        self.fs.dev
+    }
+    fn mode(&self) -> u32 {
+        self.mode
+    }
+    fn size(&self) -> usize {
+        self.content.exclusive_access().size
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.kind != InodeType::Regular {
+            return Err(EINVAL);
+        }
+        Ok(self.read_content(offset, buf))
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        if self.kind != InodeType::Regular {
+            return Err(EINVAL);
+        }
+        self.write_content(offset, buf)
+    }
+    fn truncate(&self, len: usize) -> Result<(), Errno> {
+        if self.kind != InodeType::Regular {
+            return Err(EINVAL);
+        }
+        if len > isize::MAX as usize {
+            return Err(EFBIG);
+        }
+        let mut content = self.content.exclusive_access();
+        if len < content.size {
+            // Zero the tail of the last page, which may be read again if the
+            // file grows
+            if let Some(frame) = content.pages.get(&(len / PAGE_SIZE)) {
+                frame.ppn.get_bytes_array()[len % PAGE_SIZE..].fill(0);
+            }
+            let freed = content.pages.split_off(&len.div_ceil(PAGE_SIZE)).len();
+            self.fs.release_pages(freed);
+        }
+        content.size = len;
+        Ok(())
+    }
+    fn read_link(&self) -> Result<String, Errno> {
+        if self.kind != InodeType::Symlink {
+            return Err(EINVAL);
+        }
+        let mut target = alloc::vec![0u8; self.size()];
+        self.read_content(0, &mut target);
+        String::from_utf8(target).map_err(|_| EINVAL)
+    }
+}
+
+/// A regular file of the tree below, until it is copied up to `upper` to
+/// be written
+struct CopyUpFile {
+    fs: Arc<TmpFs>,
+    lower: Arc<dyn Inode>,
+    upper: UPSafeCell<Option<Arc<TmpFile>>>,
+}
+
+impl CopyUpFile {
+    fn upper(&self) -> Option<Arc<TmpFile>> {
+        self.upper.exclusive_access().clone()
+    }
+    /// The copy of the file, made of its first `len` bytes if there is none
+    fn copy_up(&self, len: usize) -> Result<Arc<TmpFile>, Errno> {
+        if let Some(upper) = self.upper() {
+            return Ok(upper);
+        }
+        let upper = TmpFile::new(&self.fs, InodeType::Regular, self.lower.mode());
+        let mut buf = alloc::vec![0u8; PAGE_SIZE];
+        let mut offset = 0;
+        while offset < len {
+            let want = PAGE_SIZE.min(len - offset);
+            let n = self.lower.read_at(offset, &mut buf[..want])?;
+            if n == 0 {
+                break;
+            }
+            if upper.write_content(offset, &buf[..n])? < n {
+                return Err(ENOSPC);
+            }
+            offset += n;
+        }
+        *self.upper.exclusive_access() = Some(upper.clone());
+        Ok(upper)
+    }
+}
+
+impl Inode for CopyUpFile {
+    fn kind(&self) -> InodeType {
+        InodeType::Regular
+    }
+    fn ino(&self) -> usize {
+        self.lower.ino()
+    }
+    fn dev(&self) -> usize {
+        self.fs.dev
+    }
+    fn mode(&self) -> u32 {
+        self.lower.mode()
+    }
+    fn mtime(&self) -> i64 {
+        self.lower.mtime()
+    }
+    fn size(&self) -> usize {
+        match self.upper() {
+            Some(upper) => upper.size(),
+            None => self.lower.size(),
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        match self.upper() {
+            Some(upper) => upper.read_at(offset, buf),
+            None => self.lower.read_at(offset, buf),
+        }
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        self.copy_up(self.lower.size())?.write_at(offset, buf)
+    }
+    fn truncate(&self, len: usize) -> Result<(), Errno> {
+        self.copy_up(len.min(self.lower.size()))?.truncate(len)
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        let pages = self.content.exclusive_access().pages.len();
+        self.fs.release_pages(pages);
+    }
+}
+
+struct TmpDir {
+    ino: usize,
+    mode: u32,
+    fs: Arc<TmpFs>,
+    entries: UPSafeCell<BTreeMap<String, Arc<dyn Inode>>>,
+    /// The directory of the tree below at the same path, if any
+    lower: Option<Arc<dyn Inode>>,
+    /// The entries of `lower` that were removed
+    whiteouts: UPSafeCell<BTreeSet<String>>,
+}
+
+impl TmpDir {
+    fn new(fs: &Arc<TmpFs>, mode: u32, lower: Option<Arc<dyn Inode>>) -> Arc<Self> {
+        Arc::new(Self {
+            ino: new_ino(),
+            mode,
+            fs: fs.clone(),
+            entries: unsafe { UPSafeCell::new(BTreeMap::new()) },
+            lower,
+            whiteouts: unsafe { UPSafeCell::new(BTreeSet::new()) },
+        })
+    }
+    /// The entry `name` of the directory below, unless it was removed
+    fn lower_entry(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        if self.whiteouts.exclusive_access().contains(name) {
+            return Err(ENOENT);
+        }
+        self.lower.as_ref().ok_or(ENOENT)?.lookup(name)
+    }
+    fn insert_new(&self, name: &str, inode: Arc<dyn Inode>) -> Result<Arc<dyn Inode>, Errno> {
+        let mut entries = self.entries.exclusive_access();
+        if entries.contains_key(name) || self.lower_entry(name).is_ok() {
+            return Err(EEXIST);
+        }
+        self.whiteouts.exclusive_access().remove(name);
+        entries.insert(name.to_string(), inode.clone());
+        Ok(inode)
+    }
+}
+
+impl Inode for TmpDir {
+    fn kind(&self) -> InodeType {
+        InodeType::Directory
+    }
+    fn ino(&self) -> usize {
+        self.ino
+    }
+    fn dev(&self) -> usize {
+        self.fs.dev
+    }
+    fn mode(&self) -> u32 {
+        self.mode
+    }
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let mut entries = self.entries.exclusive_access();
+        if let Some(inode) = entries.get(name) {
+            return Ok(inode.clone());
+        }
+        // Kept as an entry from now on, so that it is copied up only once
+        let lower = self.lower_entry(name)?;
+        let inode: Arc<dyn Inode> = match lower.kind() {
+            InodeType::Directory => TmpDir::new(&self.fs, lower.mode(), Some(lower)),
+            InodeType::Regular => Arc::new(CopyUpFile {
+                fs: self.fs.clone(),
+                lower,
+                upper: unsafe { UPSafeCell::new(None) },
+            }),
+            _ => lower,
+        };
+        entries.insert(name.to_string(), inode.clone());
+        Ok(inode)
+    }
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> Result<Arc<dyn Inode>, Errno> {
+        let inode: Arc<dyn Inode> = match kind {
+            InodeType::Regular => TmpFile::new(&self.fs, kind, mode & 0o7777),
+            InodeType::Directory => TmpDir::new(&self.fs, mode & 0o7777, None),
+            _ => return Err(EPERM),
+        };
+        self.insert_new(name, inode)
+    }
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let link = TmpFile::new(&self.fs, InodeType::Symlink, 0o777);
+        link.write_content(0, target.as_bytes())?;
+        self.insert_new(name, link)
+    }
+    fn link(&self, name: &str, inode: Arc<dyn Inode>) -> Result<(), Errno> {
+        self.entries.exclusive_access().insert(name.to_string(), inode);
+        Ok(())
+    }
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        let removed = self.entries.exclusive_access().remove(name);
+        if self.lower_entry(name).is_ok() {
+            self.whiteouts.exclusive_access().insert(name.to_string());
+            return Ok(());
+        }
+        removed.map(|_| ()).ok_or(ENOENT)
+    }
+    fn read_dir(&self) -> Result<Vec<DirEntry>, Errno> {
+        let entries = self.entries.exclusive_access();
+        let mut dir_entries: Vec<DirEntry> = entries.iter().map(|(name, inode)| DirEntry {
+            name: name.clone(),
+            ino: inode.ino(),
+            kind: inode.kind(),
+        }).collect();
+        if let Some(lower) = &self.lower {
+            let whiteouts = self.whiteouts.exclusive_access();
+            dir_entries.extend(lower.read_dir()?.into_iter()
+                .filter(|entry| !entries.contains_key(&entry.name) && !whiteouts.contains(&entry.name)));
+        }
+        Ok(dir_entries)
+    }
+}
+
+/// The root directory of a new tmpfs holding at most `quota` bytes, with
+/// the permission bits `mode`
+pub fn new(quota: usize, mode: u32) -> Arc<dyn Inode> {
+    TmpDir::new(&new_fs(quota), mode, None)
+}
+
+/// The root directory of a new tmpfs holding at most `quota` bytes, over
+/// the tree of the directory `lower`
+pub fn new_over(quota: usize, lower: Arc<dyn Inode>) -> Arc<dyn Inode> {
+    TmpDir::new(&new_fs(quota), lower.mode(), Some(lower))
+}
+
+fn new_fs(quota: usize) -> Arc<TmpFs> {
+    Arc::new(TmpFs {
+        dev: new_dev(),
+        quota_pages: quota / PAGE_SIZE,
+        used_pages: unsafe { UPSafeCell::new(0) },
+    })
+}
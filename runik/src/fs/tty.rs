@@ -0,0 +1,436 @@
+//! mod tty: the console as a terminal
+//!
+//! Bytes received from the UART go through the line discipline as they
+//! arrive, which echoes them and acts on the control characters of the
+//! termios. In canonical mode input is edited a line at a time and reads
+//! take whole lines; otherwise reads take bytes as they come, as VMIN and
+//! VTIME say.
+
+use super::File;
+use crate::config::TTY_BUFFER_SIZE;
+use crate::console;
+use crate::drivers::uart;
+use crate::mm::user_ptr::UserPtr;
+use crate::sync::UPSafeCell;
+use crate::task::{ self, signal };
+use crate::time::{ self, NSEC_PER_SEC };
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{ AtomicBool, Ordering };
+use lazy_static::*;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::signal::{ SigInfo, SIGINT, SIGQUIT, SI_KERNEL };
+use linuxabi::termios::*;
+
+/// The window size until the app sets one
+const DEFAULT_WINSIZE: WinSize = WinSize { ws_row: 24, ws_col: 80, ws_xpixel: 0, ws_ypixel: 0 };
+
+struct Tty {
+    termios: Termios,
+    winsize: WinSize,
+    /// The input reads take
+    input: VecDeque<u8>,
+    /// In canonical mode, how far into `input` each line ends, where a line
+    /// ending no further than the one before is an end of file
+    ends: VecDeque<usize>,
+    /// The line being edited in canonical mode
+    line: Vec<u8>,
+    /// Whether the next byte is taken as it is, after LNEXT
+    literal: bool,
+}
+
+lazy_static! {
+    static ref TTY: UPSafeCell<Tty> = unsafe {
+        UPSafeCell::new(Tty {
+            termios: Termios::default(),
+            winsize: DEFAULT_WINSIZE,
+            input: VecDeque::new(),
+            ends: VecDeque::new(),
+            line: Vec::new(),
+            literal: false,
+        })
+    };
+}
+
+/// Whether `byte` is echoed as `^X` with ECHOCTL
+fn is_control(byte: u8) -> bool {
+    (byte < 0x20 && byte != b'\t' && byte != b'\n') || byte == 0x7f
+}
+
+impl Tty {
+    fn lflag(&self, flags: u32) -> bool {
+        self.termios.c_lflag & flags == flags
+    }
+
+    fn is_canonical(&self) -> bool {
+        self.lflag(ICANON)
+    }
+
+    /// Whether `byte` is the control character `index`, which is disabled
+    /// when 0
+    fn is_char(&self, byte: u8, index: usize) -> bool {
+        let c = self.termios.c_cc[index];
+        c != 0 && byte == c
+    }
+
+    /// Write `bytes` to the UART, processed as c_oflag says
+    fn output(&self, bytes: &[u8]) {
+        let oflag = self.termios.c_oflag;
+        if oflag & OPOST == 0 {
+            console::write_bytes(bytes);
+            return;
+        }
+        let mut out = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            match byte {
+                b'\n' if oflag & ONLCR != 0 => out.extend_from_slice(b"\r\n"),
+                b'\r' if oflag & OCRNL != 0 => out.push(b'\n'),
+                _ => out.push(byte),
+            }
+        }
+        console::write_bytes(&out);
+    }
+
+    fn echo(&self, byte: u8) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        if self.lflag(ECHOCTL) && is_control(byte) {
+            self.output(&[b'^', byte ^ 0x40]);
+        } else {
+            self.output(&[byte]);
+        }
+    }
+
+    /// Take `byte` as input, or drop it if there is no room
+    fn put(&mut self, byte: u8) {
+        // in canonical mode room is kept to end the line
+        let room = if self.is_canonical() { TTY_BUFFER_SIZE - 1 } else { TTY_BUFFER_SIZE };
+        if self.input.len() + self.line.len() >= room {
+            return;
+        }
+        if self.is_canonical() {
+            self.line.push(byte);
+        } else {
+            self.input.push_back(byte);
+        }
+        self.echo(byte);
+    }
+
+    /// Make the line being edited readable
+    fn end_line(&mut self) {
+        self.input.extend(self.line.drain(..));
+        self.ends.push_back(self.input.len());
+    }
+
+    /// Remove the last character of the line, erasing it on the screen with
+    /// ECHOE, and return its first byte
+    fn erase_char(&mut self) -> Option<u8> {
+        let mut byte = self.line.pop()?;
+        if self.termios.c_iflag & IUTF8 != 0 {
+            // the continuation bytes of a character, back to its first
+            while byte & 0xc0 == 0x80 {
+                match self.line.pop() {
+                    Some(prev) => byte = prev,
+                    None => break,
+                }
+            }
+        }
+        if self.lflag(ECHO | ECHOE) {
+            // a tab is taken as one column
+            let width = if self.lflag(ECHOCTL) && is_control(byte) { 2 } else { 1 };
+            for _ in 0..width {
+                self.output(b"\x08 \x08");
+            }
+        }
+        Some(byte)
+    }
+
+    fn erase(&mut self, byte: u8) {
+        if self.erase_char().is_some() && !self.lflag(ECHOE) {
+            self.echo(byte);
+        }
+    }
+
+    /// Erase the word before the cursor, and the blanks after it
+    fn erase_word(&mut self, byte: u8) {
+        let is_blank = |c: &u8| *c == b' ' || *c == b'\t';
+        while self.line.last().is_some_and(is_blank) {
+            self.erase_char();
+        }
+        while self.line.last().is_some_and(|c| !is_blank(c)) {
+            self.erase_char();
+        }
+        if !self.lflag(ECHOE) {
+            self.echo(byte);
+        }
+    }
+
+    /// Erase the whole line
+    fn kill(&mut self, byte: u8) {
+        if self.lflag(ECHO | ECHOE | ECHOK | ECHOKE) {
+            while self.erase_char().is_some() {}
+            return;
+        }
+        self.line.clear();
+        self.echo(byte);
+        if self.lflag(ECHO | ECHOK) {
+            self.output(b"\n");
+        }
+    }
+
+    /// Echo the line again, on a line of its own
+    fn reprint(&self, byte: u8) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        self.echo(byte);
+        self.output(b"\n");
+        for &c in &self.line {
+            self.echo(c);
+        }
+    }
+
+    fn flush_input(&mut self) {
+        self.input.clear();
+        self.ends.clear();
+        self.line.clear();
+        self.literal = false;
+    }
+
+    /// Take the byte received, returning the signal it makes if any
+    fn receive(&mut self, mut byte: u8) -> Option<u32> {
+        let iflag = self.termios.c_iflag;
+        if iflag & ISTRIP != 0 {
+            byte &= 0x7f;
+        }
+        if self.literal {
+            self.literal = false;
+            self.put(byte);
+            return None;
+        }
+        match byte {
+            b'\r' if iflag & IGNCR != 0 => return None,
+            b'\r' if iflag & ICRNL != 0 => byte = b'\n',
+            b'\n' if iflag & INLCR != 0 => byte = b'\r',
+            _ => {},
+        }
+        if self.lflag(ISIG) {
+            let signal = if self.is_char(byte, VINTR) {
+                Some(SIGINT)
+            } else if self.is_char(byte, VQUIT) {
+                Some(SIGQUIT)
+            } else {
+                None
+            };
+            if signal.is_some() {
+                if !self.lflag(NOFLSH) {
+                    self.flush_input();
+                }
+                self.echo(byte);
+                return signal;
+            }
+            if self.is_char(byte, VSUSP) {
+                // there is no job control to stop the app with
+                return None;
+            }
+        }
+        if !self.is_canonical() {
+            self.put(byte);
+            return None;
+        }
+        if self.lflag(IEXTEN) {
+            if self.is_char(byte, VLNEXT) {
+                self.literal = true;
+                return None;
+            }
+            if self.is_char(byte, VWERASE) {
+                self.erase_word(byte);
+                return None;
+            }
+            if self.is_char(byte, VREPRINT) {
+                self.reprint(byte);
+                return None;
+            }
+        }
+        if self.is_char(byte, VERASE) {
+            self.erase(byte);
+        } else if self.is_char(byte, VKILL) {
+            self.kill(byte);
+        } else if self.is_char(byte, VEOF) {
+            self.end_line();
+        } else if byte == b'\n' || self.is_char(byte, VEOL) || self.is_char(byte, VEOL2) {
+            if self.input.len() + self.line.len() < TTY_BUFFER_SIZE {
+                self.line.push(byte);
+                if byte == b'\n' {
+                    if self.lflag(ECHO) || self.lflag(ECHONL) {
+                        self.output(b"\n");
+                    }
+                } else {
+                    self.echo(byte);
+                }
+                self.end_line();
+            }
+        } else {
+            self.put(byte);
+        }
+        None
+    }
+
+    /// Read the rest of the first line into `buf`, or nothing at its end of
+    /// file, if a line has been ended
+    fn read_line(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let &end = self.ends.front()?;
+        let n = end.min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.input.drain(..n)) {
+            *dst = src;
+        }
+        for end in self.ends.iter_mut() {
+            *end -= n;
+        }
+        if self.ends[0] == 0 {
+            self.ends.pop_front();
+        }
+        Some(n)
+    }
+
+    /// Read the bytes there are into `buf`
+    fn read_raw(&mut self, buf: &mut [u8]) -> usize {
+        let n = self.input.len().min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.input.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+
+    fn set_termios(&mut self, termios: Termios) {
+        let was_canonical = self.is_canonical();
+        self.termios = termios;
+        match (was_canonical, self.is_canonical()) {
+            // what there is of the line can be read at once
+            (true, false) => {
+                self.input.extend(self.line.drain(..));
+                self.ends.clear();
+            }
+            (false, true) if !self.input.is_empty() => self.ends.push_back(self.input.len()),
+            _ => {},
+        }
+    }
+}
+
+/// Have the input from the UART come to the terminal
+pub fn init() {
+    uart::set_receiver(receive);
+}
+
+fn receive(byte: u8) {
+    let signo = TTY.exclusive_access().receive(byte);
+    if let Some(signo) = signo {
+        signal::send_to_app(SigInfo::new(signo, SI_KERNEL));
+    }
+}
+
+/// The terminal, which stdin, stdout and stderr are open on
+pub struct Console {
+    nonblocking: AtomicBool,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self { nonblocking: AtomicBool::new(false) }
+    }
+}
+
+impl File for Console {
+    /// Read a line in canonical mode, or otherwise the bytes there are,
+    /// waiting for them as VMIN and VTIME say
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let start = time::monotonic_ns();
+        let mut last = start;
+        let mut n = 0;
+        loop {
+            let mut tty = TTY.exclusive_access();
+            if tty.is_canonical() {
+                if let Some(got) = tty.read_line(&mut buf[n..]) {
+                    return Ok(n + got);
+                }
+            } else {
+                let now = time::monotonic_ns();
+                let got = tty.read_raw(&mut buf[n..]);
+                if got > 0 {
+                    n += got;
+                    last = now;
+                }
+                let min = tty.termios.c_cc[VMIN] as usize;
+                let timeout = tty.termios.c_cc[VTIME] as u64 * NSEC_PER_SEC / 10;
+                // with VMIN the time counts from the last byte, and without
+                // it from the read
+                let done = if min == 0 {
+                    n > 0 || timeout == 0 || now - start >= timeout
+                } else {
+                    n >= min.min(buf.len()) || (n > 0 && timeout > 0 && now - last >= timeout)
+                };
+                if done {
+                    return Ok(n);
+                }
+            }
+            drop(tty);
+            if self.nonblocking.load(Ordering::Relaxed) {
+                return if n > 0 { Ok(n) } else { Err(EAGAIN) };
+            }
+            if signal::interrupted() {
+                return if n > 0 { Ok(n) } else { Err(EINTR) };
+            }
+            task::wait_for_event();
+        }
+    }
+    /// Write `buf` as it is, but for the processing of c_oflag
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        TTY.exclusive_access().output(buf);
+        Ok(buf.len())
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+    fn ioctl(&self, cmd: u32, arg: usize) -> Result<usize, Errno> {
+        match cmd {
+            TCGETS => {
+                let termios = TTY.exclusive_access().termios;
+                UserPtr::<Termios>::new(arg).write(termios)?;
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                let termios = UserPtr::<Termios>::new(arg).read()?;
+                if cmd != TCSETS {
+                    console::flush();
+                }
+                let mut tty = TTY.exclusive_access();
+                if cmd == TCSETSF {
+                    tty.flush_input();
+                }
+                tty.set_termios(termios);
+            }
+            TIOCGWINSZ => {
+                let winsize = TTY.exclusive_access().winsize;
+                UserPtr::<WinSize>::new(arg).write(winsize)?;
+            }
+            TIOCSWINSZ => {
+                TTY.exclusive_access().winsize = UserPtr::<WinSize>::new(arg).read()?;
+            }
+            FIONREAD => {
+                let len = TTY.exclusive_access().input.len();
+                UserPtr::<i32>::new(arg).write(len as i32)?;
+            }
+            TCFLSH => match arg {
+                TCIFLUSH | TCIOFLUSH => TTY.exclusive_access().flush_input(),
+                // output is sent as it is written
+                TCOFLUSH => {},
+                _ => return Err(EINVAL),
+            },
+            _ => return Err(ENOTTY),
+        }
+        Ok(0)
+    }
+}
@@ -1,20 +1,42 @@
 //! Kernel stack
+//!
+//! Every thread has a kernel stack of its own, which its traps run on, with
+//! the [`TrapContext`] of the thread at the top.
 use crate::arch::trap::TrapContext;
-use crate::plat::qemu::MEMORY_END;
 use crate::arch::paging::PAGE_SIZE;
-pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
+use crate::mm::frame::{ frame_alloc_contiguous, FrameTracker };
+use alloc::vec::Vec;
+/// With no guard page below, the stack must hold the deepest path through
+/// the kernel, like a trap that delivers a signal frame or reads through a
+/// filesystem
+pub const KERNEL_STACK_SIZE: usize = 4096 * 4;
 
-/// Return (bottom, top) of a kernel stack in kernel space.
-pub fn kernel_stack_position() -> (usize, usize) {
-    let top = MEMORY_END - (KERNEL_STACK_SIZE + PAGE_SIZE);
-    let bottom = top - KERNEL_STACK_SIZE;
-    (bottom, top)
+pub struct KernelStack {
+    frames: Vec<FrameTracker>,
 }
 
-pub fn push_context(cx: TrapContext) -> &'static mut TrapContext {
-    let cx_ptr = (kernel_stack_position().1 - core::mem::size_of::<TrapContext>()) as *mut TrapContext;
-    unsafe {
-        *cx_ptr = cx;
+impl KernelStack {
+    /// A stack in fresh frames, or None if there are not enough
+    pub fn new() -> Option<Self> {
+        let frames = frame_alloc_contiguous(KERNEL_STACK_SIZE / PAGE_SIZE)?;
+        Some(Self { frames })
+    }
+
+    /// Return (bottom, top) of the stack in kernel space.
+    pub fn position(&self) -> (usize, usize) {
+        let bottom = self.frames[0].ppn.0 * PAGE_SIZE;
+        (bottom, bottom + KERNEL_STACK_SIZE)
+    }
+
+    /// The trap context at the top of the stack
+    pub fn trap_context(&self) -> &'static mut TrapContext {
+        let cx_ptr = (self.position().1 - core::mem::size_of::<TrapContext>()) as *mut TrapContext;
+        unsafe { cx_ptr.as_mut().unwrap() }
+    }
+
+    pub fn push_context(&self, cx: TrapContext) -> &'static mut TrapContext {
+        let cx_ref = self.trap_context();
+        *cx_ref = cx;
+        cx_ref
     }
-    unsafe { cx_ptr.as_mut().unwrap() }
 }
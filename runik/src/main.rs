@@ -25,12 +25,18 @@ use core::arch::global_asm;
 mod console;
 
 pub mod app;
+pub mod config;
 pub mod kernel_stack;
 mod kernel_panic;
 mod sbi;
 mod sync;
 pub mod mm;
+pub mod fs;
+pub mod drivers;
+pub mod net;
 pub mod syscall;
+pub mod task;
+pub mod time;
 pub mod arch;
 pub mod plat;
 
@@ -53,15 +59,21 @@ fn clear_bss() {
 pub fn rust_main() -> () {
     clear_bss();
     arch::trap::init();
+    arch::time::init();
     mm::init();
+    drivers::init();
+    net::init();
+    fs::init();
     // mm::kernel_heap::heap_test();
     let app = app::App::load_from_img();
-    let user_stack_base = crate::mm::addr_space::kspace_load_elf(&app.elf_file);
-    println!("[kernel] [debug] user_sp: {:p}", user_stack_base as *const usize);
+    let (user_sp, user_tp) = crate::mm::addr_space::kspace_load_elf(&app.elf_file, &app.argv, &app.envp);
+    println!("[kernel] [debug] user_sp: {:p}", user_sp as *const usize);
+    println!("[kernel] [debug] user_tp: {:p}", user_tp as *const usize);
+    task::signal::init();
     crate::mm::addr_space::kspace_activate();
     println!("[kernel] [trace] Paging mode activated");
     println!("[kernel] [info] Running user's application");
-    app.run(user_stack_base);
+    app.run(user_sp, user_tp);
     /*let kernel_space: Arc<UPIntrFreeCell<AddrSpace>> =
         Arc::new(unsafe { UPIntrFreeCell::new() });*/
 }
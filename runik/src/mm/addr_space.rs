@@ -4,16 +4,29 @@ use super::frame::{ frame_alloc, FrameTracker };
 use super::page_table::{ PTEFlags, PageTable, PageTableEntry };
 use super::addr::{ PhysPageNum, VirtAddr, VirtPageNum, StepByOne, VPNRange };
 use crate::plat::qemu::{ MMIO, MEMORY_END };
-use crate::arch::paging::PAGE_SIZE;
+use crate::arch::paging::{ PAGE_SIZE, VA_WIDTH, flush_icache, flush_tlb };
+use crate::config::{
+    USER_STACK_TOP, USER_STACK_SIZE, USER_STACK_RLIMIT, USER_HEAP_LIMIT, USER_MMAP_BASE, USER_MMAP_TOP,
+};
+use crate::arch::hwcap::HWCAP;
+use crate::arch::time::get_clock;
+use crate::fs::Inode;
 use crate::sync::UPSafeCell;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::fmt;
 use lazy_static::*;
 use riscv::register::satp;
 use bitflags::bitflags;
 use xmas_elf::ElfFile;
+use linuxabi::auxv::*;
+use linuxabi::errno::Errno::{ self, * };
+
+/// The bytes below tp kept for the thread control block of the libc, as
+/// the `tcbhead_t` of glibc on RISC-V holds the dtv and a private pointer
+const TLS_TCB_SIZE: usize = 16;
 
 extern "C" {
     fn stext();
@@ -28,7 +41,7 @@ extern "C" {
 }
 
 bitflags! {
-    #[derive(Copy, Clone, Debug)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
     pub struct MapPermission: u8 {
         const R = 1 << 1;
         const W = 1 << 2;
@@ -40,7 +53,17 @@ bitflags! {
 #[derive(Debug)]
 pub struct AddrSpace {
     page_table: PageTable,
+    /// sorted by start, where the empty heap comes before a segment starting
+    /// at the same page
     segments: Vec<Segment>,
+    /// start of the heap, right after the highest PT_LOAD segment
+    heap_bottom: VirtAddr,
+    /// the program break, end of the heap
+    brk: VirtAddr,
+    /// top of the user stack, which grows downwards
+    stack_top: VirtAddr,
+    /// the maximum size of the user stack
+    stack_rlimit: usize,
 }
 
 impl AddrSpace {
@@ -48,6 +71,10 @@ impl AddrSpace {
         Self {
             page_table: PageTable::new(),
             segments: Vec::new(),
+            heap_bottom: VirtAddr(0),
+            brk: VirtAddr(0),
+            stack_top: VirtAddr(0),
+            stack_rlimit: USER_STACK_RLIMIT,
         }
     }
     pub fn token(&self) -> usize {
@@ -72,7 +99,333 @@ impl AddrSpace {
         if let Some((va, data)) = data {
             map_area.copy_data(&mut self.page_table, va, data);
         }
-        self.segments.push(map_area);
+        self.insert(map_area);
+    }
+    /// Put the mapped `seg` among the segments, after those starting at or
+    /// below it
+    fn insert(&mut self, seg: Segment) {
+        let idx = self.segments.partition_point(|other| other.vpn_range.get_start() <= seg.vpn_range.get_start());
+        self.segments.insert(idx, seg);
+    }
+    /// Whether any segment overlaps the pages in [start, end)
+    fn overlaps(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        self.segments.iter().any(|seg| {
+            seg.vpn_range.get_start() < end && start < seg.vpn_range.get_end()
+        })
+    }
+    /// Move the program break to `new_brk` and return the new break. On
+    /// failure, or for `brk(0)`, the current break is returned instead, as
+    /// the Linux syscall does.
+    pub fn set_brk(&mut self, new_brk: VirtAddr) -> VirtAddr {
+        if new_brk < self.heap_bottom
+            || usize::from(new_brk) - usize::from(self.heap_bottom) > USER_HEAP_LIMIT
+        {
+            return self.brk;
+        }
+        let heap_start_vpn = self.heap_bottom.floor();
+        let (old_end, new_end) = (self.brk.ceil(), new_brk.ceil());
+        if new_end > old_end {
+            if self.overlaps(old_end, new_end) {
+                return self.brk;
+            }
+        }
+        if new_end != old_end {
+            // The heap may have been cut by munmap, leaving no room to move
+            let heap = match self.segments.iter_mut().find(|seg| {
+                seg.vpn_range.get_start() == heap_start_vpn && seg.vpn_range.get_end() == old_end
+            }) {
+                Some(heap) => heap,
+                None => return self.brk,
+            };
+            if new_end > old_end {
+                heap.append_to(&mut self.page_table, new_end);
+            } else {
+                heap.shrink_to(&mut self.page_table, new_end);
+                flush_tlb();
+            }
+        }
+        self.brk = new_brk;
+        self.brk
+    }
+    /// Whether [start, end) lies in the lower half of the address space,
+    /// where the application lives
+    fn in_user_half(start: VirtPageNum, end: VirtPageNum) -> bool {
+        start.0 > 0 && start <= end && end.0 <= (1 << (VA_WIDTH - 1)) / PAGE_SIZE
+    }
+    /// The end of the `pages` pages from `start`, failing with ENOMEM if it
+    /// is past the address space
+    fn end_of(start: VirtPageNum, pages: usize) -> Result<VirtPageNum, Errno> {
+        start.0.checked_add(pages).map(VirtPageNum).ok_or(ENOMEM)
+    }
+    /// Whether the segment is the heap, which must stay a single segment
+    /// for brk to resize it
+    fn is_heap(&self, seg: &Segment) -> bool {
+        seg.vpn_range.get_start() == self.heap_bottom.floor()
+    }
+    /// Whether the segment is the user stack, which must stay a single
+    /// segment to grow downwards
+    fn is_stack(&self, seg: &Segment) -> bool {
+        self.stack_top.0 != 0 && seg.vpn_range.get_end() == self.stack_top.floor()
+    }
+    /// The pages reserved for the stack to grow into, with a guard page
+    /// below the lowest one the rlimit allows
+    fn stack_reserved(&self) -> VPNRange {
+        let top = self.stack_top.floor();
+        let pages = self.stack_rlimit / PAGE_SIZE + 1;
+        VPNRange::new(VirtPageNum(top.0.saturating_sub(pages)), top)
+    }
+    pub fn stack_rlimit(&self) -> usize {
+        self.stack_rlimit
+    }
+    /// Set the maximum size of the user stack, which can not be lower than
+    /// its current size, nor raised above the hard limit
+    pub fn set_stack_rlimit(&mut self, rlimit: usize) -> Result<(), Errno> {
+        let top = self.stack_top.floor();
+        let current = self.segments
+            .iter()
+            .find(|seg| self.is_stack(seg))
+            .map_or(0, |seg| (top.0 - seg.vpn_range.get_start().0) * PAGE_SIZE);
+        if rlimit > USER_STACK_RLIMIT {
+            return Err(EPERM);
+        }
+        if rlimit < current {
+            return Err(EINVAL);
+        }
+        self.stack_rlimit = rlimit;
+        Ok(())
+    }
+    /// Grow the stack downwards to cover `vpn`, keeping an unmapped guard
+    /// page below it. Faults in the guard page under the rlimit, or growing
+    /// into another segment, are stack overflows.
+    fn grow_stack(&mut self, vpn: VirtPageNum) -> Result<(), PageFaultError> {
+        let reserved = self.stack_reserved();
+        if vpn < reserved.get_start() || vpn >= reserved.get_end() {
+            return Err(PageFaultError::Invalid);
+        }
+        let idx = self.segments
+            .iter()
+            .position(|seg| self.is_stack(seg))
+            .ok_or(PageFaultError::Invalid)?;
+        let bottom = self.segments[idx].vpn_range.get_start();
+        if vpn >= bottom {
+            return Err(PageFaultError::Invalid);
+        }
+        if vpn == reserved.get_start() || self.overlaps(VirtPageNum(vpn.0 - 1), bottom) {
+            return Err(PageFaultError::StackOverflow);
+        }
+        self.segments[idx].prepend_to(vpn);
+        Ok(())
+    }
+    /// Find `pages` free pages for mmap, searching downwards from the top
+    /// of the mmap area for the first gap that fits.
+    fn find_free_area(&self, pages: usize) -> Option<VirtPageNum> {
+        let bottom = VirtAddr::from(USER_MMAP_BASE).floor();
+        let mut top = VirtAddr::from(USER_MMAP_TOP).floor();
+        let mut ranges: Vec<VPNRange> = self.segments.iter().map(|seg| seg.vpn_range).collect();
+        ranges.sort_by(|a, b| b.get_end().cmp(&a.get_end()));
+        for range in ranges {
+            if range.get_end() <= bottom {
+                break;
+            }
+            if range.get_end() <= top && top.0 - range.get_end().0 >= pages {
+                return Some(VirtPageNum(top.0 - pages));
+            }
+            top = top.min(range.get_start());
+        }
+        if top >= bottom && top.0 - bottom.0 >= pages {
+            Some(VirtPageNum(top.0 - pages))
+        } else {
+            None
+        }
+    }
+    /// Split the segment crossing `vpn`, if any, into two at `vpn`
+    fn split_at(&mut self, vpn: VirtPageNum) {
+        if let Some(idx) = self.segments.iter().position(|seg| {
+            seg.vpn_range.get_start() < vpn && vpn < seg.vpn_range.get_end()
+        }) {
+            let tail = self.segments[idx].split_off(vpn);
+            self.segments.insert(idx + 1, tail);
+        }
+    }
+    /// Merge the user segments in [start, end), and those right before and
+    /// after it, with their neighbours sharing the same type and permission
+    fn merge_around(&mut self, start: VirtPageNum, end: VirtPageNum) {
+        let mut i = self.segments.partition_point(|seg| seg.vpn_range.get_end() < start);
+        while i + 1 < self.segments.len() && self.segments[i + 1].vpn_range.get_start() <= end {
+            let (a, b) = (&self.segments[i], &self.segments[i + 1]);
+            if a.vpn_range.get_end() == b.vpn_range.get_start()
+                && !self.is_heap(a)
+                && !self.is_heap(b)
+                && !self.is_stack(a)
+                && !self.is_stack(b)
+                && a.can_merge(b)
+            {
+                let b = self.segments.remove(i + 1);
+                self.segments[i].absorb(b);
+            } else {
+                i += 1;
+            }
+        }
+    }
+    /// Map `pages` pages with `perm` and return the start. The pages are
+    /// filled from `file` at its offset if given, and zeroed otherwise. With
+    /// `fixed`, the pages are placed exactly at `start`, replacing user
+    /// mappings there unless `noreplace`. Otherwise `start` is only a hint.
+    pub fn mmap(
+        &mut self,
+        start: VirtPageNum,
+        pages: usize,
+        perm: MapPermission,
+        fixed: bool,
+        noreplace: bool,
+        file: Option<(Arc<dyn Inode>, usize)>,
+    ) -> Result<VirtPageNum, Errno> {
+        let end = Self::end_of(start, pages);
+        let start = if fixed {
+            let end = end?;
+            if !Self::in_user_half(start, end) {
+                return Err(EINVAL);
+            }
+            if self.overlaps(start, end) {
+                if noreplace {
+                    return Err(EEXIST);
+                }
+                self.munmap(start, pages)?;
+            }
+            start
+        } else if end.is_ok_and(|end| {
+            Self::in_user_half(start, end)
+                && !self.overlaps(start, end)
+                && !(start < self.stack_reserved().get_end() && self.stack_reserved().get_start() < end)
+        }) {
+            start
+        } else {
+            self.find_free_area(pages).ok_or(ENOMEM)?
+        };
+        let mut seg = Segment::new(start.into(), VirtPageNum(start.0 + pages).into(), MapType::Lazy, perm);
+        if let Some((inode, offset)) = file {
+            seg = seg.with_file(start.into(), inode, offset);
+        }
+        self.push(seg, None);
+        self.merge_around(start, VirtPageNum(start.0 + pages));
+        Ok(start)
+    }
+    /// Unmap the user pages in [start, start + pages), splitting segments
+    /// that are partially covered. Unmapped holes in the range are fine.
+    pub fn munmap(&mut self, start: VirtPageNum, pages: usize) -> Result<(), Errno> {
+        let end = Self::end_of(start, pages).map_err(|_| EINVAL)?;
+        if !Self::in_user_half(start, end) {
+            return Err(EINVAL);
+        }
+        if self.segments.iter().any(|seg| {
+            !seg.is_user() && seg.vpn_range.get_start() < end && start < seg.vpn_range.get_end()
+        }) {
+            return Err(EINVAL);
+        }
+        self.split_at(start);
+        self.split_at(end);
+        let page_table = &mut self.page_table;
+        self.segments.retain_mut(|seg| {
+            if start <= seg.vpn_range.get_start() && seg.vpn_range.get_end() <= end {
+                seg.unmap(page_table);
+                false
+            } else {
+                true
+            }
+        });
+        flush_tlb();
+        Ok(())
+    }
+    /// Change the permission of the user pages in [start, start + pages),
+    /// all of which must be mapped.
+    pub fn mprotect(&mut self, start: VirtPageNum, pages: usize, perm: MapPermission) -> Result<(), Errno> {
+        let end = Self::end_of(start, pages)?;
+        if !Self::in_user_half(start, end) {
+            return Err(ENOMEM);
+        }
+        let covered: usize = self.segments
+            .iter()
+            .filter(|seg| seg.is_user())
+            .map(|seg| {
+                let l = seg.vpn_range.get_start().max(start);
+                let r = seg.vpn_range.get_end().min(end);
+                r.0.saturating_sub(l.0)
+            })
+            .sum();
+        if covered != pages {
+            return Err(ENOMEM);
+        }
+        self.split_at(start);
+        self.split_at(end);
+        for seg in self.segments.iter_mut() {
+            if start <= seg.vpn_range.get_start() && seg.vpn_range.get_end() <= end {
+                seg.set_perm(&mut self.page_table, perm);
+            }
+        }
+        flush_tlb();
+        self.merge_around(start, end);
+        Ok(())
+    }
+    /// Resize the user mapping [old_start, old_start + old_pages), which
+    /// must lie in a single segment, to `new_pages`. It grows in place when
+    /// possible, and is otherwise moved if `may_move`, or moved to `new_start`
+    /// when given. The frames are kept when moving, so no data is copied.
+    pub fn mremap(
+        &mut self,
+        old_start: VirtPageNum,
+        old_pages: usize,
+        new_pages: usize,
+        may_move: bool,
+        new_start: Option<VirtPageNum>,
+    ) -> Result<VirtPageNum, Errno> {
+        let old_end = Self::end_of(old_start, old_pages).map_err(|_| EFAULT)?;
+        if !self.segments.iter().any(|seg| {
+            seg.is_user()
+                && !self.is_heap(seg)
+                && seg.vpn_range.get_start() <= old_start
+                && old_end <= seg.vpn_range.get_end()
+        }) {
+            return Err(EFAULT);
+        }
+        if let Some(new_start) = new_start {
+            let new_end = Self::end_of(new_start, new_pages)?;
+            if !Self::in_user_half(new_start, new_end) || (new_start < old_end && old_start < new_end) {
+                return Err(EINVAL);
+            }
+            self.munmap(new_start, new_pages)?;
+        } else if new_pages <= old_pages {
+            self.munmap(VirtPageNum(old_start.0 + new_pages), old_pages - new_pages)?;
+            return Ok(old_start);
+        }
+        let grow_end = Self::end_of(old_start, new_pages);
+        let target = match new_start {
+            Some(new_start) => new_start,
+            None if grow_end.is_ok_and(|grow_end| {
+                Self::in_user_half(old_start, grow_end) && !self.overlaps(old_end, grow_end)
+            }) => old_start,
+            None if may_move => self.find_free_area(new_pages).ok_or(ENOMEM)?,
+            None => return Err(ENOMEM),
+        };
+        self.split_at(old_start);
+        self.split_at(old_end);
+        if new_pages < old_pages {
+            self.munmap(VirtPageNum(old_start.0 + new_pages), old_pages - new_pages)?;
+        }
+        let idx = self.segments
+            .iter()
+            .position(|seg| seg.vpn_range.get_start() == old_start && old_start < seg.vpn_range.get_end())
+            .expect("Impossible");
+        // taken out to be put back where it lands
+        let mut seg = self.segments.remove(idx);
+        if target != old_start {
+            seg.move_to(&mut self.page_table, target);
+        }
+        let target_end = VirtPageNum(target.0 + new_pages);
+        seg.append_to(&mut self.page_table, target_end);
+        self.insert(seg);
+        flush_tlb();
+        self.merge_around(target, target_end);
+        Ok(target)
     }
     /// Without kernel stacks.
     pub fn new_with_kernel() -> Self  {
@@ -149,16 +502,29 @@ impl AddrSpace {
         }
         addr_space
     }
-    /// also returns user_sp_base (brk).
-    pub fn load_elf(&mut self, elf: &ElfFile) -> usize {
+    /// Map the program headers of `elf` and a user stack, on which the
+    /// initial process stack is built with `argv`, `envp` and the auxiliary
+    /// vector. Returns the initial user sp, pointing to `argc`, and the
+    /// initial tp, pointing to the TLS block if the elf has PT_TLS.
+    pub fn load_elf(&mut self, elf: &ElfFile<'static>, argv: &[&str], envp: &[&str]) -> (usize, usize) {
         // map program headers of elf, with U flag
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
         assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "Invalid elf!");
         let ph_count = elf_header.pt2.ph_count();
+        let ph_offset = elf_header.pt2.ph_offset();
+        let mut phdr_va: Option<u64> = None;
+        let mut tls: Option<(&'static [u8], usize, usize)> = None;
         let mut max_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
             let ph = elf.program_header(i).unwrap();
+            if ph.get_type().unwrap() == xmas_elf::program::Type::Phdr {
+                phdr_va = Some(ph.virtual_addr());
+            }
+            if ph.get_type().unwrap() == xmas_elf::program::Type::Tls {
+                let template = &elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize];
+                tls = Some((template, ph.mem_size() as usize, ph.align() as usize));
+            }
             if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
                 let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
                 let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
@@ -176,35 +542,237 @@ impl AddrSpace {
                 }
                 println!("[kernel] mapping app section [{:#x} {:#x}) -> [{:?} {:?}), permission: {:?}",
                     ph.offset(), ph.offset() + ph.file_size(), start_va, end_va, map_perm);
-                let map_area = Segment::new(start_va, end_va, MapType::Framed, map_perm);
-                max_end_vpn = map_area.vpn_range.get_end();
-                self.push(
-                    map_area,
-                    Some((start_va, (&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]))),
+                // Pages are filled from the file on first touch, and the
+                // .bss part beyond the file data is left zeroed
+                let map_area = Segment::new(start_va, end_va, MapType::Lazy, map_perm).with_data(
+                    start_va,
+                    &elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize],
                 );
-                // We should zero out the .bss section if there is any
-                if ph.file_size() < ph.mem_size() {
-                    let bss_start_va = start_va + (ph.file_size() as isize);
-                    let bss_size = (ph.mem_size() - ph.file_size()) as usize;
-                    self.segments.last_mut().expect("Impossible").zero_out(
-                        &mut self.page_table, bss_start_va, bss_size);
+                max_end_vpn = map_area.vpn_range.get_end();
+                self.push(map_area, None);
+                // Without PT_PHDR, the program headers are found in the
+                // segment that loads them from the file
+                if phdr_va.is_none()
+                    && ph.offset() <= ph_offset
+                    && ph_offset < ph.offset() + ph.file_size()
+                {
+                    phdr_va = Some(ph.virtual_addr() + ph_offset - ph.offset());
                 }
             }
         }
-        // Construct a RW user stack based on max_end_vpn
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let user_stack_base_va: VirtAddr = (usize::from(max_end_va) + PAGE_SIZE - 0x10).into();
-        println!("[kernel] mapping app stack {:?} {:?}", max_end_va, user_stack_base_va);
+        // The heap starts empty right after the last segment, growing by brk
+        self.heap_bottom = max_end_vpn.into();
+        self.brk = self.heap_bottom;
+        println!("[kernel] app heap starts at {:?}", self.heap_bottom);
         self.push(
             Segment::new(
-                max_end_va,
-                user_stack_base_va,
-                MapType::Framed,
+                self.heap_bottom,
+                self.brk,
+                MapType::Lazy,
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
         );
-        user_stack_base_va.into()
+        // Construct a RW user stack at the top of user space, growing
+        // downwards on page faults up to the rlimit
+        let user_stack_top: VirtAddr = USER_STACK_TOP.into();
+        let user_stack_bottom: VirtAddr = user_stack_top - (USER_STACK_SIZE as isize);
+        self.stack_top = user_stack_top;
+        println!("[kernel] mapping app stack {:?} {:?}", user_stack_bottom, user_stack_top);
+        self.push(
+            Segment::new(
+                user_stack_bottom,
+                user_stack_top,
+                MapType::Lazy,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        );
+        let auxv = [
+            (AT_PHDR, phdr_va.unwrap_or(0) as usize),
+            (AT_PHENT, elf_header.pt2.ph_entry_size() as usize),
+            (AT_PHNUM, ph_count as usize),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, 0),
+            (AT_FLAGS, 0),
+            (AT_ENTRY, elf_header.pt2.entry_point() as usize),
+            (AT_UID, 0),
+            (AT_EUID, 0),
+            (AT_GID, 0),
+            (AT_EGID, 0),
+            (AT_HWCAP, HWCAP),
+            (AT_CLKTCK, 100),
+            (AT_SECURE, 0),
+        ];
+        let tp = match tls {
+            Some((template, mem_size, align)) => self.init_tls(template, mem_size, align),
+            None => 0,
+        };
+        (self.init_user_stack(user_stack_top, argv, envp, &auxv), tp)
+    }
+    /// Instantiate the TLS template of PT_TLS in fresh pages, returning the
+    /// tp of the first thread. As in variant I of RISC-V, the TLS block
+    /// starts at tp, aligned as PT_TLS asks, with the TCB right below it.
+    /// The part of the block beyond `template` is left zeroed.
+    fn init_tls(&mut self, template: &[u8], mem_size: usize, align: usize) -> usize {
+        let align = align.max(16);
+        let offset = TLS_TCB_SIZE.next_multiple_of(align);
+        let pages = (offset + mem_size).div_ceil(PAGE_SIZE);
+        let start = self
+            .find_free_area(pages)
+            .expect("No room for the TLS block of the app");
+        self.push(
+            Segment::new(
+                start.into(),
+                VirtPageNum(start.0 + pages).into(),
+                MapType::Lazy,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        );
+        let tp = usize::from(VirtAddr::from(start)) + offset;
+        println!("[kernel] mapping app TLS block at {:#x}, {:#x} bytes", tp, mem_size);
+        self.write_bytes(tp.into(), template);
+        tp
+    }
+    /// Map `code` for the app to run, in pages of its own, and return where
+    /// it starts
+    pub fn map_code(&mut self, code: &'static [u8]) -> usize {
+        let pages = code.len().div_ceil(PAGE_SIZE);
+        let start = self
+            .find_free_area(pages)
+            .expect("No room for code of the kernel in the app");
+        let start_va: VirtAddr = start.into();
+        self.push(
+            Segment::new(
+                start_va,
+                VirtPageNum(start.0 + pages).into(),
+                MapType::Lazy,
+                MapPermission::R | MapPermission::X | MapPermission::U,
+            )
+            .with_data(start_va, code),
+            None,
+        );
+        start_va.into()
+    }
+    /// Build the initial process stack below `stack_top` as Linux does. From
+    /// high to low addresses, it holds the `AT_EXECFN` string, the envp and
+    /// argv strings, 16 random bytes for `AT_RANDOM`, then after aligning,
+    /// the auxv pairs ending with `AT_NULL`, the NULL-ended envp and argv
+    /// pointer arrays and finally argc, at a 16-byte aligned sp.
+    fn init_user_stack(
+        &mut self,
+        stack_top: VirtAddr,
+        argv: &[&str],
+        envp: &[&str],
+        auxv: &[(usize, usize)],
+    ) -> usize {
+        let mut sp = usize::from(stack_top);
+        let execfn = match argv.first() {
+            Some(arg0) => self.push_str_to_stack(&mut sp, arg0),
+            None => 0,
+        };
+        let mut envp_ptrs = Vec::new();
+        for env in envp.iter().rev() {
+            envp_ptrs.push(self.push_str_to_stack(&mut sp, env));
+        }
+        envp_ptrs.reverse();
+        let mut argv_ptrs = Vec::new();
+        for arg in argv.iter().rev() {
+            argv_ptrs.push(self.push_str_to_stack(&mut sp, arg));
+        }
+        argv_ptrs.reverse();
+        let random = self.push_to_stack(&mut sp, &random_bytes());
+
+        let mut words: Vec<usize> = Vec::new();
+        words.push(argv.len());
+        words.extend(argv_ptrs);
+        words.push(0);
+        words.extend(envp_ptrs);
+        words.push(0);
+        for &(key, value) in auxv {
+            words.push(key);
+            words.push(value);
+        }
+        words.extend([AT_RANDOM, random, AT_EXECFN, execfn, AT_NULL, 0]);
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_ne_bytes()).collect();
+        sp = (sp - bytes.len()) & !0xf;
+        self.write_bytes(sp.into(), &bytes);
+        sp
+    }
+    /// Push `data` onto the stack at `sp`, returning its address.
+    fn push_to_stack(&mut self, sp: &mut usize, data: &[u8]) -> usize {
+        *sp -= data.len();
+        self.write_bytes((*sp).into(), data);
+        *sp
+    }
+    /// Push a NUL-terminated C string onto the stack at `sp`, returning its address.
+    fn push_str_to_stack(&mut self, sp: &mut usize, s: &str) -> usize {
+        self.push_to_stack(sp, &[0]);
+        self.push_to_stack(sp, s.as_bytes())
+    }
+    /// Copy `data` to the mapped pages starting at `start` through the page table.
+    fn write_bytes(&mut self, start: VirtAddr, data: &[u8]) {
+        let mut i = 0;
+        let mut cur = start;
+        while i < data.len() {
+            let ppn = self.user_page(cur.floor(), AccessType::Write)
+                .expect("Writing to unmapped user memory");
+            let page = &mut ppn.get_bytes_array()[cur.page_offset()..];
+            let len = page.len().min(data.len() - i);
+            page[..len].copy_from_slice(&data[i..i + len]);
+            cur += len as isize;
+            i += len;
+        }
+    }
+    /// The physical page of the user page `vpn` if its segment and its PTE
+    /// allow `access` from user mode, populating it first if it is lazily
+    /// mapped
+    pub fn user_page(&mut self, vpn: VirtPageNum, access: AccessType) -> Option<PhysPageNum> {
+        if !self.segments.iter().any(|seg| {
+            seg.is_user() && seg.vpn_range.get_start() <= vpn && vpn < seg.vpn_range.get_end()
+        }) {
+            self.grow_stack(vpn).ok()?;
+        }
+        let seg = self.segments.iter_mut().find(|seg| {
+            seg.is_user() && seg.vpn_range.get_start() <= vpn && vpn < seg.vpn_range.get_end()
+        })?;
+        if !seg.allows(access) {
+            return None;
+        }
+        let ppn = seg.populate(&mut self.page_table, vpn).ok()?;
+        let pte = self.page_table.translate(vpn)?;
+        let permitted = match access {
+            AccessType::Read => pte.readable(),
+            AccessType::Write => pte.writable(),
+            AccessType::Execute => pte.executable(),
+        };
+        if !pte.is_valid() || !pte.is_user() || !permitted {
+            return None;
+        }
+        Some(ppn)
+    }
+    /// Resolve a page fault at `va` from the application, which is valid if
+    /// it hits a segment allowing `access`, or the area the stack grows into.
+    /// The page is populated on first touch.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, access: AccessType) -> Result<(), PageFaultError> {
+        let vpn = va.floor();
+        if !self.segments.iter().any(|seg| {
+            seg.is_user() && seg.vpn_range.get_start() <= vpn && vpn < seg.vpn_range.get_end()
+        }) {
+            self.grow_stack(vpn)?;
+        }
+        let seg = self.segments
+            .iter_mut()
+            .find(|seg| seg.vpn_range.get_start() <= vpn && vpn < seg.vpn_range.get_end())
+            .expect("Impossible");
+        if !seg.is_user() {
+            return Err(PageFaultError::Invalid);
+        }
+        if !seg.allows(access) {
+            return Err(PageFaultError::Denied);
+        }
+        seg.populate(&mut self.page_table, vpn).map(|_| ())
     }
     pub fn activate(&self) {
         let satp = self.page_table.token();
@@ -228,6 +796,27 @@ pub struct Segment {
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// initial content of a lazy segment from the address given, copied in
+    /// on first touch
+    data: Option<(VirtAddr, SegmentData)>,
+}
+
+/// Where the initial content of a lazy segment comes from
+#[derive(Clone)]
+pub enum SegmentData {
+    /// bytes in the kernel image, like the program headers of the app
+    Bytes(&'static [u8]),
+    /// a file from the byte offset given, as mmap maps it
+    File(Arc<dyn Inode>, usize),
+}
+
+impl fmt::Debug for SegmentData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Bytes(data) => write!(f, "Bytes({:#x} bytes)", data.len()),
+            Self::File(inode, offset) => write!(f, "File(ino {}, offset {:#x})", inode.ino(), offset),
+        }
+    }
 }
 
 impl Segment {
@@ -244,14 +833,29 @@ impl Segment {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            data: None,
         }
     }
+    /// Fill the pages of a lazy segment with `data` starting from `start`
+    pub fn with_data(mut self, start: VirtAddr, data: &'static [u8]) -> Self {
+        assert_eq!(self.map_type, MapType::Lazy);
+        self.data = Some((start, SegmentData::Bytes(data)));
+        self
+    }
+    /// Fill the pages of a lazy segment with the content of `inode` from
+    /// `offset`, starting from `start`
+    pub fn with_file(mut self, start: VirtAddr, inode: Arc<dyn Inode>, offset: usize) -> Self {
+        assert_eq!(self.map_type, MapType::Lazy);
+        self.data = Some((start, SegmentData::File(inode, offset)));
+        self
+    }
     pub fn from_another(another: &Segment) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            data: another.data.clone(),
         }
     }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
@@ -270,15 +874,167 @@ impl Segment {
                 assert!(vpn.0 < (1usize << 27));
                 ppn = PhysPageNum((vpn.0 as isize + pn_offset) as usize);
             }
+            MapType::Lazy => {
+                // populated on page faults
+                return;
+            }
+        }
+        if let Some(pte_flags) = self.pte_flags() {
+            page_table.map(vpn, ppn, pte_flags);
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits()).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+        let populated = match self.map_type {
+            MapType::Framed | MapType::Lazy => self.data_frames.remove(&vpn).is_some(),
+            _ => true,
+        };
+        if populated && self.pte_flags().is_some() {
+            page_table.unmap(vpn);
+        }
+    }
+    /// Make sure the page at `vpn` is backed by a frame, allocating a zeroed
+    /// one filled with the segment data if it is lazy and not touched yet.
+    /// Returns the physical page.
+    pub fn populate(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<PhysPageNum, PageFaultError> {
+        if self.map_type != MapType::Lazy || self.data_frames.contains_key(&vpn) {
+            return self.ppn_of(vpn).ok_or(PageFaultError::Invalid);
+        }
+        let frame = frame_alloc().ok_or(PageFaultError::OutOfMemory)?;
+        let page_start = VirtAddr::from(vpn);
+        match &self.data {
+            Some((start, SegmentData::Bytes(data))) => {
+                let start = *start;
+                let page_end = page_start + (PAGE_SIZE as isize);
+                let data_end = start + (data.len() as isize);
+                let (l, r) = (page_start.max(start), page_end.min(data_end));
+                if l < r {
+                    let src = &data[(l - start) as usize..(r - start) as usize];
+                    let offset = l.page_offset();
+                    frame.ppn.get_bytes_array()[offset..offset + src.len()].copy_from_slice(src);
+                }
+            }
+            Some((start, SegmentData::File(inode, offset))) => {
+                // Mappings of files start on a page, and the part of the page
+                // past the end of the file is left zeroed
+                let pos = offset + (page_start - *start) as usize;
+                inode.read_at(pos, frame.ppn.get_bytes_array()).map_err(|_| PageFaultError::BadFile)?;
+            }
+            None => (),
+        }
+        let ppn = frame.ppn;
+        self.data_frames.insert(vpn, frame);
+        if let Some(pte_flags) = self.pte_flags() {
+            page_table.map(vpn, ppn, pte_flags);
+        }
+        // the code just stored may be run
+        if self.map_perm.contains(MapPermission::X) {
+            flush_icache();
+        }
+        Ok(ppn)
+    }
+    /// Whether the permission of the segment allows `access`
+    pub fn allows(&self, access: AccessType) -> bool {
+        match access {
+            AccessType::Read => self.map_perm.contains(MapPermission::R),
+            AccessType::Write => self.map_perm.contains(MapPermission::W),
+            AccessType::Execute => self.map_perm.contains(MapPermission::X),
+        }
+    }
+    /// The PTE flags of the pages. Inaccessible (PROT_NONE) pages keep their
+    /// frames but are left out of the page table, since a valid PTE without
+    /// R/W/X would point to the next level.
+    fn pte_flags(&self) -> Option<PTEFlags> {
+        if self.map_perm.intersects(MapPermission::R | MapPermission::W | MapPermission::X) {
+            Some(PTEFlags::from_bits(self.map_perm.bits()).unwrap())
+        } else {
+            None
+        }
+    }
+    /// The physical page mapped at `vpn`, if any
+    fn ppn_of(&self, vpn: VirtPageNum) -> Option<PhysPageNum> {
+        match self.map_type {
+            MapType::Identical => Some(PhysPageNum(vpn.0)),
+            MapType::Framed | MapType::Lazy => self.data_frames.get(&vpn).map(|frame| frame.ppn),
+            MapType::Linear(pn_offset) => Some(PhysPageNum((vpn.0 as isize + pn_offset) as usize)),
+        }
+    }
+    pub fn is_user(&self) -> bool {
+        self.map_perm.contains(MapPermission::U)
+    }
+    /// Change the permission of all pages in the segment
+    pub fn set_perm(&mut self, page_table: &mut PageTable, map_perm: MapPermission) {
+        // code may have been stored to the pages while they were not
+        // executable
+        if map_perm.contains(MapPermission::X) && !self.map_perm.contains(MapPermission::X) {
+            flush_icache();
+        }
+        let old_flags = self.pte_flags();
+        self.map_perm = map_perm;
+        let new_flags = self.pte_flags();
+        for vpn in self.vpn_range {
+            // untouched lazy pages are not in the page table
+            let ppn = match self.ppn_of(vpn) {
+                Some(ppn) => ppn,
+                None => continue,
+            };
+            match (old_flags, new_flags) {
+                (Some(_), Some(flags)) => page_table.set_flags(vpn, flags),
+                (Some(_), None) => page_table.unmap(vpn),
+                (None, Some(flags)) => page_table.map(vpn, ppn, flags),
+                (None, None) => (),
+            }
         }
-        page_table.unmap(vpn);
+    }
+    /// Split the segment at `vpn`, keeping [start, vpn) and returning
+    /// [vpn, end) with the frames therein.
+    pub fn split_off(&mut self, vpn: VirtPageNum) -> Segment {
+        let tail = Segment {
+            vpn_range: VPNRange::new(vpn, self.vpn_range.get_end()),
+            data_frames: self.data_frames.split_off(&vpn),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            data: self.data.clone(),
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), vpn);
+        tail
+    }
+    /// Whether `next`, starting right after this segment, can join it
+    pub fn can_merge(&self, next: &Segment) -> bool {
+        self.is_user()
+            && self.map_type == next.map_type
+            && matches!(self.map_type, MapType::Framed | MapType::Lazy)
+            && self.data.is_none()
+            && next.data.is_none()
+            && self.map_perm == next.map_perm
+    }
+    /// Take over the pages of `next`, which starts right after this segment
+    pub fn absorb(&mut self, mut next: Segment) {
+        assert!(self.vpn_range.get_end() == next.vpn_range.get_start());
+        self.data_frames.append(&mut next.data_frames);
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), next.vpn_range.get_end());
+    }
+    /// Move the pages to start at `new_start`, keeping their frames
+    pub fn move_to(&mut self, page_table: &mut PageTable, new_start: VirtPageNum) {
+        assert!(matches!(self.map_type, MapType::Framed | MapType::Lazy));
+        let old_start = self.vpn_range.get_start();
+        if let Some((start, _)) = &mut self.data {
+            *start = *start + ((new_start.0 as isize - old_start.0 as isize) * PAGE_SIZE as isize);
+        }
+        let frames = core::mem::take(&mut self.data_frames);
+        if self.pte_flags().is_some() {
+            for vpn in frames.keys() {
+                page_table.unmap(*vpn);
+            }
+        }
+        for (vpn, frame) in frames {
+            let new_vpn = VirtPageNum(vpn.0 - old_start.0 + new_start.0);
+            if let Some(pte_flags) = self.pte_flags() {
+                page_table.map(new_vpn, frame.ppn, pte_flags);
+            }
+            self.data_frames.insert(new_vpn, frame);
+        }
+        let pages = self.vpn_range.get_end().0 - old_start.0;
+        self.vpn_range = VPNRange::new(new_start, VirtPageNum(new_start.0 + pages));
     }
     pub fn map(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
@@ -290,6 +1046,25 @@ impl Segment {
             self.unmap_one(page_table, vpn);
         }
     }
+    /// Grow the segment upwards to end at `new_end`, mapping the new pages.
+    pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(self.vpn_range.get_end(), new_end) {
+            self.map_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+    /// Grow a lazy segment downwards to start at `new_start`.
+    pub fn prepend_to(&mut self, new_start: VirtPageNum) {
+        assert_eq!(self.map_type, MapType::Lazy);
+        self.vpn_range = VPNRange::new(new_start, self.vpn_range.get_end());
+    }
+    /// Shrink the segment to end at `new_end`, unmapping the pages beyond.
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(new_end, self.vpn_range.get_end()) {
+            self.unmap_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
     /// data: start-aligned but maybe with shorter length
     pub fn copy_data(&mut self, page_table: &mut PageTable, start: VirtAddr, data: &[u8]) {
         assert_eq!(self.map_type, MapType::Framed);
@@ -350,6 +1125,31 @@ pub enum MapType {
     Framed,
     /// offset of page num
     Linear(isize),
+    /// framed on first access, by page faults
+    Lazy,
+}
+
+/// Why a page fault of the application can not be resolved
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PageFaultError {
+    /// no segment of the app maps the address
+    Invalid,
+    /// the segment of the address does not allow the access
+    Denied,
+    /// the stack hits its rlimit or another segment
+    StackOverflow,
+    /// no frame left to populate the page
+    OutOfMemory,
+    /// the file the page is mapped from can not be read
+    BadFile,
+}
+
+/// The kind of memory access causing a page fault
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AccessType {
+    Read,
+    Write,
+    Execute,
 }
 
 lazy_static! {
@@ -358,10 +1158,37 @@ lazy_static! {
         Arc::new(unsafe { UPSafeCell::new(AddrSpace::new_with_kernel()) });
 }
 
-/// Load the kernel space with elf file
-pub fn kspace_load_elf(elf: &ElfFile) -> usize {
+/// 16 bytes for `AT_RANDOM`, which libc uses for stack canaries and pointer
+/// guards, drawn from the cycle counter by splitmix64.
+fn random_bytes() -> [u8; 16] {
+    let mut seed = get_clock() as u64;
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_ne_bytes());
+    }
+    bytes
+}
+
+/// Load the kernel space with elf file, returning the initial user sp and tp
+pub fn kspace_load_elf(elf: &ElfFile<'static>, argv: &[&str], envp: &[&str]) -> (usize, usize) {
+    let mut kernel_space = KERNEL_SPACE.exclusive_access();
+    kernel_space.load_elf(elf, argv, envp)
+}
+
+/// Set the program break of the application
+pub fn kspace_set_brk(new_brk: usize) -> usize {
     let mut kernel_space = KERNEL_SPACE.exclusive_access();
-    kernel_space.load_elf(elf)
+    kernel_space.set_brk(new_brk.into()).into()
+}
+
+/// Handle a page fault of the application
+pub fn kspace_handle_page_fault(va: usize, access: AccessType) -> Result<(), PageFaultError> {
+    let mut kernel_space = KERNEL_SPACE.exclusive_access();
+    kernel_space.handle_page_fault(va.into(), access)
 }
 
 /// Activate sv39
@@ -369,26 +1196,3 @@ pub fn kspace_activate() {
     let kernel_space = KERNEL_SPACE.exclusive_access();
     kernel_space.activate();
 }
-
-pub fn kspace_from_user_buffer(ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let kernel_space = KERNEL_SPACE.exclusive_access();
-    let page_table = &kernel_space.page_table;
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::new();
-    while start < end {
-        let start_va = VirtAddr::from(start);
-        let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
-        vpn.step();
-        let mut end_va: VirtAddr = vpn.into();
-        end_va = end_va.min(VirtAddr::from(end));
-        if end_va.page_offset() == 0 {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
-        } else {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
-        }
-        start = end_va.into();
-    }
-    v
-}
@@ -40,7 +40,9 @@ impl Drop for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    fn available(&self) -> usize;
 }
 /// an implementation for frame allocator
 pub struct StackFrameAllocator {
@@ -74,6 +76,15 @@ impl FrameAllocator for StackFrameAllocator {
             Some((self.current - 1).into())
         }
     }
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum> {
+        // recycled frames are scattered, so take a run of fresh ones
+        if self.end - self.current < pages {
+            None
+        } else {
+            self.current += pages;
+            Some((self.current - pages).into())
+        }
+    }
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
         // validity check
@@ -83,6 +94,9 @@ impl FrameAllocator for StackFrameAllocator {
         // recycle
         self.recycled.push(ppn);
     }
+    fn available(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
 }
 
 type FrameAllocatorImpl = StackFrameAllocator;
@@ -109,6 +123,16 @@ pub fn frame_alloc() -> Option<FrameTracker> {
         .alloc()
         .map(FrameTracker::new)
 }
+/// allocate `pages` frames at consecutive physical addresses, as devices
+/// doing DMA need
+pub fn frame_alloc_contiguous(pages: usize) -> Option<Vec<FrameTracker>> {
+    let first = FRAME_ALLOCATOR.exclusive_access().alloc_contiguous(pages)?;
+    Some((first.0..first.0 + pages).map(|ppn| FrameTracker::new(ppn.into())).collect())
+}
+/// the number of frames that can still be allocated
+pub fn frames_available() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().available()
+}
 /// deallocate a frame
 fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
@@ -2,6 +2,7 @@
 
 pub mod addr;
 pub mod addr_space;
+pub mod user_ptr;
 pub mod page_table;
 pub mod frame;
 pub mod kernel_heap;
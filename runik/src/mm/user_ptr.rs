@@ -0,0 +1,213 @@
+//! mod user_ptr: checked access to the memory of the application
+//!
+//! Every pointer the application passes to a syscall is wrapped in a
+//! [`UserPtr`] or a [`UserSlice`], which translate it page by page through
+//! the address space, populate lazily mapped pages, and refuse pages whose
+//! PTE does not grant the access to user mode. A bad pointer thus ends in
+//! `EFAULT` instead of a kernel panic.
+
+use super::addr::VirtAddr;
+use super::addr_space::{ AccessType, KERNEL_SPACE };
+use crate::arch::paging::{ PAGE_SIZE, VA_WIDTH };
+use crate::syscall::FromSyscallArg;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{ size_of, MaybeUninit };
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::uio::{ IoVec, UIO_MAXIOV };
+
+/// Translate the user range `[addr, addr + len)` into kernel slices of its
+/// frames, one per page, failing if any page does not allow `access`
+fn translate(addr: usize, len: usize, access: AccessType) -> Result<Vec<&'static mut [u8]>, Errno> {
+    let end = addr.checked_add(len).ok_or(EFAULT)?;
+    if end > 1 << (VA_WIDTH - 1) {
+        return Err(EFAULT);
+    }
+    let mut kernel_space = KERNEL_SPACE.exclusive_access();
+    let mut pieces = Vec::new();
+    let mut start = addr;
+    while start < end {
+        let va = VirtAddr::from(start);
+        let ppn = kernel_space.user_page(va.floor(), access).ok_or(EFAULT)?;
+        let piece_end = end.min((start & !(PAGE_SIZE - 1)) + PAGE_SIZE);
+        let offset = va.page_offset();
+        pieces.push(&mut ppn.get_bytes_array()[offset..offset + piece_end - start]);
+        start = piece_end;
+    }
+    Ok(pieces)
+}
+
+/// A pointer to a `T` in the memory of the application
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> core::fmt::Debug for UserPtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "UserPtr({:#x})", self.addr)
+    }
+}
+
+impl<T> UserPtr<T> {
+    pub fn new(addr: usize) -> Self {
+        Self { addr, _marker: PhantomData }
+    }
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+    pub fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+    /// The pointer `count` elements after this one
+    pub fn add(&self, count: usize) -> Self {
+        Self::new(self.addr.wrapping_add(count.wrapping_mul(size_of::<T>())))
+    }
+}
+
+/// `T` is copied byte by byte, so it must be plain data that is valid for
+/// any bit pattern, like the `#[repr(C)]` structs of `linuxabi`
+impl<T: Copy> UserPtr<T> {
+    /// Copy the `T` from the application
+    pub fn read(&self) -> Result<T, Errno> {
+        let mut val = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        UserSlice::new(self.cast(), bytes.len()).read_into(bytes)?;
+        Ok(unsafe { val.assume_init() })
+    }
+    /// Copy `val` to the application
+    pub fn write(&self, val: T) -> Result<(), Errno> {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(&val as *const T as *const u8, size_of::<T>())
+        };
+        UserSlice::new(self.cast(), bytes.len()).write(bytes)?;
+        Ok(())
+    }
+    /// Copy `count` consecutive `T`s from the application
+    pub fn read_array(&self, count: usize) -> Result<Vec<T>, Errno> {
+        (0..count).map(|i| self.add(i).read()).collect()
+    }
+    fn cast(&self) -> UserPtr<u8> {
+        UserPtr::new(self.addr)
+    }
+}
+
+impl UserPtr<u8> {
+    /// Copy a nul-terminated string from the application, which may take at
+    /// most `max_len` bytes including the nul
+    pub fn read_cstr(&self, max_len: usize) -> Result<String, Errno> {
+        let mut bytes = Vec::new();
+        let mut start = self.addr;
+        loop {
+            let page_end = (start & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+            let len = (page_end - start).min(max_len - bytes.len());
+            if len == 0 {
+                return Err(ENAMETOOLONG);
+            }
+            for piece in translate(start, len, AccessType::Read)? {
+                match piece.iter().position(|&b| b == 0) {
+                    Some(nul) => {
+                        bytes.extend_from_slice(&piece[..nul]);
+                        return String::from_utf8(bytes).map_err(|_| EINVAL);
+                    }
+                    None => bytes.extend_from_slice(piece),
+                }
+            }
+            start += len;
+        }
+    }
+}
+
+impl<T> FromSyscallArg for UserPtr<T> {
+    fn from_arg(raw: usize) -> Self {
+        Self::new(raw)
+    }
+}
+
+/// A byte buffer in the memory of the application
+#[derive(Copy, Clone, Debug)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(ptr: UserPtr<u8>, len: usize) -> Self {
+        Self { addr: ptr.addr(), len }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// The rest of the buffer after its first `n` bytes
+    pub fn skip(&self, n: usize) -> Self {
+        let n = n.min(self.len);
+        Self { addr: self.addr + n, len: self.len - n }
+    }
+    /// The buffers described by an array of `count` iovecs
+    pub fn from_iovecs(iov: UserPtr<IoVec>, count: usize) -> Result<Vec<Self>, Errno> {
+        if count > UIO_MAXIOV {
+            return Err(EINVAL);
+        }
+        let mut total: usize = 0;
+        let mut bufs = Vec::with_capacity(count);
+        for iovec in iov.read_array(count)? {
+            total = total.checked_add(iovec.iov_len).ok_or(EINVAL)?;
+            if total > isize::MAX as usize {
+                return Err(EINVAL);
+            }
+            bufs.push(Self::new(UserPtr::new(iovec.iov_base), iovec.iov_len));
+        }
+        Ok(bufs)
+    }
+    /// The buffer as kernel slices, one per page, all of which are checked
+    /// for `access` before any is returned
+    pub fn pages(&self, access: AccessType) -> Result<Vec<&'static mut [u8]>, Errno> {
+        translate(self.addr, self.len, access)
+    }
+    /// Copy the whole buffer from the application
+    pub fn read(&self) -> Result<Vec<u8>, Errno> {
+        let mut data = Vec::with_capacity(self.len);
+        for piece in self.pages(AccessType::Read)? {
+            data.extend_from_slice(piece);
+        }
+        Ok(data)
+    }
+    /// Fill `dst` from the start of the buffer, which must be long enough
+    pub fn read_into(&self, dst: &mut [u8]) -> Result<(), Errno> {
+        if dst.len() > self.len {
+            return Err(EFAULT);
+        }
+        let mut i = 0;
+        for piece in translate(self.addr, dst.len(), AccessType::Read)? {
+            dst[i..i + piece.len()].copy_from_slice(piece);
+            i += piece.len();
+        }
+        Ok(())
+    }
+    /// Copy `src` to the start of the buffer, truncated to its length, and
+    /// return the number of bytes copied. Nothing is written unless the
+    /// whole destination is writable.
+    pub fn write(&self, src: &[u8]) -> Result<usize, Errno> {
+        let len = src.len().min(self.len);
+        let mut i = 0;
+        for piece in translate(self.addr, len, AccessType::Write)? {
+            piece.copy_from_slice(&src[i..i + piece.len()]);
+            i += piece.len();
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,236 @@
+//! Networking
+//!
+//! The TCP/IP stack is smoltcp, over the first virtio network device. The
+//! stack is polled around every socket operation, on the interrupt of the
+//! device, and while a socket waits, which it does letting other threads
+//! run until the device or the timer interrupts.
+
+mod socket;
+
+pub use socket::{ Socket, SocketKind };
+
+use crate::arch::time::get_clock;
+use crate::config::{ NET_GATEWAY, NET_IP_ADDR, NET_PREFIX_LEN, TCP_BUFFER_SIZE, UDP_BUFFER_SIZE, UDP_PACKETS };
+use crate::drivers::plic;
+use crate::drivers::virtio::{ self, DeviceType };
+use crate::drivers::virtio::net::{ VirtioNet, MAX_FRAME_SIZE };
+use crate::plat::qemu::CLOCK_FREQ;
+use crate::sync::UPSafeCell;
+use crate::task::{ self, signal };
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+use linuxabi::errno::Errno::{ self, * };
+use smoltcp::iface::{ Config, Interface, SocketHandle, SocketSet };
+use smoltcp::phy::{ self, Device, DeviceCapabilities, Medium };
+use smoltcp::socket::{ tcp, udp };
+use smoltcp::time::Instant;
+use smoltcp::wire::{ EthernetAddress, HardwareAddress, IpCidr, Ipv4Address };
+
+/// The first port handed out to sockets not bound to one
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+struct NetDevice(VirtioNet);
+
+struct RxToken(Vec<u8>);
+
+struct TxToken<'a>(&'a mut VirtioNet);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(mut self, f: F) -> R {
+        f(&mut self.0)
+    }
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+        let mut frame = vec![0u8; len];
+        let result = f(&mut frame);
+        if let Err(e) = self.0.send(&frame) {
+            println!("[kernel] [warn] virtio-net: failed to send a frame: {:?}", e);
+        }
+        result
+    }
+}
+
+impl Device for NetDevice {
+    type RxToken<'a> = RxToken where Self: 'a;
+    type TxToken<'a> = TxToken<'a> where Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(RxToken, TxToken<'_>)> {
+        let frame = self.0.receive()?;
+        Some((RxToken(frame), TxToken(&mut self.0)))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<TxToken<'_>> {
+        Some(TxToken(&mut self.0))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = MAX_FRAME_SIZE;
+        caps.max_burst_size = Some(1);
+        caps
+    }
+}
+
+/// The interface and the sockets on it
+pub struct NetStack {
+    device: NetDevice,
+    iface: Interface,
+    sockets: SocketSet<'static>,
+    /// TCP sockets closed by their owner, removed once the connection is done
+    closing: Vec<SocketHandle>,
+    tcp_ports: BTreeSet<u16>,
+    udp_ports: BTreeSet<u16>,
+    next_port: u16,
+}
+
+lazy_static! {
+    static ref NET: UPSafeCell<Option<NetStack>> = unsafe { UPSafeCell::new(None) };
+}
+
+fn now() -> Instant {
+    Instant::from_millis((get_clock() / (CLOCK_FREQ / 1000)) as i64)
+}
+
+/// Bring up the interface on the first network device, if there is one
+pub fn init() {
+    let Some(transport) = virtio::take(DeviceType::Network) else {
+        return;
+    };
+    let base = transport.base();
+    let irq = transport.irq();
+    let net = match VirtioNet::new(transport) {
+        Ok(net) => net,
+        Err(e) => {
+            println!("[kernel] [warn] virtio-net@{:#x}: failed to set up: {:?}", base, e);
+            return;
+        }
+    };
+    let mac = EthernetAddress(net.mac());
+    let mut device = NetDevice(net);
+    let mut iface = Interface::new(Config::new(HardwareAddress::Ethernet(mac)), &mut device, now());
+    iface.update_ip_addrs(|addrs| {
+        addrs.push(IpCidr::new(Ipv4Address(NET_IP_ADDR).into(), NET_PREFIX_LEN)).unwrap();
+    });
+    iface.routes_mut().add_default_ipv4_route(Ipv4Address(NET_GATEWAY)).unwrap();
+    println!("[kernel] [info] virtio-net@{:#x}: {} at {}/{}", base, mac, Ipv4Address(NET_IP_ADDR), NET_PREFIX_LEN);
+    *NET.exclusive_access() = Some(NetStack {
+        device,
+        iface,
+        sockets: SocketSet::new(vec![]),
+        closing: Vec::new(),
+        tcp_ports: BTreeSet::new(),
+        udp_ports: BTreeSet::new(),
+        next_port: EPHEMERAL_PORT_START,
+    });
+    plic::register(irq, 1, handle_irq);
+}
+
+/// Take in the frames the device has received, which also acknowledges
+/// its interrupt
+fn handle_irq() {
+    // a socket operation in progress polls the stack itself
+    if let Some(mut net) = NET.try_exclusive_access() {
+        if let Some(stack) = net.as_mut() {
+            stack.poll();
+        }
+    }
+}
+
+/// Whether there is an interface to make sockets on
+pub fn is_up() -> bool {
+    NET.exclusive_access().is_some()
+}
+
+/// Run `f` on the stack, letting it process packets before and after
+pub fn with_stack<R>(f: impl FnOnce(&mut NetStack) -> R) -> Result<R, Errno> {
+    let mut net = NET.exclusive_access();
+    let stack = net.as_mut().ok_or(ENETDOWN)?;
+    stack.poll();
+    let result = f(stack);
+    stack.poll();
+    Ok(result)
+}
+
+/// Poll the stack until `f` has a result, or just once with EAGAIN if
+/// `nonblocking`
+pub fn block_on<R>(nonblocking: bool, mut f: impl FnMut(&mut NetStack) -> Option<Result<R, Errno>>) -> Result<R, Errno> {
+    loop {
+        if let Some(result) = with_stack(&mut f)? {
+            return result;
+        }
+        if nonblocking {
+            return Err(EAGAIN);
+        }
+        if signal::interrupted() {
+            return Err(EINTR);
+        }
+        task::wait_for_event();
+    }
+}
+
+impl NetStack {
+    fn poll(&mut self) {
+        self.iface.poll(now(), &mut self.device, &mut self.sockets);
+        let sockets = &mut self.sockets;
+        self.closing.retain(|&handle| {
+            let state = sockets.get::<tcp::Socket>(handle).state();
+            if matches!(state, tcp::State::Closed | tcp::State::TimeWait) {
+                sockets.remove(handle);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn ports(&mut self, kind: SocketKind) -> &mut BTreeSet<u16> {
+        match kind {
+            SocketKind::Tcp => &mut self.tcp_ports,
+            SocketKind::Udp => &mut self.udp_ports,
+        }
+    }
+
+    /// Take `port` for a socket of `kind`, or a free ephemeral one if it is 0
+    fn alloc_port(&mut self, kind: SocketKind, port: u16) -> Result<u16, Errno> {
+        if port != 0 {
+            return if self.ports(kind).insert(port) { Ok(port) } else { Err(EADDRINUSE) };
+        }
+        for _ in EPHEMERAL_PORT_START..=u16::MAX {
+            let port = self.next_port;
+            self.next_port = if port == u16::MAX { EPHEMERAL_PORT_START } else { port + 1 };
+            if self.ports(kind).insert(port) {
+                return Ok(port);
+            }
+        }
+        Err(EADDRINUSE)
+    }
+
+    fn release_port(&mut self, kind: SocketKind, port: u16) {
+        self.ports(kind).remove(&port);
+    }
+
+    fn new_tcp(&mut self) -> SocketHandle {
+        let rx = tcp::SocketBuffer::new(vec![0u8; TCP_BUFFER_SIZE]);
+        let tx = tcp::SocketBuffer::new(vec![0u8; TCP_BUFFER_SIZE]);
+        self.sockets.add(tcp::Socket::new(rx, tx))
+    }
+
+    fn new_udp(&mut self) -> SocketHandle {
+        let rx = udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0u8; UDP_BUFFER_SIZE]);
+        let tx = udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0u8; UDP_BUFFER_SIZE]);
+        self.sockets.add(udp::Socket::new(rx, tx))
+    }
+
+    fn tcp(&mut self, handle: SocketHandle) -> &mut tcp::Socket<'static> {
+        self.sockets.get_mut(handle)
+    }
+
+    fn udp(&mut self, handle: SocketHandle) -> &mut udp::Socket<'static> {
+        self.sockets.get_mut(handle)
+    }
+}
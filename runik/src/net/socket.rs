@@ -0,0 +1,427 @@
+//! mod socket: the sockets behind socket file descriptors
+
+use super::{ block_on, is_up, with_stack, NetStack, NET };
+use crate::config::{ NET_IP_ADDR, SOCKET_BACKLOG_MAX };
+use crate::fs::File;
+use crate::sync::UPSafeCell;
+use crate::task::signal;
+use alloc::vec::Vec;
+use core::sync::atomic::{ AtomicBool, Ordering };
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::signal::SIGPIPE;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::{ tcp, udp };
+use smoltcp::wire::{ IpAddress, IpEndpoint, IpListenEndpoint, Ipv4Address };
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SocketKind {
+    Tcp,
+    Udp,
+}
+
+struct SocketState {
+    /// The address bound, explicitly or when first needed
+    local: Option<IpEndpoint>,
+    /// Whether this socket took the port of `local`, which accepted
+    /// connections share with their listener
+    owns_port: bool,
+    /// The smoltcp socket of a TCP connection, or of a bound UDP socket
+    handle: Option<SocketHandle>,
+    /// The listening smoltcp sockets, each of which becomes a connection
+    backlog: Vec<SocketHandle>,
+    /// The default destination of a UDP socket, set by connect
+    peer: Option<IpEndpoint>,
+    shut_read: bool,
+    nodelay: bool,
+}
+
+/// An IPv4 TCP or UDP socket
+pub struct Socket {
+    kind: SocketKind,
+    nonblocking: AtomicBool,
+    state: UPSafeCell<SocketState>,
+}
+
+fn listen_endpoint(endpoint: IpEndpoint) -> IpListenEndpoint {
+    IpListenEndpoint {
+        addr: if endpoint.addr.is_unspecified() { None } else { Some(endpoint.addr) },
+        port: endpoint.port,
+    }
+}
+
+fn unspecified() -> IpEndpoint {
+    IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::UNSPECIFIED), 0)
+}
+
+impl Socket {
+    pub fn new(kind: SocketKind) -> Result<Self, Errno> {
+        if !is_up() {
+            return Err(ENETDOWN);
+        }
+        Ok(Self::with_state(kind, SocketState {
+            local: None,
+            owns_port: false,
+            handle: None,
+            backlog: Vec::new(),
+            peer: None,
+            shut_read: false,
+            nodelay: false,
+        }))
+    }
+
+    fn with_state(kind: SocketKind, state: SocketState) -> Self {
+        Self {
+            kind,
+            nonblocking: AtomicBool::new(false),
+            state: unsafe { UPSafeCell::new(state) },
+        }
+    }
+
+    pub fn kind(&self) -> SocketKind {
+        self.kind
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    /// Take the port of `endpoint`, and for UDP start receiving on it
+    fn bind_in(&self, state: &mut SocketState, stack: &mut NetStack, endpoint: IpEndpoint) -> Result<IpEndpoint, Errno> {
+        if !endpoint.addr.is_unspecified() && endpoint.addr != IpAddress::Ipv4(Ipv4Address(NET_IP_ADDR)) {
+            return Err(EADDRNOTAVAIL);
+        }
+        let port = stack.alloc_port(self.kind, endpoint.port)?;
+        let local = IpEndpoint::new(endpoint.addr, port);
+        if self.kind == SocketKind::Udp {
+            let handle = stack.new_udp();
+            if stack.udp(handle).bind(listen_endpoint(local)).is_err() {
+                stack.sockets.remove(handle);
+                stack.release_port(self.kind, port);
+                return Err(EINVAL);
+            }
+            state.handle = Some(handle);
+        }
+        state.local = Some(local);
+        state.owns_port = true;
+        Ok(local)
+    }
+
+    /// The address bound, binding an ephemeral port if there is none
+    fn local_in(&self, state: &mut SocketState, stack: &mut NetStack) -> Result<IpEndpoint, Errno> {
+        match state.local {
+            Some(local) => Ok(local),
+            None => self.bind_in(state, stack, unspecified()),
+        }
+    }
+
+    pub fn bind(&self, endpoint: IpEndpoint) -> Result<(), Errno> {
+        let mut state = self.state.exclusive_access();
+        if state.local.is_some() {
+            return Err(EINVAL);
+        }
+        with_stack(|stack| self.bind_in(&mut state, stack, endpoint).map(|_| ()))?
+    }
+
+    pub fn listen(&self, backlog: i32) -> Result<(), Errno> {
+        if self.kind != SocketKind::Tcp {
+            return Err(EOPNOTSUPP);
+        }
+        let mut state = self.state.exclusive_access();
+        if state.handle.is_some() {
+            return Err(EINVAL);
+        }
+        let count = (backlog.max(1) as usize).min(SOCKET_BACKLOG_MAX);
+        with_stack(|stack| {
+            let local = self.local_in(&mut state, stack)?;
+            while state.backlog.len() < count {
+                let handle = stack.new_tcp();
+                if stack.tcp(handle).listen(listen_endpoint(local)).is_err() {
+                    stack.sockets.remove(handle);
+                    return Err(EINVAL);
+                }
+                state.backlog.push(handle);
+            }
+            Ok(())
+        })?
+    }
+
+    /// Wait for a connection, giving its socket and the peer's address
+    pub fn accept(&self) -> Result<(Socket, IpEndpoint), Errno> {
+        if self.kind != SocketKind::Tcp {
+            return Err(EOPNOTSUPP);
+        }
+        if self.state.exclusive_access().backlog.is_empty() {
+            return Err(EINVAL);
+        }
+        let (handle, peer, local, nodelay) = block_on(self.is_nonblocking(), |stack| {
+            let mut state = self.state.exclusive_access();
+            let local = state.local.unwrap();
+            for i in 0..state.backlog.len() {
+                let handle = state.backlog[i];
+                let socket = stack.tcp(handle);
+                let ready = match socket.state() {
+                    tcp::State::Listen | tcp::State::SynReceived => false,
+                    // reset during the handshake, so listen again
+                    tcp::State::Closed => {
+                        socket.abort();
+                        let _ = socket.listen(listen_endpoint(local));
+                        false
+                    }
+                    _ => true,
+                };
+                if !ready {
+                    continue;
+                }
+                let (Some(peer), Some(conn)) = (socket.remote_endpoint(), socket.local_endpoint()) else {
+                    continue;
+                };
+                let replacement = stack.new_tcp();
+                if stack.tcp(replacement).listen(listen_endpoint(local)).is_err() {
+                    stack.sockets.remove(replacement);
+                    return Some(Err(ENOMEM));
+                }
+                state.backlog[i] = replacement;
+                return Some(Ok((handle, peer, conn, state.nodelay)));
+            }
+            None
+        })?;
+        let socket = Self::with_state(SocketKind::Tcp, SocketState {
+            local: Some(local),
+            owns_port: false,
+            handle: Some(handle),
+            backlog: Vec::new(),
+            peer: Some(peer),
+            shut_read: false,
+            nodelay,
+        });
+        Ok((socket, peer))
+    }
+
+    pub fn connect(&self, endpoint: IpEndpoint) -> Result<(), Errno> {
+        let mut state = self.state.exclusive_access();
+        match self.kind {
+            SocketKind::Udp => {
+                with_stack(|stack| self.local_in(&mut state, stack))??;
+                state.peer = Some(endpoint);
+                Ok(())
+            }
+            SocketKind::Tcp => {
+                if !state.backlog.is_empty() {
+                    return Err(EINVAL);
+                }
+                let handle = match state.handle {
+                    Some(handle) => {
+                        let established = with_stack(|stack| stack.tcp(handle).may_send())?;
+                        return Err(if established { EISCONN } else { EALREADY });
+                    }
+                    None => with_stack(|stack| {
+                        let local = self.local_in(&mut state, stack)?;
+                        let handle = stack.new_tcp();
+                        let socket = stack.sockets.get_mut::<tcp::Socket>(handle);
+                        socket.set_nagle_enabled(!state.nodelay);
+                        if let Err(e) = socket.connect(stack.iface.context(), endpoint, listen_endpoint(local)) {
+                            stack.sockets.remove(handle);
+                            return Err(match e {
+                                tcp::ConnectError::InvalidState => EISCONN,
+                                tcp::ConnectError::Unaddressable => ENETUNREACH,
+                            });
+                        }
+                        Ok(handle)
+                    })??,
+                };
+                state.handle = Some(handle);
+                state.peer = Some(endpoint);
+                drop(state);
+                block_on(self.is_nonblocking(), |stack| match stack.tcp(handle).state() {
+                    tcp::State::SynSent | tcp::State::SynReceived => None,
+                    tcp::State::Closed => Some(Err(ECONNREFUSED)),
+                    _ => Some(Ok(())),
+                }).map_err(|e| if e == EAGAIN { EINPROGRESS } else { e })
+            }
+        }
+    }
+
+    /// Send `buf` to `dest`, or to the peer if it is None
+    pub fn send(&self, buf: &[u8], dest: Option<IpEndpoint>, nonblocking: bool) -> Result<usize, Errno> {
+        let nonblocking = nonblocking || self.is_nonblocking();
+        match self.kind {
+            SocketKind::Tcp => {
+                let handle = self.state.exclusive_access().handle.ok_or(ENOTCONN)?;
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                block_on(nonblocking, |stack| {
+                    let socket = stack.tcp(handle);
+                    if !socket.may_send() {
+                        Some(Err(if socket.state() == tcp::State::SynSent { ENOTCONN } else { EPIPE }))
+                    } else if socket.can_send() {
+                        Some(socket.send_slice(buf).map_err(|_| EPIPE))
+                    } else {
+                        None
+                    }
+                })
+            }
+            SocketKind::Udp => {
+                let mut state = self.state.exclusive_access();
+                let dest = dest.or(state.peer).ok_or(EDESTADDRREQ)?;
+                with_stack(|stack| self.local_in(&mut state, stack))??;
+                let handle = state.handle.unwrap();
+                drop(state);
+                block_on(nonblocking, |stack| {
+                    let socket = stack.udp(handle);
+                    if buf.len() > socket.payload_send_capacity() {
+                        return Some(Err(EMSGSIZE));
+                    }
+                    match socket.send_slice(buf, dest) {
+                        Ok(()) => Some(Ok(buf.len())),
+                        Err(udp::SendError::BufferFull) => None,
+                        Err(udp::SendError::Unaddressable) => Some(Err(EDESTADDRREQ)),
+                    }
+                })
+            }
+        }
+    }
+
+    /// Receive into `buf`, giving the length and where it came from
+    pub fn recv(&self, buf: &mut [u8], nonblocking: bool, peek: bool) -> Result<(usize, Option<IpEndpoint>), Errno> {
+        let nonblocking = nonblocking || self.is_nonblocking();
+        match self.kind {
+            SocketKind::Tcp => {
+                let state = self.state.exclusive_access();
+                let handle = state.handle.ok_or(ENOTCONN)?;
+                if state.shut_read || buf.is_empty() {
+                    return Ok((0, state.peer));
+                }
+                let peer = state.peer;
+                drop(state);
+                block_on(nonblocking, |stack| {
+                    let socket = stack.tcp(handle);
+                    if socket.can_recv() {
+                        let n = if peek { socket.peek_slice(buf) } else { socket.recv_slice(buf) };
+                        Some(n.map(|n| (n, peer)).map_err(|_| ECONNRESET))
+                    } else if !socket.may_recv() {
+                        Some(Ok((0, peer)))
+                    } else {
+                        None
+                    }
+                })
+            }
+            SocketKind::Udp => {
+                let mut state = self.state.exclusive_access();
+                if state.shut_read {
+                    return Ok((0, None));
+                }
+                with_stack(|stack| self.local_in(&mut state, stack))??;
+                let handle = state.handle.unwrap();
+                drop(state);
+                block_on(nonblocking, |stack| {
+                    let socket = stack.udp(handle);
+                    let result = if peek {
+                        socket.peek_slice(buf).map(|(n, meta)| (n, meta.endpoint))
+                    } else {
+                        socket.recv_slice(buf).map(|(n, meta)| (n, meta.endpoint))
+                    };
+                    result.ok().map(|(n, from)| Ok((n, Some(from))))
+                })
+            }
+        }
+    }
+
+    pub fn shutdown(&self, read: bool, write: bool) -> Result<(), Errno> {
+        let mut state = self.state.exclusive_access();
+        match (self.kind, state.handle) {
+            (SocketKind::Tcp, Some(handle)) => {
+                if write {
+                    with_stack(|stack| stack.tcp(handle).close())?;
+                }
+            }
+            (SocketKind::Udp, _) if state.peer.is_some() => {}
+            _ => return Err(ENOTCONN),
+        }
+        state.shut_read |= read;
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> IpEndpoint {
+        self.state.exclusive_access().local.unwrap_or_else(unspecified)
+    }
+
+    pub fn peer_addr(&self) -> Result<IpEndpoint, Errno> {
+        let state = self.state.exclusive_access();
+        let peer = state.peer.ok_or(ENOTCONN)?;
+        match (self.kind, state.handle) {
+            (SocketKind::Tcp, Some(handle)) => {
+                let connected = with_stack(|stack| stack.tcp(handle).remote_endpoint().is_some())?;
+                if connected { Ok(peer) } else { Err(ENOTCONN) }
+            }
+            _ => Ok(peer),
+        }
+    }
+
+    /// Send small TCP segments right away rather than coalescing them
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<(), Errno> {
+        if self.kind != SocketKind::Tcp {
+            return Err(ENOPROTOOPT);
+        }
+        let mut state = self.state.exclusive_access();
+        state.nodelay = nodelay;
+        if let Some(handle) = state.handle {
+            with_stack(|stack| stack.tcp(handle).set_nagle_enabled(!nodelay))?;
+        }
+        Ok(())
+    }
+
+    pub fn nodelay(&self) -> bool {
+        self.state.exclusive_access().nodelay
+    }
+}
+
+impl File for Socket {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.recv(buf, false, false).map(|(n, _)| n)
+    }
+    /// Send `buf` to the peer, raising SIGPIPE if it can take no more
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        self.send(buf, None, false).inspect_err(|&errno| {
+            if errno == EPIPE {
+                signal::raise(SIGPIPE);
+            }
+        })
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+    fn as_socket(&self) -> Option<&Socket> {
+        Some(self)
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let state = self.state.exclusive_access();
+        let mut net = NET.exclusive_access();
+        let Some(stack) = net.as_mut() else {
+            return;
+        };
+        for &handle in state.backlog.iter() {
+            stack.tcp(handle).abort();
+            stack.sockets.remove(handle);
+        }
+        if let Some(handle) = state.handle {
+            match self.kind {
+                SocketKind::Tcp => {
+                    // let the connection finish in the background
+                    stack.tcp(handle).close();
+                    stack.closing.push(handle);
+                }
+                SocketKind::Udp => {
+                    stack.sockets.remove(handle);
+                }
+            }
+        }
+        if let (Some(local), true) = (state.local, state.owns_port) {
+            stack.release_port(self.kind, local.port);
+        }
+        stack.poll();
+    }
+}
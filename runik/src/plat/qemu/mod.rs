@@ -5,5 +5,25 @@ pub const MMIO: &[(usize, usize)] = &[
     (0x0010_0000, 0x00_2000), // VIRT_TEST/RTC  in virt machine
     (0x2000000, 0x10000),     // core local interrupter (CLINT)
     (0xc000000, 0x210000),    // VIRT_PLIC in virt machine
-    (0x10000000, 0x9000),     // VIRT_UART0 with GPU  in virt machine
+    (0x10000000, 0x9000),     // VIRT_UART0 and VIRT_VIRTIO in virt machine
 ];
+
+/// The ns16550a UART of the virt machine, and its interrupt
+pub const UART0_BASE: usize = 0x1000_0000;
+pub const UART0_IRQ: u32 = 10;
+
+/// The PLIC of the virt machine, and the context of supervisor mode on hart
+/// 0, the only hart we run on
+pub const PLIC_BASE: usize = 0x0c00_0000;
+pub const PLIC_CONTEXT: usize = 1;
+
+/// The Goldfish RTC of the virt machine, in the VIRT_TEST/RTC region above
+pub const GOLDFISH_RTC_BASE: usize = 0x0010_1000;
+
+/// The virtio-mmio slots of the virt machine, each `VIRTIO_MMIO_SIZE` bytes,
+/// which lie in the VIRT_UART0 region above
+pub const VIRTIO_MMIO_BASE: usize = 0x1000_1000;
+pub const VIRTIO_MMIO_SIZE: usize = 0x1000;
+pub const VIRTIO_MMIO_COUNT: usize = 8;
+/// The IRQ of the first virtio-mmio slot, after which each has the next
+pub const VIRTIO_MMIO_IRQ: u32 = 1;
@@ -12,9 +12,15 @@ pub fn console_getchar() -> usize {
     sbi_rt::legacy::console_getchar()
 }
 
+/// use sbi call to raise a timer interrupt when the clock reaches `timer`
+pub fn set_timer(timer: usize) {
+    sbi_rt::set_timer(timer as u64);
+}
+
 /// use sbi call to shutdown the kernel
 pub fn shutdown(failure: bool) -> ! {
     use sbi_rt::{system_reset, NoReason, Shutdown, SystemFailure};
+    crate::console::flush();
     if !failure {
         system_reset(Shutdown, NoReason);
     } else {
@@ -0,0 +1,78 @@
+//! Decoding of syscall arguments
+//!
+//! The RISC-V Linux syscall ABI passes the syscall number in `a7` and up to
+//! six arguments in `a0`..`a5`. Each handler declares the types it expects,
+//! and [`SyscallArgs::get()`] converts the raw register value accordingly,
+//! so the dispatcher in [`super::syscall()`] never casts by hand.
+
+/// The raw arguments of a syscall, `a0`..`a5`
+#[derive(Copy, Clone, Debug)]
+pub struct SyscallArgs([usize; 6]);
+
+impl SyscallArgs {
+    pub fn new(raw: [usize; 6]) -> Self {
+        Self(raw)
+    }
+    /// Decode the `i`-th argument as `T`
+    pub fn get<T: FromSyscallArg>(&self, i: usize) -> T {
+        T::from_arg(self.0[i])
+    }
+    pub fn raw(&self) -> [usize; 6] {
+        self.0
+    }
+}
+
+/// Types a syscall argument can be decoded into from its register value
+pub trait FromSyscallArg {
+    fn from_arg(raw: usize) -> Self;
+}
+
+impl FromSyscallArg for usize {
+    fn from_arg(raw: usize) -> Self {
+        raw
+    }
+}
+
+impl FromSyscallArg for isize {
+    fn from_arg(raw: usize) -> Self {
+        raw as isize
+    }
+}
+
+impl FromSyscallArg for u64 {
+    fn from_arg(raw: usize) -> Self {
+        raw as u64
+    }
+}
+
+impl FromSyscallArg for i64 {
+    fn from_arg(raw: usize) -> Self {
+        raw as i64
+    }
+}
+
+/// C `unsigned int`, of which only the lower 32 bits are meaningful
+impl FromSyscallArg for u32 {
+    fn from_arg(raw: usize) -> Self {
+        raw as u32
+    }
+}
+
+/// C `int`, such as file descriptors and exit codes
+impl FromSyscallArg for i32 {
+    fn from_arg(raw: usize) -> Self {
+        raw as i32
+    }
+}
+
+impl<T> FromSyscallArg for *const T {
+    fn from_arg(raw: usize) -> Self {
+        raw as *const T
+    }
+}
+
+impl<T> FromSyscallArg for *mut T {
+    fn from_arg(raw: usize) -> Self {
+        raw as *mut T
+    }
+}
@@ -1,62 +1,373 @@
 //! File and filesystem-related syscalls
 
-use crate::sbi::console_getchar;
-
-const FD_STDIN: usize = 0;
-const FD_STDOUT: usize = 1;
-
-/// read a byte and place it to the buffer
-pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
-    if len == 0 {
-        return -1;
-    }
-    match fd {
-        FD_STDIN => {
-            let mut bufs = crate::mm::addr_space::kspace_from_user_buffer(buf, len);
-            let mut cnt: usize = 0;
-            let mut i: usize = 0;
-            let mut buf_st: usize = 0;
-            while cnt < len {
-                match console_getchar() {
-                    0 => { break; },
-                    ch => {
-                        let ch_u8 = match u8::try_from(ch) {
-                            Ok(val) => val,
-                            Err(_err) => { return -1 },
-                        };
-                        bufs[i][cnt - buf_st] = ch_u8;
-                        cnt += 1;
-                        if cnt - buf_st >= bufs[i].len() {
-                            buf_st = cnt;
-                            i += 1;
-                        }
-                    },
-                }
-            }
-            cnt as isize
-        }
-        _ => {
-            panic!("Illegal fd ({}) in sys_read!", fd);
+use super::SyscallResult;
+use crate::config::IO_BUFFER_SIZE;
+use crate::fs::{ self, DirEntry, InodeType, OpenFile, OpenFlags, SeekFrom };
+use crate::fs::fd_table::FD_TABLE;
+use crate::mm::user_ptr::{ UserPtr, UserSlice };
+use alloc::sync::Arc;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use linuxabi::dirent::DIRENT64_NAME_OFFSET;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::fcntl::*;
+use linuxabi::limits::PATH_MAX;
+use linuxabi::stat::Stat;
+use linuxabi::termios::FIONBIO;
+use linuxabi::uio::IoVec;
+
+/// Whether `file` is a regular file, whose reads only return less than
+/// asked for at the end. Other files, like the console and sockets, return
+/// what is ready, and may block if read again.
+fn is_regular(file: &OpenFile) -> bool {
+    file.inode().is_some_and(|inode| inode.kind() == InodeType::Regular)
+}
+
+/// Read from `file` into `buf` through a kernel buffer, at `offset` if given
+/// and otherwise at the offset of the file. A regular file is read until
+/// `buf` is full or it returns less than asked for, and other files once.
+fn read_to_user(file: &OpenFile, buf: UserSlice, offset: Option<usize>) -> SyscallResult {
+    let regular = is_regular(file);
+    let mut kbuf = vec![0u8; buf.len().min(IO_BUFFER_SIZE)];
+    let mut total = 0;
+    while total < buf.len() {
+        let want = (buf.len() - total).min(kbuf.len());
+        let read = match offset {
+            Some(offset) => file.read_at(offset + total, &mut kbuf[..want]),
+            None => file.read(&mut kbuf[..want]),
+        };
+        let n = match read {
+            Ok(n) => n,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        match buf.skip(total).write(&kbuf[..n]) {
+            Ok(_) => (),
+            // what was copied before is still reported
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        }
+        total += n;
+        if n < want || !regular {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+/// Write `buf` to `file` through a kernel buffer, until all is written or
+/// the file takes less than given, at `offset` if given and otherwise at the
+/// offset of the file
+fn write_from_user(file: &OpenFile, buf: UserSlice, offset: Option<usize>) -> SyscallResult {
+    let mut kbuf = vec![0u8; buf.len().min(IO_BUFFER_SIZE)];
+    let mut total = 0;
+    while total < buf.len() {
+        let want = (buf.len() - total).min(kbuf.len());
+        buf.skip(total).read_into(&mut kbuf[..want])?;
+        let written = match offset {
+            Some(offset) => file.write_at(offset + total, &kbuf[..want]),
+            None => file.write(&kbuf[..want]),
+        };
+        let n = match written {
+            Ok(n) => n,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += n;
+        if n < want {
+            break;
         }
     }
+    Ok(total)
+}
+
+/// Read into each of `bufs` in turn, until one is not filled, or into the
+/// first one that is not empty if `file` is not regular
+fn read_vectored(file: &OpenFile, bufs: Vec<UserSlice>, offset: Option<usize>) -> SyscallResult {
+    let regular = is_regular(file);
+    let mut total = 0;
+    for buf in bufs.into_iter().filter(|buf| regular || !buf.is_empty()) {
+        let n = match read_to_user(file, buf, offset.map(|offset| offset + total)) {
+            Ok(n) => n,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += n;
+        if n < buf.len() || !regular {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+/// Write each of `bufs` in turn, until one is not all written
+fn write_vectored(file: &OpenFile, bufs: Vec<UserSlice>, offset: Option<usize>) -> SyscallResult {
+    let mut total = 0;
+    for buf in bufs {
+        let n = match write_from_user(file, buf, offset.map(|offset| offset + total)) {
+            Ok(n) => n,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += n;
+        if n < buf.len() {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+/// The offset of a positional read or write, which must not be negative
+fn position(offset: i64) -> Result<usize, Errno> {
+    usize::try_from(offset).map_err(|_| EINVAL)
+}
+
+/// read up to `len` bytes from the file `fd` into `buf`
+pub fn sys_read(fd: i32, buf: UserPtr<u8>, len: usize) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    read_to_user(&file, UserSlice::new(buf, len), None)
 }
 
 /// write buf of length `len`  to a file with `fd`
-pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDOUT => {
-            let bufs = crate::mm::addr_space::kspace_from_user_buffer(buf, len);
-            for buf_i in bufs {
-                unsafe {
-                    let slice = core::slice::from_raw_parts(buf_i.as_ptr(), buf_i.len());
-                    let str = core::str::from_utf8(slice).unwrap();
-                    print!("{}", str);
-                }
-            };
-            len as isize
-        }
-        _ => {
-            panic!("Illegal fd ({}) in sys_write!", fd);
+pub fn sys_write(fd: i32, buf: UserPtr<u8>, len: usize) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    write_from_user(&file, UserSlice::new(buf, len), None)
+}
+
+/// read from the file `fd` into the `count` buffers of `iov`
+pub fn sys_readv(fd: i32, iov: UserPtr<IoVec>, count: usize) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    read_vectored(&file, UserSlice::from_iovecs(iov, count)?, None)
+}
+
+/// write the `count` buffers of `iov` to the file `fd`
+pub fn sys_writev(fd: i32, iov: UserPtr<IoVec>, count: usize) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    write_vectored(&file, UserSlice::from_iovecs(iov, count)?, None)
+}
+
+/// read up to `len` bytes at `offset` of the file `fd` into `buf`, leaving
+/// the offset of the file as it is
+pub fn sys_pread64(fd: i32, buf: UserPtr<u8>, len: usize, offset: i64) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    read_to_user(&file, UserSlice::new(buf, len), Some(position(offset)?))
+}
+
+/// write `len` bytes of `buf` at `offset` of the file `fd`, leaving the
+/// offset of the file as it is
+pub fn sys_pwrite64(fd: i32, buf: UserPtr<u8>, len: usize, offset: i64) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    write_from_user(&file, UserSlice::new(buf, len), Some(position(offset)?))
+}
+
+/// read at `offset` of the file `fd` into the `count` buffers of `iov`,
+/// where the offset is whole in a 64-bit register
+pub fn sys_preadv(fd: i32, iov: UserPtr<IoVec>, count: usize, offset: i64) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    read_vectored(&file, UserSlice::from_iovecs(iov, count)?, Some(position(offset)?))
+}
+
+/// write the `count` buffers of `iov` at `offset` of the file `fd`
+pub fn sys_pwritev(fd: i32, iov: UserPtr<IoVec>, count: usize, offset: i64) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    write_vectored(&file, UserSlice::from_iovecs(iov, count)?, Some(position(offset)?))
+}
+
+/// open the file at `path` relative to the directory `dirfd`
+pub fn sys_openat(dirfd: i32, path: UserPtr<u8>, flags: u32, mode: u32) -> SyscallResult {
+    let flags = OpenFlags::from_bits_truncate(flags);
+    if flags.contains(OpenFlags::WRONLY | OpenFlags::RDWR) {
+        return Err(EINVAL);
+    }
+    let path = fs::resolve_at(dirfd, &path.read_cstr(PATH_MAX)?)?;
+    let file = Arc::new(fs::open(&path, flags, mode)?);
+    let fd = FD_TABLE.exclusive_access().insert(file, flags.contains(OpenFlags::CLOEXEC), 0)?;
+    Ok(fd as usize)
+}
+
+/// close the file descriptor `fd`
+pub fn sys_close(fd: i32) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().remove(fd)?;
+    drop(file);
+    Ok(0)
+}
+
+/// duplicate `fd` to the lowest free file descriptor
+pub fn sys_dup(fd: i32) -> SyscallResult {
+    let mut fd_table = FD_TABLE.exclusive_access();
+    let file = fd_table.get(fd)?;
+    Ok(fd_table.insert(file, false, 0)? as usize)
+}
+
+/// duplicate `oldfd` to `newfd`, closing what `newfd` referred to
+pub fn sys_dup3(oldfd: i32, newfd: i32, flags: u32) -> SyscallResult {
+    if oldfd == newfd || flags & !O_CLOEXEC != 0 {
+        return Err(EINVAL);
+    }
+    let mut fd_table = FD_TABLE.exclusive_access();
+    let file = fd_table.get(oldfd)?;
+    let replaced = fd_table.insert_at(newfd, file, flags & O_CLOEXEC != 0)?;
+    drop(fd_table);
+    drop(replaced);
+    Ok(newfd as usize)
+}
+
+/// manipulate the file descriptor `fd`
+pub fn sys_fcntl(fd: i32, cmd: u32, arg: usize) -> SyscallResult {
+    let mut fd_table = FD_TABLE.exclusive_access();
+    let file = fd_table.get(fd)?;
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let newfd = fd_table.insert(file, cmd == F_DUPFD_CLOEXEC, arg)?;
+            Ok(newfd as usize)
+        }
+        F_GETFD => Ok(if fd_table.cloexec(fd)? { FD_CLOEXEC as usize } else { 0 }),
+        F_SETFD => {
+            fd_table.set_cloexec(fd, arg as u32 & FD_CLOEXEC != 0)?;
+            Ok(0)
         }
+        F_GETFL => Ok(file.flags().bits() as usize),
+        F_SETFL => {
+            file.set_flags(OpenFlags::from_bits_truncate(arg as u32));
+            Ok(0)
+        }
+        _ => Err(EINVAL),
+    }
+}
+
+/// carry out the request `cmd` on the device `fd`, where FIONBIO is the
+/// same for any file
+pub fn sys_ioctl(fd: i32, cmd: u32, arg: usize) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    if cmd == FIONBIO {
+        let nonblocking = UserPtr::<i32>::new(arg).read()? != 0;
+        let flags = file.flags();
+        file.set_flags(if nonblocking { flags | OpenFlags::NONBLOCK } else { flags - OpenFlags::NONBLOCK });
+        return Ok(0);
+    }
+    file.ioctl(cmd, arg)
+}
+
+/// move the offset of the file `fd`
+pub fn sys_lseek(fd: i32, offset: i64, whence: u32) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    let pos = match whence {
+        SEEK_SET => SeekFrom::Start(usize::try_from(offset).map_err(|_| EINVAL)?),
+        SEEK_CUR => SeekFrom::Current(offset as isize),
+        SEEK_END => SeekFrom::End(offset as isize),
+        _ => return Err(EINVAL),
+    };
+    file.seek(pos)
+}
+
+/// read entries of the directory `fd` into `buf` as struct linux_dirent64,
+/// where the offset of the directory is the index of the next entry
+pub fn sys_getdents64(fd: i32, buf: UserPtr<u8>, len: usize) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    let dir = file.inode()
+        .filter(|inode| inode.kind() == InodeType::Directory)
+        .ok_or(ENOTDIR)?;
+    let dot = |name: &str| DirEntry { name: name.to_string(), ino: dir.ino(), kind: InodeType::Directory };
+    let mut entries = vec![dot("."), dot("..")];
+    entries.extend(dir.read_dir()?);
+    let start = file.seek(SeekFrom::Current(0))?;
+    let mut records = Vec::new();
+    let mut next = start;
+    for entry in entries.iter().skip(start) {
+        let reclen = (DIRENT64_NAME_OFFSET + entry.name.len() + 1 + 7) & !7;
+        if records.len() + reclen > len {
+            break;
+        }
+        next += 1;
+        records.extend_from_slice(&(entry.ino as u64).to_ne_bytes());
+        records.extend_from_slice(&(next as i64).to_ne_bytes());
+        records.extend_from_slice(&(reclen as u16).to_ne_bytes());
+        records.push(entry.kind.dirent_type());
+        records.extend_from_slice(entry.name.as_bytes());
+        records.resize(records.len() + reclen - DIRENT64_NAME_OFFSET - entry.name.len(), 0);
+    }
+    if next == start && start < entries.len() {
+        // the buffer cannot hold the next entry
+        return Err(EINVAL);
     }
+    UserSlice::new(buf, len).write(&records)?;
+    file.seek(SeekFrom::Start(next))?;
+    Ok(records.len())
+}
+
+/// get the status of the file `fd`
+pub fn sys_fstat(fd: i32, statbuf: UserPtr<Stat>) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    statbuf.write(file.stat()?)?;
+    Ok(0)
+}
+
+/// get the status of the file at `path` relative to the directory `dirfd`
+pub fn sys_newfstatat(dirfd: i32, path: UserPtr<u8>, statbuf: UserPtr<Stat>, flags: u32) -> SyscallResult {
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_NO_AUTOMOUNT | AT_EMPTY_PATH) != 0 {
+        return Err(EINVAL);
+    }
+    let path = path.read_cstr(PATH_MAX)?;
+    let stat = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        if dirfd == AT_FDCWD {
+            fs::lookup(&fs::cwd())?.stat()
+        } else {
+            FD_TABLE.exclusive_access().get(dirfd)?.stat()?
+        }
+    } else if flags & AT_SYMLINK_NOFOLLOW != 0 {
+        fs::lookup_nofollow(&fs::resolve_at(dirfd, &path)?)?.stat()
+    } else {
+        fs::lookup(&fs::resolve_at(dirfd, &path)?)?.stat()
+    };
+    statbuf.write(stat)?;
+    Ok(0)
+}
+
+/// read the target of the symbolic link at `path` relative to the directory
+/// `dirfd` into `buf`, truncated to `bufsiz` bytes and without a NUL
+pub fn sys_readlinkat(dirfd: i32, path: UserPtr<u8>, buf: UserPtr<u8>, bufsiz: usize) -> SyscallResult {
+    if bufsiz == 0 || bufsiz > i32::MAX as usize {
+        return Err(EINVAL);
+    }
+    let path = fs::resolve_at(dirfd, &path.read_cstr(PATH_MAX)?)?;
+    let target = fs::lookup_nofollow(&path)?.read_link()?;
+    UserSlice::new(buf, bufsiz).write(target.as_bytes())
+}
+
+/// create the directory `path` relative to the directory `dirfd`
+pub fn sys_mkdirat(dirfd: i32, path: UserPtr<u8>, mode: u32) -> SyscallResult {
+    let path = fs::resolve_at(dirfd, &path.read_cstr(PATH_MAX)?)?;
+    fs::mkdir(&path, mode)?;
+    Ok(0)
+}
+
+/// remove the file, or the directory with AT_REMOVEDIR, at `path` relative
+/// to the directory `dirfd`
+pub fn sys_unlinkat(dirfd: i32, path: UserPtr<u8>, flags: u32) -> SyscallResult {
+    if flags & !AT_REMOVEDIR != 0 {
+        return Err(EINVAL);
+    }
+    let path = fs::resolve_at(dirfd, &path.read_cstr(PATH_MAX)?)?;
+    fs::unlink(&path, flags & AT_REMOVEDIR != 0)?;
+    Ok(0)
+}
+
+/// move the file at `oldpath` relative to the directory `olddirfd` to
+/// `newpath` relative to `newdirfd`
+pub fn sys_renameat2(olddirfd: i32, oldpath: UserPtr<u8>, newdirfd: i32, newpath: UserPtr<u8>, flags: u32) -> SyscallResult {
+    let old = fs::resolve_at(olddirfd, &oldpath.read_cstr(PATH_MAX)?)?;
+    let new = fs::resolve_at(newdirfd, &newpath.read_cstr(PATH_MAX)?)?;
+    fs::rename(&old, &new, flags)?;
+    Ok(0)
+}
+
+/// change the length of the regular file `fd`
+pub fn sys_ftruncate(fd: i32, length: i64) -> SyscallResult {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    let inode = file.inode()
+        .filter(|inode| inode.kind() == InodeType::Regular && file.flags().writable())
+        .ok_or(EINVAL)?;
+    inode.truncate(usize::try_from(length).map_err(|_| EINVAL)?)?;
+    Ok(0)
 }
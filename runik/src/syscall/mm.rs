@@ -1,5 +1,128 @@
 //! mm related syscalls
 
-pub fn sys_brk(addr: *const u8) -> isize {
-    0
+use super::SyscallResult;
+use crate::arch::paging::PAGE_SIZE;
+use crate::fs::fd_table::FD_TABLE;
+use crate::fs::InodeType;
+use crate::mm::addr::VirtAddr;
+use crate::mm::addr_space::{ MapPermission, KERNEL_SPACE };
+use linuxabi::errno::Errno::*;
+use linuxabi::mman::*;
+
+/// Translate PROT_* bits to the permission of user pages
+fn prot_to_perm(prot: u32) -> Option<MapPermission> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return None;
+    }
+    let mut perm = MapPermission::U;
+    if prot & PROT_READ != 0 {
+        perm |= MapPermission::R;
+    }
+    if prot & PROT_WRITE != 0 {
+        // write-only pages are reserved in sv39
+        perm |= MapPermission::R | MapPermission::W;
+    }
+    if prot & PROT_EXEC != 0 {
+        perm |= MapPermission::X;
+    }
+    Some(perm)
+}
+
+/// The number of pages covering `len` bytes
+fn pages_of(len: usize) -> usize {
+    len.div_ceil(PAGE_SIZE)
+}
+
+/// Set the program break to `addr`, returning the new break on success or
+/// the current break on failure. `brk(0)` queries the current break.
+pub fn sys_brk(addr: usize) -> SyscallResult {
+    let new_brk = KERNEL_SPACE.exclusive_access().set_brk(addr.into());
+    Ok(new_brk.into())
+}
+
+/// Map `len` bytes at `addr`, which is a hint unless MAP_FIXED is given and
+/// is then rounded down to a page.
+/// Only private mappings of files can be written, as writes to the pages
+/// never reach the file.
+pub fn sys_mmap(addr: usize, len: usize, prot: u32, flags: u32, fd: i32, offset: usize) -> SyscallResult {
+    let fixed = flags & (MAP_FIXED | MAP_FIXED_NOREPLACE) != 0;
+    if len == 0 || (fixed && addr % PAGE_SIZE != 0) || offset % PAGE_SIZE != 0 {
+        return Err(EINVAL);
+    }
+    match flags & MAP_TYPE {
+        MAP_SHARED | MAP_PRIVATE | MAP_SHARED_VALIDATE => (),
+        _ => return Err(EINVAL),
+    }
+    let perm = prot_to_perm(prot).ok_or(EINVAL)?;
+    let file = if flags & MAP_ANONYMOUS != 0 {
+        None
+    } else {
+        let file = FD_TABLE.exclusive_access().get(fd)?;
+        let inode = match file.inode() {
+            Some(inode) if inode.kind() == InodeType::Regular => inode,
+            _ => return Err(ENODEV),
+        };
+        if !file.flags().readable() {
+            return Err(EACCES);
+        }
+        if flags & MAP_TYPE != MAP_PRIVATE && prot & PROT_WRITE != 0 {
+            return Err(if file.flags().writable() { ENODEV } else { EACCES });
+        }
+        Some((inode, offset))
+    };
+    let start = KERNEL_SPACE.exclusive_access().mmap(
+        VirtAddr::from(addr).floor(),
+        pages_of(len),
+        perm,
+        fixed,
+        flags & MAP_FIXED_NOREPLACE != 0,
+        file,
+    )?;
+    Ok(VirtAddr::from(start).into())
+}
+
+/// Unmap the pages in [addr, addr + len)
+pub fn sys_munmap(addr: usize, len: usize) -> SyscallResult {
+    if len == 0 || addr % PAGE_SIZE != 0 {
+        return Err(EINVAL);
+    }
+    KERNEL_SPACE.exclusive_access().munmap(VirtAddr::from(addr).floor(), pages_of(len))?;
+    Ok(0)
+}
+
+/// Change the protection of the pages in [addr, addr + len)
+pub fn sys_mprotect(addr: usize, len: usize, prot: u32) -> SyscallResult {
+    if addr % PAGE_SIZE != 0 {
+        return Err(EINVAL);
+    }
+    let perm = prot_to_perm(prot).ok_or(EINVAL)?;
+    if len == 0 {
+        return Ok(0);
+    }
+    KERNEL_SPACE.exclusive_access().mprotect(VirtAddr::from(addr).floor(), pages_of(len), perm)?;
+    Ok(0)
+}
+
+/// Resize the mapping at `old_addr`, possibly moving it
+pub fn sys_mremap(old_addr: usize, old_len: usize, new_len: usize, flags: u32, new_addr: usize) -> SyscallResult {
+    if old_addr % PAGE_SIZE != 0
+        || new_len == 0
+        || flags & !(MREMAP_MAYMOVE | MREMAP_FIXED) != 0
+        || (flags & MREMAP_FIXED != 0 && (flags & MREMAP_MAYMOVE == 0 || new_addr % PAGE_SIZE != 0))
+    {
+        return Err(EINVAL);
+    }
+    let new_start = if flags & MREMAP_FIXED != 0 {
+        Some(VirtAddr::from(new_addr).floor())
+    } else {
+        None
+    };
+    let start = KERNEL_SPACE.exclusive_access().mremap(
+        VirtAddr::from(old_addr).floor(),
+        pages_of(old_len),
+        pages_of(new_len),
+        flags & MREMAP_MAYMOVE != 0,
+        new_start,
+    )?;
+    Ok(VirtAddr::from(start).into())
 }
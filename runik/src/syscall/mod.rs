@@ -11,26 +11,168 @@
 //! submodules, and you should also implement syscalls this way.
 
 use crate::arch::syscall::ids::*;
+use crate::config::LOG_UNSUPPORTED_SYSCALL;
+use linuxabi::errno::Errno;
 
+mod args;
 mod fs;
 mod mm;
+mod net;
 mod process;
+mod signal;
+mod time;
 
+pub use args::{ SyscallArgs, FromSyscallArg };
 use fs::*;
 use process::*;
 use mm::*;
+use net::*;
+use signal::*;
+use time::*;
+
+/// The result of a syscall, which is negated into the return value on errors
+pub type SyscallResult = Result<usize, Errno>;
 
 /// handle syscall exception with `syscall_id` and other arguments
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+pub fn syscall(syscall_id: usize, args: SyscallArgs) -> SyscallResult {
     match syscall_id {
+        #[cfg(syscall23)]
+        SYSCALL_DUP => sys_dup(args.get(0)),
+        #[cfg(syscall24)]
+        SYSCALL_DUP3 => sys_dup3(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall25)]
+        SYSCALL_FCNTL => sys_fcntl(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall29)]
+        SYSCALL_IOCTL => sys_ioctl(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall34)]
+        SYSCALL_MKDIRAT => sys_mkdirat(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall35)]
+        SYSCALL_UNLINKAT => sys_unlinkat(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall46)]
+        SYSCALL_FTRUNCATE => sys_ftruncate(args.get(0), args.get(1)),
+        #[cfg(syscall56)]
+        SYSCALL_OPENAT => sys_openat(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall57)]
+        SYSCALL_CLOSE => sys_close(args.get(0)),
+        #[cfg(syscall61)]
+        SYSCALL_GETDENTS64 => sys_getdents64(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall62)]
+        SYSCALL_LSEEK => sys_lseek(args.get(0), args.get(1), args.get(2)),
         #[cfg(syscall63)]
-        SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_READ => sys_read(args.get(0), args.get(1), args.get(2)),
         #[cfg(syscall64)]
-        SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_WRITE => sys_write(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall65)]
+        SYSCALL_READV => sys_readv(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall66)]
+        SYSCALL_WRITEV => sys_writev(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall67)]
+        SYSCALL_PREAD64 => sys_pread64(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall68)]
+        SYSCALL_PWRITE64 => sys_pwrite64(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall69)]
+        SYSCALL_PREADV => sys_preadv(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall70)]
+        SYSCALL_PWRITEV => sys_pwritev(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall78)]
+        SYSCALL_READLINKAT => sys_readlinkat(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall79)]
+        SYSCALL_FSTATAT => sys_newfstatat(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall80)]
+        SYSCALL_FSTAT => sys_fstat(args.get(0), args.get(1)),
         #[cfg(syscall93)]
-        SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_EXIT => sys_exit(args.get(0)),
+        #[cfg(syscall94)]
+        SYSCALL_EXIT_GROUP => sys_exit_group(args.get(0)),
+        #[cfg(syscall96)]
+        SYSCALL_SET_TID_ADDRESS => sys_set_tid_address(args.get(0)),
+        #[cfg(syscall98)]
+        SYSCALL_FUTEX => sys_futex(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4), args.get(5)),
+        #[cfg(syscall101)]
+        SYSCALL_NANOSLEEP => sys_nanosleep(args.get(0), args.get(1)),
+        #[cfg(syscall102)]
+        SYSCALL_GETITIMER => sys_getitimer(args.get(0), args.get(1)),
+        #[cfg(syscall103)]
+        SYSCALL_SETITIMER => sys_setitimer(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall112)]
+        SYSCALL_CLOCK_SETTIME => sys_clock_settime(args.get(0), args.get(1)),
+        #[cfg(syscall113)]
+        SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args.get(0), args.get(1)),
+        #[cfg(syscall114)]
+        SYSCALL_CLOCK_GETRES => sys_clock_getres(args.get(0), args.get(1)),
+        #[cfg(syscall115)]
+        SYSCALL_CLOCK_NANOSLEEP => sys_clock_nanosleep(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall124)]
+        SYSCALL_SCHED_YIELD => sys_sched_yield(),
+        #[cfg(syscall129)]
+        SYSCALL_KILL => sys_kill(args.get(0), args.get(1)),
+        #[cfg(syscall130)]
+        SYSCALL_TKILL => sys_tkill(args.get(0), args.get(1)),
+        #[cfg(syscall131)]
+        SYSCALL_TGKILL => sys_tgkill(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall132)]
+        SYSCALL_SIGALTSTACK => sys_sigaltstack(args.get(0), args.get(1)),
+        #[cfg(syscall134)]
+        SYSCALL_RT_SIGACTION => sys_rt_sigaction(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall135)]
+        SYSCALL_RT_SIGPROCMASK => sys_rt_sigprocmask(args.get(0), args.get(1), args.get(2), args.get(3)),
+        // made by the trampoline of the kernel rather than the app, which
+        // only needs it if it sets handlers
+        #[cfg(syscall134)]
+        SYSCALL_RT_SIGRETURN => sys_rt_sigreturn(),
+        #[cfg(syscall169)]
+        SYSCALL_GETTIMEOFDAY => sys_gettimeofday(args.get(0), args.get(1)),
+        #[cfg(syscall172)]
+        SYSCALL_GETPID => sys_getpid(),
+        #[cfg(syscall178)]
+        SYSCALL_GETTID => sys_gettid(),
+        #[cfg(syscall198)]
+        SYSCALL_SOCKET => sys_socket(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall200)]
+        SYSCALL_BIND => sys_bind(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall201)]
+        SYSCALL_LISTEN => sys_listen(args.get(0), args.get(1)),
+        #[cfg(syscall202)]
+        SYSCALL_ACCEPT => sys_accept(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall203)]
+        SYSCALL_CONNECT => sys_connect(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall204)]
+        SYSCALL_GETSOCKNAME => sys_getsockname(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall205)]
+        SYSCALL_GETPEERNAME => sys_getpeername(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall206)]
+        SYSCALL_SENDTO => sys_sendto(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4), args.get(5)),
+        #[cfg(syscall207)]
+        SYSCALL_RECVFROM => sys_recvfrom(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4), args.get(5)),
+        #[cfg(syscall208)]
+        SYSCALL_SETSOCKOPT => sys_setsockopt(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4)),
+        #[cfg(syscall209)]
+        SYSCALL_GETSOCKOPT => sys_getsockopt(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4)),
+        #[cfg(syscall210)]
+        SYSCALL_SHUTDOWN => sys_shutdown(args.get(0), args.get(1)),
         #[cfg(syscall214)]
-        SYSCALL_BRK => { println!("sys_brk {:#x}", args[0]); sys_brk(args[0] as *const u8) },
-        _ => panic!("Unsupported syscall_id: {}", syscall_id),
+        SYSCALL_BRK => sys_brk(args.get(0)),
+        #[cfg(syscall215)]
+        SYSCALL_MUNMAP => sys_munmap(args.get(0), args.get(1)),
+        #[cfg(syscall216)]
+        SYSCALL_MREMAP => sys_mremap(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4)),
+        #[cfg(syscall220)]
+        SYSCALL_CLONE => sys_clone(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4)),
+        #[cfg(syscall222)]
+        SYSCALL_MMAP => sys_mmap(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4), args.get(5)),
+        #[cfg(syscall226)]
+        SYSCALL_MPROTECT => sys_mprotect(args.get(0), args.get(1), args.get(2)),
+        #[cfg(syscall242)]
+        SYSCALL_ACCEPT4 => sys_accept4(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall261)]
+        SYSCALL_PRLIMIT64 => sys_prlimit64(args.get(0), args.get(1), args.get(2), args.get(3)),
+        #[cfg(syscall276)]
+        SYSCALL_RENAMEAT2 => sys_renameat2(args.get(0), args.get(1), args.get(2), args.get(3), args.get(4)),
+        _ => {
+            if LOG_UNSUPPORTED_SYSCALL {
+                println!("[kernel] [warn] Unsupported syscall_id: {}", syscall_id);
+            }
+            Err(Errno::ENOSYS)
+        }
     }
 }
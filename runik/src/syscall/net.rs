@@ -0,0 +1,241 @@
+//! Socket syscalls
+
+use super::SyscallResult;
+use crate::config::IO_BUFFER_SIZE;
+use crate::fs::{ OpenFile, OpenFlags };
+use crate::fs::fd_table::FD_TABLE;
+use crate::mm::user_ptr::{ UserPtr, UserSlice };
+use crate::net::{ Socket, SocketKind };
+use crate::task::signal;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::mem::size_of;
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::signal::SIGPIPE;
+use linuxabi::socket::*;
+use smoltcp::wire::{ IpAddress, IpEndpoint, Ipv4Address };
+
+/// The number in the name of the next socket, as in "socket:[N]"
+static NEXT_SOCKET: AtomicUsize = AtomicUsize::new(1);
+
+const SOCKADDR_IN_LEN: usize = size_of::<SockAddrIn>();
+
+fn read_sockaddr(addr: UserPtr<SockAddrIn>, addrlen: u32) -> Result<IpEndpoint, Errno> {
+    if (addrlen as usize) < SOCKADDR_IN_LEN {
+        return Err(EINVAL);
+    }
+    let addr = addr.read()?;
+    if addr.sin_family != AF_INET {
+        return Err(EAFNOSUPPORT);
+    }
+    Ok(IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(addr.sin_addr)), u16::from_be(addr.sin_port)))
+}
+
+/// Write `endpoint` to `addr`, truncated to `*addrlen`, which is set to the
+/// full length
+fn write_sockaddr(addr: UserPtr<u8>, addrlen: UserPtr<u32>, endpoint: IpEndpoint) -> Result<(), Errno> {
+    if addr.is_null() {
+        return Ok(());
+    }
+    let len = addrlen.read()? as usize;
+    let IpAddress::Ipv4(ip) = endpoint.addr;
+    let sockaddr = SockAddrIn {
+        sin_family: AF_INET,
+        sin_port: endpoint.port.to_be(),
+        sin_addr: ip.0,
+        sin_zero: [0; 8],
+    };
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&sockaddr as *const SockAddrIn as *const u8, SOCKADDR_IN_LEN)
+    };
+    UserSlice::new(addr, len.min(SOCKADDR_IN_LEN)).write(&bytes[..len.min(SOCKADDR_IN_LEN)])?;
+    addrlen.write(SOCKADDR_IN_LEN as u32)
+}
+
+/// Put `socket` in the fd table with the SOCK_NONBLOCK and SOCK_CLOEXEC of
+/// `flags`
+fn insert_socket(socket: Socket, flags: u32) -> SyscallResult {
+    let mut open_flags = OpenFlags::RDWR;
+    if flags & SOCK_NONBLOCK != 0 {
+        open_flags |= OpenFlags::NONBLOCK;
+    }
+    let path = format!("socket:[{}]", NEXT_SOCKET.fetch_add(1, Ordering::Relaxed));
+    let file = Arc::new(OpenFile::new(Arc::new(socket), None, path, open_flags));
+    let fd = FD_TABLE.exclusive_access().insert(file, flags & SOCK_CLOEXEC != 0, 0)?;
+    Ok(fd as usize)
+}
+
+fn get_socket(fd: i32) -> Result<Arc<OpenFile>, Errno> {
+    let file = FD_TABLE.exclusive_access().get(fd)?;
+    file.socket()?;
+    Ok(file)
+}
+
+/// create a socket of the type `ty` in `domain`
+pub fn sys_socket(domain: u32, ty: u32, protocol: u32) -> SyscallResult {
+    if domain != AF_INET as u32 {
+        return Err(EAFNOSUPPORT);
+    }
+    if ty & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(EINVAL);
+    }
+    let kind = match (ty & SOCK_TYPE_MASK, protocol) {
+        (SOCK_STREAM, IPPROTO_IP | IPPROTO_TCP) => SocketKind::Tcp,
+        (SOCK_DGRAM, IPPROTO_IP | IPPROTO_UDP) => SocketKind::Udp,
+        (SOCK_STREAM | SOCK_DGRAM, _) => return Err(EPROTONOSUPPORT),
+        _ => return Err(EINVAL),
+    };
+    insert_socket(Socket::new(kind)?, ty & !SOCK_TYPE_MASK)
+}
+
+/// bind the socket `fd` to `addr`
+pub fn sys_bind(fd: i32, addr: UserPtr<SockAddrIn>, addrlen: u32) -> SyscallResult {
+    let file = get_socket(fd)?;
+    file.socket()?.bind(read_sockaddr(addr, addrlen)?)?;
+    Ok(0)
+}
+
+/// listen for connections on the socket `fd`
+pub fn sys_listen(fd: i32, backlog: i32) -> SyscallResult {
+    let file = get_socket(fd)?;
+    file.socket()?.listen(backlog)?;
+    Ok(0)
+}
+
+/// accept a connection on the socket `fd`
+pub fn sys_accept(fd: i32, addr: UserPtr<u8>, addrlen: UserPtr<u32>) -> SyscallResult {
+    sys_accept4(fd, addr, addrlen, 0)
+}
+
+/// connect the socket `fd` to `addr`
+pub fn sys_connect(fd: i32, addr: UserPtr<SockAddrIn>, addrlen: u32) -> SyscallResult {
+    let file = get_socket(fd)?;
+    file.socket()?.connect(read_sockaddr(addr, addrlen)?)?;
+    Ok(0)
+}
+
+/// get the address the socket `fd` is bound to
+pub fn sys_getsockname(fd: i32, addr: UserPtr<u8>, addrlen: UserPtr<u32>) -> SyscallResult {
+    let file = get_socket(fd)?;
+    write_sockaddr(addr, addrlen, file.socket()?.local_addr())?;
+    Ok(0)
+}
+
+/// get the address of the peer of the socket `fd`
+pub fn sys_getpeername(fd: i32, addr: UserPtr<u8>, addrlen: UserPtr<u32>) -> SyscallResult {
+    let file = get_socket(fd)?;
+    write_sockaddr(addr, addrlen, file.socket()?.peer_addr()?)?;
+    Ok(0)
+}
+
+/// send `len` bytes of `buf` on the socket `fd`, to `addr` if not null
+pub fn sys_sendto(fd: i32, buf: UserPtr<u8>, len: usize, flags: u32, addr: UserPtr<SockAddrIn>, addrlen: u32) -> SyscallResult {
+    let file = get_socket(fd)?;
+    let socket = file.socket()?;
+    let dest = if addr.is_null() { None } else { Some(read_sockaddr(addr, addrlen)?) };
+    let nonblocking = flags & MSG_DONTWAIT != 0;
+    let buf = UserSlice::new(buf, len);
+    let result = send(socket, buf, dest, nonblocking);
+    if result == Err(EPIPE) && flags & MSG_NOSIGNAL == 0 {
+        signal::raise(SIGPIPE);
+    }
+    result
+}
+
+/// Send `buf` on `socket`, as sendto(2) does
+fn send(socket: &Socket, buf: UserSlice, dest: Option<IpEndpoint>, nonblocking: bool) -> SyscallResult {
+    let len = buf.len();
+    if socket.kind() == SocketKind::Udp {
+        return socket.send(&buf.read()?, dest, nonblocking);
+    }
+    // send a stream in pieces, stopping early only if it would block
+    let mut kbuf = vec![0u8; len.min(IO_BUFFER_SIZE)];
+    let mut total = 0;
+    while total < len {
+        let want = (len - total).min(kbuf.len());
+        buf.skip(total).read_into(&mut kbuf[..want])?;
+        let n = match socket.send(&kbuf[..want], dest, nonblocking) {
+            Ok(n) => n,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += n;
+    }
+    Ok(total)
+}
+
+/// receive up to `len` bytes into `buf` from the socket `fd`, writing where
+/// they came from to `addr` if not null
+pub fn sys_recvfrom(fd: i32, buf: UserPtr<u8>, len: usize, flags: u32, addr: UserPtr<u8>, addrlen: UserPtr<u32>) -> SyscallResult {
+    let file = get_socket(fd)?;
+    let mut kbuf = vec![0u8; len.min(IO_BUFFER_SIZE)];
+    let (n, from) = file.socket()?.recv(&mut kbuf, flags & MSG_DONTWAIT != 0, flags & MSG_PEEK != 0)?;
+    UserSlice::new(buf, n).write(&kbuf[..n])?;
+    if let Some(from) = from {
+        write_sockaddr(addr, addrlen, from)?;
+    }
+    Ok(n)
+}
+
+/// set the option `optname` at `level` of the socket `fd`
+pub fn sys_setsockopt(fd: i32, level: u32, optname: u32, optval: UserPtr<u8>, optlen: u32) -> SyscallResult {
+    let file = get_socket(fd)?;
+    let socket = file.socket()?;
+    if (optlen as usize) < size_of::<i32>() {
+        return Err(EINVAL);
+    }
+    let value = UserPtr::<i32>::new(optval.addr()).read()?;
+    match (level, optname) {
+        (IPPROTO_TCP, TCP_NODELAY) => socket.set_nodelay(value != 0)?,
+        // accepted, though the stack has no use for them
+        (SOL_SOCKET, SO_REUSEADDR | SO_REUSEPORT | SO_KEEPALIVE | SO_BROADCAST | SO_SNDBUF | SO_RCVBUF) => {}
+        _ => return Err(ENOPROTOOPT),
+    }
+    Ok(0)
+}
+
+/// get the option `optname` at `level` of the socket `fd`
+pub fn sys_getsockopt(fd: i32, level: u32, optname: u32, optval: UserPtr<u8>, optlen: UserPtr<u32>) -> SyscallResult {
+    let file = get_socket(fd)?;
+    let socket = file.socket()?;
+    let value = match (level, optname) {
+        (SOL_SOCKET, SO_TYPE) => match socket.kind() {
+            SocketKind::Tcp => SOCK_STREAM as i32,
+            SocketKind::Udp => SOCK_DGRAM as i32,
+        },
+        (SOL_SOCKET, SO_ERROR) => 0,
+        (IPPROTO_TCP, TCP_NODELAY) => socket.nodelay() as i32,
+        _ => return Err(ENOPROTOOPT),
+    };
+    let len = (optlen.read()? as usize).min(size_of::<i32>());
+    UserSlice::new(optval, len).write(&value.to_ne_bytes()[..len])?;
+    optlen.write(len as u32)?;
+    Ok(0)
+}
+
+/// stop receiving, sending or both on the socket `fd`
+pub fn sys_shutdown(fd: i32, how: u32) -> SyscallResult {
+    let file = get_socket(fd)?;
+    let (read, write) = match how {
+        SHUT_RD => (true, false),
+        SHUT_WR => (false, true),
+        SHUT_RDWR => (true, true),
+        _ => return Err(EINVAL),
+    };
+    file.socket()?.shutdown(read, write)?;
+    Ok(0)
+}
+
+/// accept a connection on the socket `fd`, with SOCK_NONBLOCK and
+/// SOCK_CLOEXEC in `flags` for the new socket
+pub fn sys_accept4(fd: i32, addr: UserPtr<u8>, addrlen: UserPtr<u32>, flags: u32) -> SyscallResult {
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(EINVAL);
+    }
+    let file = get_socket(fd)?;
+    let (socket, peer) = file.socket()?.accept()?;
+    write_sockaddr(addr, addrlen, peer)?;
+    insert_socket(socket, flags)
+}
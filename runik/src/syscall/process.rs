@@ -1,7 +1,180 @@
 use crate::sbi::shutdown;
+use crate::config::{ USER_STACK_RLIMIT, FD_LIMIT };
+use crate::mm::addr_space::KERNEL_SPACE;
+use crate::mm::user_ptr::UserPtr;
+use crate::task::{ self, futex };
+use crate::time;
+use super::SyscallResult;
+use super::time::from_timespec;
+use linuxabi::errno::Errno::*;
+use linuxabi::futex::*;
+use linuxabi::resource::*;
+use linuxabi::sched::*;
+use linuxabi::time::TimeSpec;
 
-/// task exits and submit an exit code
+/// the calling thread exits, which is the app exiting with `exit_code` if
+/// it is the last
 pub fn sys_exit(exit_code: i32) -> ! {
+    let thread = task::current();
+    let clear_child_tid = UserPtr::<i32>::new(thread.clear_child_tid());
+    drop(thread);
+    if task::thread_count() == 1 {
+        sys_exit_group(exit_code);
+    }
+    // for pthread_join(), which waits on the futex
+    if !clear_child_tid.is_null() && clear_child_tid.write(0).is_ok() {
+        futex::wake_up(clear_child_tid.addr(), 1, FUTEX_BITSET_MATCH_ANY);
+    }
+    task::exit_current();
+}
+
+/// the app exits with `exit_code`, ending all its threads
+pub fn sys_exit_group(exit_code: i32) -> ! {
     println!("[kernel] Application exited with code {}", exit_code);
     shutdown(exit_code != 0);
 }
+
+/// create a thread sharing the address space, which starts returning 0 on
+/// the stack `stack`, as the app has no way to have other processes
+pub fn sys_clone(flags: u32, stack: usize, parent_tid: UserPtr<i32>, tls: usize, child_tid: UserPtr<i32>) -> SyscallResult {
+    if flags & (CLONE_VM | CLONE_THREAD) != CLONE_VM | CLONE_THREAD {
+        return Err(EINVAL);
+    }
+    let mut cx = *task::current().trap_context();
+    cx.x[10] = 0;
+    if stack != 0 {
+        cx.set_sp(stack);
+    }
+    if flags & CLONE_SETTLS != 0 {
+        cx.set_tp(tls);
+    }
+    let thread = task::spawn(cx)?;
+    // the thread starts with the FP registers of the caller
+    thread.save_fp();
+    let mask = task::current().signals().mask;
+    thread.signals().set_mask(mask);
+    let tid = thread.tid();
+    // The thread runs anyway, so failing to write the tid is ignored, as
+    // Linux does
+    if flags & CLONE_PARENT_SETTID != 0 {
+        let _ = parent_tid.write(tid as i32);
+    }
+    if flags & CLONE_CHILD_SETTID != 0 {
+        let _ = child_tid.write(tid as i32);
+    }
+    if flags & CLONE_CHILD_CLEARTID != 0 {
+        thread.set_clear_child_tid(child_tid.addr());
+    }
+    Ok(tid)
+}
+
+/// set the address of the tid to clear when the calling thread exits
+pub fn sys_set_tid_address(tidptr: UserPtr<i32>) -> SyscallResult {
+    let thread = task::current();
+    thread.set_clear_child_tid(tidptr.addr());
+    Ok(thread.tid())
+}
+
+/// get the pid of the app
+pub fn sys_getpid() -> SyscallResult {
+    Ok(task::PID)
+}
+
+/// get the tid of the calling thread
+pub fn sys_gettid() -> SyscallResult {
+    Ok(task::current().tid())
+}
+
+/// let the other threads run
+pub fn sys_sched_yield() -> SyscallResult {
+    task::yield_now();
+    Ok(0)
+}
+
+/// get and set resource limits, of which only RLIMIT_STACK is enforced
+pub fn sys_prlimit64(pid: i32, resource: u32, new_limit: UserPtr<RLimit>, old_limit: UserPtr<RLimit>) -> SyscallResult {
+    if resource >= RLIM_NLIMITS {
+        return Err(EINVAL);
+    }
+    // the limits are of the app, which any of its threads may name
+    if pid < 0 || (pid != 0 && pid as usize != task::PID && task::find(pid as usize).is_none()) {
+        return Err(ESRCH);
+    }
+    let new = if new_limit.is_null() { None } else { Some(new_limit.read()?) };
+    if !old_limit.is_null() {
+        let limit = match resource {
+            RLIMIT_STACK => {
+                let rlimit = KERNEL_SPACE.exclusive_access().stack_rlimit() as u64;
+                RLimit { rlim_cur: rlimit, rlim_max: USER_STACK_RLIMIT as u64 }
+            }
+            RLIMIT_NOFILE => RLimit { rlim_cur: FD_LIMIT as u64, rlim_max: FD_LIMIT as u64 },
+            _ => RLimit { rlim_cur: RLIM_INFINITY, rlim_max: RLIM_INFINITY },
+        };
+        old_limit.write(limit)?;
+    }
+    if let Some(limit) = new {
+        if limit.rlim_cur > limit.rlim_max {
+            return Err(EINVAL);
+        }
+        if resource == RLIMIT_STACK {
+            KERNEL_SPACE.exclusive_access().set_stack_rlimit(limit.rlim_cur as usize)?;
+        }
+    }
+    Ok(0)
+}
+
+/// wait on or wake the futex at `uaddr`, where the fourth argument is the
+/// timeout of waits, and the count to requeue of requeues
+pub fn sys_futex(uaddr: UserPtr<u32>, op: u32, val: u32, timeout: usize, uaddr2: UserPtr<u32>, val3: u32) -> SyscallResult {
+    if uaddr.addr() % 4 != 0 {
+        return Err(EINVAL);
+    }
+    let cmd = op & FUTEX_CMD_MASK;
+    // Only the waits with an absolute timeout may take it on CLOCK_REALTIME
+    if op & FUTEX_CLOCK_REALTIME != 0 && cmd != FUTEX_WAIT_BITSET {
+        return Err(ENOSYS);
+    }
+    match cmd {
+        FUTEX_WAIT | FUTEX_WAIT_BITSET => {
+            let bitset = if cmd == FUTEX_WAIT { FUTEX_BITSET_MATCH_ANY } else { val3 };
+            if bitset == 0 {
+                return Err(EINVAL);
+            }
+            let timeout = UserPtr::<TimeSpec>::new(timeout);
+            let deadline = if timeout.is_null() {
+                None
+            } else {
+                let ns = from_timespec(timeout.read()?)?;
+                let now = time::monotonic_ns();
+                Some(if cmd == FUTEX_WAIT {
+                    now.saturating_add(ns as u64)
+                } else if op & FUTEX_CLOCK_REALTIME != 0 {
+                    // the distance to the deadline is the same on the monotonic clock
+                    now.saturating_add(ns.saturating_sub(time::realtime_ns()).max(0) as u64)
+                } else {
+                    ns as u64
+                })
+            };
+            futex::wait(uaddr, val, bitset, deadline)?;
+            Ok(0)
+        }
+        FUTEX_WAKE | FUTEX_WAKE_BITSET => {
+            let bitset = if cmd == FUTEX_WAKE { FUTEX_BITSET_MATCH_ANY } else { val3 };
+            if bitset == 0 {
+                return Err(EINVAL);
+            }
+            Ok(futex::wake_up(uaddr.addr(), val as usize, bitset))
+        }
+        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+            let requeue = timeout as u32 as i32;
+            if uaddr2.addr() % 4 != 0 || (val as i32) < 0 || requeue < 0 {
+                return Err(EINVAL);
+            }
+            if cmd == FUTEX_CMP_REQUEUE && uaddr.read()? != val3 {
+                return Err(EAGAIN);
+            }
+            Ok(futex::requeue(uaddr.addr(), val as usize, uaddr2.addr(), requeue as usize))
+        }
+        _ => Err(ENOSYS),
+    }
+}
@@ -0,0 +1,125 @@
+//! Signal syscalls
+
+use super::SyscallResult;
+use crate::mm::user_ptr::UserPtr;
+use crate::task::{ self, signal, PID };
+use core::mem::size_of;
+use linuxabi::errno::Errno::*;
+use linuxabi::signal::*;
+
+/// examine and change the action of `signo`
+pub fn sys_rt_sigaction(signo: u32, act: UserPtr<SigAction>, oldact: UserPtr<SigAction>, sigsetsize: usize) -> SyscallResult {
+    if sigsetsize != size_of::<SigSet>() || !signal::is_valid(signo) {
+        return Err(EINVAL);
+    }
+    let new = if act.is_null() { None } else { Some(act.read()?) };
+    if new.is_some() && signal::is_unblockable(signo) {
+        return Err(EINVAL);
+    }
+    if !oldact.is_null() {
+        oldact.write(signal::action(signo))?;
+    }
+    if let Some(action) = new {
+        signal::set_action(signo, action);
+    }
+    Ok(0)
+}
+
+/// examine and change the signals the calling thread blocks
+pub fn sys_rt_sigprocmask(how: u32, set: UserPtr<SigSet>, oldset: UserPtr<SigSet>, sigsetsize: usize) -> SyscallResult {
+    if sigsetsize != size_of::<SigSet>() {
+        return Err(EINVAL);
+    }
+    let new = if set.is_null() { None } else { Some(set.read()?) };
+    let thread = task::current();
+    let old = thread.signals().mask;
+    if let Some(set) = new {
+        let mask = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(EINVAL),
+        };
+        thread.signals().set_mask(mask);
+    }
+    if !oldset.is_null() {
+        oldset.write(old)?;
+    }
+    Ok(0)
+}
+
+/// examine and change the alternate signal stack of the calling thread,
+/// which can not change while the thread is on it
+pub fn sys_sigaltstack(ss: UserPtr<SignalStack>, old_ss: UserPtr<SignalStack>) -> SyscallResult {
+    let thread = task::current();
+    let sp = thread.trap_context().x[2];
+    let new = if ss.is_null() { None } else { Some(ss.read()?) };
+    let mut signals = thread.signals();
+    let old = SignalStack { ss_flags: signals.altstack_flags(sp), ..signals.altstack };
+    if let Some(stack) = new {
+        if signals.is_on_altstack(sp) {
+            return Err(EPERM);
+        }
+        signals.altstack = match stack.ss_flags {
+            SS_DISABLE => SignalStack::default(),
+            0 | SS_ONSTACK if stack.ss_size < MINSIGSTKSZ => return Err(ENOMEM),
+            0 | SS_ONSTACK => SignalStack { ss_flags: 0, ..stack },
+            _ => return Err(EINVAL),
+        };
+    }
+    drop(signals);
+    if !old_ss.is_null() {
+        old_ss.write(old)?;
+    }
+    Ok(0)
+}
+
+/// send `signo` to the app, which is the only process, and only checks that
+/// it may if `signo` is 0
+pub fn sys_kill(pid: i32, signo: u32) -> SyscallResult {
+    if signo != 0 && !signal::is_valid(signo) {
+        return Err(EINVAL);
+    }
+    // the app is also its own process group
+    if ![0, -1, PID as i32, -(PID as i32)].contains(&pid) {
+        return Err(ESRCH);
+    }
+    if signo != 0 {
+        let mut info = SigInfo::new(signo, SI_USER);
+        info.si_fields[0] = PID;
+        signal::send_to_app(info);
+    }
+    Ok(0)
+}
+
+/// send `signo` to the thread `tid`
+pub fn sys_tkill(tid: i32, signo: u32) -> SyscallResult {
+    sys_tgkill(PID as i32, tid, signo)
+}
+
+/// send `signo` to the thread `tid` of the process `tgid`
+pub fn sys_tgkill(tgid: i32, tid: i32, signo: u32) -> SyscallResult {
+    if tgid <= 0 || tid <= 0 || (signo != 0 && !signal::is_valid(signo)) {
+        return Err(EINVAL);
+    }
+    if tgid as usize != PID {
+        return Err(ESRCH);
+    }
+    let thread = task::find(tid as usize).ok_or(ESRCH)?;
+    if signo != 0 {
+        let mut info = SigInfo::new(signo, SI_TKILL);
+        info.si_fields[0] = PID;
+        signal::send_to_thread(&thread, info);
+    }
+    Ok(0)
+}
+
+/// return from a signal handler to where the signal came, as the frame on
+/// the stack saved it
+pub fn sys_rt_sigreturn() -> SyscallResult {
+    let cx = task::current().trap_context();
+    signal::sigreturn(cx).or_else(|_| {
+        signal::force(SIGSEGV, SI_KERNEL, 0);
+        Ok(cx.x[10])
+    })
+}
@@ -0,0 +1,165 @@
+//! Clock and sleep syscalls
+
+use super::SyscallResult;
+use crate::mm::user_ptr::UserPtr;
+use crate::task::{ self, itimer };
+use crate::time::{ self, NSEC_PER_SEC };
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::time::*;
+
+fn to_timespec(ns: i64) -> TimeSpec {
+    TimeSpec {
+        tv_sec: ns.div_euclid(NSEC_PER_SEC as i64),
+        tv_nsec: ns.rem_euclid(NSEC_PER_SEC as i64),
+    }
+}
+
+/// A timeval of `ns`, rounded up to whole microseconds
+fn to_timeval(ns: u64) -> TimeVal {
+    let us = ns.div_ceil(1000);
+    TimeVal { tv_sec: (us / 1_000_000) as i64, tv_usec: (us % 1_000_000) as i64 }
+}
+
+/// The nanoseconds of a timeval from the app, which must be valid
+fn from_timeval(tv: TimeVal) -> Result<u64, Errno> {
+    if tv.tv_sec < 0 || !(0..1_000_000).contains(&tv.tv_usec) {
+        return Err(EINVAL);
+    }
+    Ok((tv.tv_sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(tv.tv_usec as u64 * 1000))
+}
+
+/// The nanoseconds of a timespec from the app, which must be valid
+pub(super) fn from_timespec(ts: TimeSpec) -> Result<i64, Errno> {
+    if ts.tv_sec < 0 || !(0..NSEC_PER_SEC as i64).contains(&ts.tv_nsec) {
+        return Err(EINVAL);
+    }
+    Ok(ts.tv_sec.saturating_mul(NSEC_PER_SEC as i64).saturating_add(ts.tv_nsec))
+}
+
+/// Whether `clock` counts from the epoch, for the clocks supported
+fn is_realtime(clock: i32) -> Result<bool, Errno> {
+    match clock {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Ok(true),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME
+            | CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => Ok(false),
+        _ => Err(EINVAL),
+    }
+}
+
+/// The time of `clock` in nanoseconds, where the CPU-time clocks count the
+/// time the scheduler charged to the thread or the app
+fn clock_ns(clock: i32) -> Result<i64, Errno> {
+    Ok(match clock {
+        CLOCK_THREAD_CPUTIME_ID => task::cpu_time().0 as i64,
+        CLOCK_PROCESS_CPUTIME_ID => task::cpu_time().1 as i64,
+        _ if is_realtime(clock)? => time::realtime_ns(),
+        _ => time::monotonic_ns() as i64,
+    })
+}
+
+/// get the time of `clock`
+pub fn sys_clock_gettime(clock: i32, tp: UserPtr<TimeSpec>) -> SyscallResult {
+    tp.write(to_timespec(clock_ns(clock)?))?;
+    Ok(0)
+}
+
+/// set the time of `clock`, which only CLOCK_REALTIME allows
+pub fn sys_clock_settime(clock: i32, tp: UserPtr<TimeSpec>) -> SyscallResult {
+    if clock != CLOCK_REALTIME {
+        return Err(if is_realtime(clock).is_ok() { EPERM } else { EINVAL });
+    }
+    time::set_realtime_ns(from_timespec(tp.read()?)?);
+    Ok(0)
+}
+
+/// get the resolution of `clock`
+pub fn sys_clock_getres(clock: i32, res: UserPtr<TimeSpec>) -> SyscallResult {
+    is_realtime(clock)?;
+    if !res.is_null() {
+        res.write(to_timespec(time::resolution_ns() as i64))?;
+    }
+    Ok(0)
+}
+
+/// get the time since the epoch, with no timezone
+pub fn sys_gettimeofday(tv: UserPtr<TimeVal>, tz: UserPtr<TimeZone>) -> SyscallResult {
+    if !tv.is_null() {
+        let ts = to_timespec(time::realtime_ns());
+        tv.write(TimeVal { tv_sec: ts.tv_sec, tv_usec: ts.tv_nsec / 1000 })?;
+    }
+    if !tz.is_null() {
+        tz.write(TimeZone::default())?;
+    }
+    Ok(0)
+}
+
+/// Sleep until the monotonic clock reaches `deadline`, writing the time
+/// left to `rem` if a signal comes first
+fn sleep(deadline: u64, rem: UserPtr<TimeSpec>) -> SyscallResult {
+    if let Err(errno) = time::sleep_until(deadline) {
+        if !rem.is_null() {
+            let left = deadline.saturating_sub(time::monotonic_ns());
+            rem.write(to_timespec(left as i64))?;
+        }
+        return Err(errno);
+    }
+    Ok(0)
+}
+
+/// sleep for the duration `req`
+pub fn sys_nanosleep(req: UserPtr<TimeSpec>, rem: UserPtr<TimeSpec>) -> SyscallResult {
+    let duration = from_timespec(req.read()?)? as u64;
+    sleep(time::monotonic_ns().saturating_add(duration), rem)
+}
+
+/// sleep for the duration `req` of `clock`, or until `clock` reaches `req`
+/// with TIMER_ABSTIME
+pub fn sys_clock_nanosleep(clock: i32, flags: u32, req: UserPtr<TimeSpec>, rem: UserPtr<TimeSpec>) -> SyscallResult {
+    if matches!(clock, CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID) {
+        return Err(EINVAL);
+    }
+    is_realtime(clock)?;
+    let req = from_timespec(req.read()?)?;
+    let now = time::monotonic_ns();
+    let deadline = if flags & TIMER_ABSTIME == 0 {
+        now.saturating_add(req as u64)
+    } else {
+        // the distance to the deadline is the same on the monotonic clock
+        let ahead = req.saturating_sub(clock_ns(clock)?);
+        if ahead <= 0 {
+            return Ok(0);
+        }
+        now.saturating_add(ahead as u64)
+    };
+    // the time left is only for relative sleeps
+    let rem = if flags & TIMER_ABSTIME == 0 { rem } else { UserPtr::new(0) };
+    sleep(deadline, rem)
+}
+
+/// get the time left and the interval of the timer `which`, of which only
+/// ITIMER_REAL is supported
+pub fn sys_getitimer(which: i32, curr_value: UserPtr<ITimerVal>) -> SyscallResult {
+    if which != ITIMER_REAL {
+        return Err(EINVAL);
+    }
+    let (value, interval) = itimer::get();
+    curr_value.write(ITimerVal { it_interval: to_timeval(interval), it_value: to_timeval(value) })?;
+    Ok(0)
+}
+
+/// arm or disarm the timer `which`, of which only ITIMER_REAL is supported,
+/// raising SIGALRM on expiry
+pub fn sys_setitimer(which: i32, new_value: UserPtr<ITimerVal>, old_value: UserPtr<ITimerVal>) -> SyscallResult {
+    if which != ITIMER_REAL {
+        return Err(EINVAL);
+    }
+    // a null new value disarms the timer, as it has for Linux
+    let new = if new_value.is_null() { ITimerVal::default() } else { new_value.read()? };
+    let value = from_timeval(new.it_value)?;
+    let interval = from_timeval(new.it_interval)?;
+    let (old, old_interval) = itimer::set(value, interval);
+    if !old_value.is_null() {
+        old_value.write(ITimerVal { it_interval: to_timeval(old_interval), it_value: to_timeval(old) })?;
+    }
+    Ok(0)
+}
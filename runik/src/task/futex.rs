@@ -0,0 +1,116 @@
+//! mod futex: wait queues on words of the app
+//!
+//! The app has one address space, so a futex is keyed by the virtual
+//! address of its word. The word is checked and the waiter queued with no
+//! other thread running in between, so no wake between the two is missed.
+
+use super::{ block_current, current, signal, wake, Thread };
+use crate::mm::user_ptr::UserPtr;
+use crate::sync::UPSafeCell;
+use crate::time;
+use alloc::collections::{ BTreeMap, VecDeque };
+use alloc::sync::Arc;
+use lazy_static::*;
+use linuxabi::errno::Errno::{ self, * };
+
+struct Waiter {
+    thread: Arc<Thread>,
+    /// The bits of FUTEX_WAIT_BITSET, which wakes must share
+    bitset: u32,
+}
+
+lazy_static! {
+    /// The waiters of each futex, in the order they came
+    static ref FUTEXES: UPSafeCell<BTreeMap<usize, VecDeque<Waiter>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// Remove the waiter of `thread` from whichever futex it is on, returning
+/// whether there was one
+fn dequeue(thread: &Arc<Thread>) -> bool {
+    let mut futexes = FUTEXES.exclusive_access();
+    let found = futexes.iter_mut().find_map(|(&addr, waiters)| {
+        let i = waiters.iter().position(|waiter| Arc::ptr_eq(&waiter.thread, thread))?;
+        waiters.remove(i);
+        Some(addr)
+    });
+    match found {
+        Some(addr) => {
+            if futexes.get(&addr).is_some_and(|waiters| waiters.is_empty()) {
+                futexes.remove(&addr);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Block on the futex at `uaddr` if it holds `val`, until woken with a
+/// bit of `bitset`, the monotonic clock reaches `deadline` or a signal comes
+pub fn wait(uaddr: UserPtr<u32>, val: u32, bitset: u32, deadline: Option<u64>) -> Result<(), Errno> {
+    if uaddr.read()? != val {
+        return Err(EAGAIN);
+    }
+    if signal::interrupted() {
+        return Err(EINTR);
+    }
+    let thread = current();
+    FUTEXES.exclusive_access()
+        .entry(uaddr.addr())
+        .or_default()
+        .push_back(Waiter { thread: thread.clone(), bitset });
+    block_current(deadline);
+    // a waiter that was woken has been taken off
+    if dequeue(&thread) {
+        let timed_out = deadline.is_some_and(|deadline| time::monotonic_ns() >= deadline);
+        return Err(if timed_out { ETIMEDOUT } else { EINTR });
+    }
+    Ok(())
+}
+
+/// Wake up to `count` waiters on the futex at `uaddr` that share a bit of
+/// `bitset`, returning how many there were
+pub fn wake_up(uaddr: usize, count: usize, bitset: u32) -> usize {
+    let mut futexes = FUTEXES.exclusive_access();
+    let Some(waiters) = futexes.get_mut(&uaddr) else {
+        return 0;
+    };
+    let mut woken = 0;
+    let mut i = 0;
+    while woken < count && i < waiters.len() {
+        if waiters[i].bitset & bitset == 0 {
+            i += 1;
+            continue;
+        }
+        let waiter = waiters.remove(i).unwrap();
+        wake(&waiter.thread);
+        woken += 1;
+    }
+    if waiters.is_empty() {
+        futexes.remove(&uaddr);
+    }
+    woken
+}
+
+/// Wake up to `count` waiters on the futex at `uaddr` and move up to
+/// `requeue` of the rest to the futex at `uaddr2`, returning how many were
+/// woken and moved
+pub fn requeue(uaddr: usize, count: usize, uaddr2: usize, requeue: usize) -> usize {
+    let woken = wake_up(uaddr, count, u32::MAX);
+    if uaddr == uaddr2 {
+        return woken;
+    }
+    let mut futexes = FUTEXES.exclusive_access();
+    let Some(waiters) = futexes.get_mut(&uaddr) else {
+        return woken;
+    };
+    let moved: VecDeque<Waiter> = waiters.drain(..requeue.min(waiters.len())).collect();
+    if waiters.is_empty() {
+        futexes.remove(&uaddr);
+    }
+    let n = moved.len();
+    if n > 0 {
+        futexes.entry(uaddr2).or_default().extend(moved);
+    }
+    woken + n
+}
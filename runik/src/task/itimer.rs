@@ -0,0 +1,64 @@
+//! mod itimer: the real-time interval timer of setitimer(2)
+//!
+//! The timer is checked whenever waiting threads are woken, which is on
+//! every tick and interrupt, so it expires at the resolution of the tick.
+
+use super::signal;
+use crate::sync::UPSafeCell;
+use crate::time;
+use lazy_static::*;
+use linuxabi::signal::{ SigInfo, SIGALRM, SI_KERNEL };
+
+struct RealTimer {
+    /// When the timer expires next, on the monotonic clock in nanoseconds
+    deadline: u64,
+    /// The nanoseconds it is rearmed with on expiring, or 0 if it is not
+    interval: u64,
+}
+
+lazy_static! {
+    static ref REAL_TIMER: UPSafeCell<Option<RealTimer>> = unsafe { UPSafeCell::new(None) };
+}
+
+/// The nanoseconds until the timer expires, 0 if it is disarmed, and its
+/// interval
+pub fn get() -> (u64, u64) {
+    match &*REAL_TIMER.exclusive_access() {
+        // an expired timer not yet checked is about to go off
+        Some(timer) => (timer.deadline.saturating_sub(time::monotonic_ns()).max(1), timer.interval),
+        None => (0, 0),
+    }
+}
+
+/// Arm the timer to expire in `value` nanoseconds and then every
+/// `interval`, or disarm it if `value` is 0, returning what [`get()`] did
+pub fn set(value: u64, interval: u64) -> (u64, u64) {
+    let old = get();
+    *REAL_TIMER.exclusive_access() = (value != 0).then(|| RealTimer {
+        deadline: time::monotonic_ns().saturating_add(value),
+        interval,
+    });
+    old
+}
+
+/// Raise SIGALRM for the app if the timer expired, rearming it if it has
+/// an interval
+pub fn check() {
+    let now = time::monotonic_ns();
+    let mut timer = REAL_TIMER.exclusive_access();
+    let Some(real) = timer.as_mut() else {
+        return;
+    };
+    if real.deadline > now {
+        return;
+    }
+    if real.interval == 0 {
+        *timer = None;
+    } else {
+        // expirations missed in between are lost
+        let missed = (now - real.deadline) / real.interval;
+        real.deadline += (missed + 1) * real.interval;
+    }
+    drop(timer);
+    signal::send_to_app(SigInfo::new(SIGALRM, SI_KERNEL));
+}
@@ -0,0 +1,275 @@
+//! Threads and scheduling
+//!
+//! The app runs as threads sharing its address space, each with a kernel
+//! stack of its own. They take turns on the hart round robin: a thread runs
+//! until the timer ticks while it is in the app, or it waits in the kernel,
+//! or it exits, and then the next ready thread runs.
+//!
+//! A thread waiting in the kernel for a device or the time is parked until
+//! the next interrupt, which may be what it waits for, and then checks
+//! again. A thread waiting for another, as on a futex, is blocked until the
+//! other wakes it or its deadline passes. With nothing ready to run, the
+//! hart idles until an interrupt.
+//!
+//! The FP registers of a thread are saved on switching away from it, if it
+//! wrote them, and loaded on switching to it.
+//!
+//! The time a thread runs, in the app or in the kernel for it, is charged to
+//! it for its CPU-time clock. The time idling is charged to none.
+
+mod thread;
+pub mod futex;
+pub mod itimer;
+pub mod signal;
+
+pub use thread::{ Thread, PID };
+
+use crate::arch::task::switch;
+use crate::arch::trap::{ wait_for_interrupt, TrapContext };
+use crate::sync::UPSafeCell;
+use crate::time;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::RefMut;
+use lazy_static::*;
+use linuxabi::errno::Errno;
+
+struct Scheduler {
+    current: Option<Arc<Thread>>,
+    ready: VecDeque<Arc<Thread>>,
+    /// Threads parked until the next interrupt
+    waiting: Vec<Arc<Thread>>,
+    /// Threads blocked until woken, or until the monotonic clock reaches
+    /// their deadline in nanoseconds
+    blocked: Vec<(Arc<Thread>, Option<u64>)>,
+    /// A thread that has exited, kept until switched away from as its
+    /// kernel stack is in use until then
+    exited: Option<Arc<Thread>>,
+    /// The threads that have not exited
+    count: usize,
+    /// When the time of the current thread was last charged, on the
+    /// monotonic clock
+    since: u64,
+    /// The time charged to all the threads, exited ones included
+    cpu_ns: u64,
+}
+
+lazy_static! {
+    static ref SCHEDULER: UPSafeCell<Scheduler> = unsafe {
+        UPSafeCell::new(Scheduler {
+            current: None,
+            ready: VecDeque::new(),
+            waiting: Vec::new(),
+            blocked: Vec::new(),
+            exited: None,
+            count: 0,
+            since: 0,
+            cpu_ns: 0,
+        })
+    };
+}
+
+extern "C" {
+    fn __restore(cx_addr: usize);
+}
+
+/// Where new threads start: on their kernel stack, returning to the app
+fn thread_start() -> ! {
+    reap();
+    let cx = current().trap_context() as *mut TrapContext as usize;
+    unsafe {
+        __restore(cx);
+    }
+    unreachable!()
+}
+
+/// Free the thread switched away from if it exited
+fn reap() {
+    let exited = SCHEDULER.exclusive_access().exited.take();
+    drop(exited);
+}
+
+/// Charge the time since the last charge to the current thread
+fn charge(scheduler: &mut Scheduler) {
+    let now = time::monotonic_ns();
+    let ran = now.saturating_sub(scheduler.since);
+    scheduler.since = now;
+    scheduler.cpu_ns += ran;
+    scheduler.current.as_ref().unwrap().add_cpu_ns(ran);
+}
+
+/// Idle until an interrupt, charging the current thread for the time up to
+/// now only, and make the threads waiting for the interrupt ready
+fn idle(mut scheduler: RefMut<'_, Scheduler>) {
+    charge(&mut scheduler);
+    drop(scheduler);
+    wait_for_interrupt();
+    SCHEDULER.exclusive_access().since = time::monotonic_ns();
+    wake_waiting();
+}
+
+/// Run `next` in place of the current thread, which `park` puts away, and
+/// return once the current thread runs again
+fn switch_to(mut scheduler: RefMut<'_, Scheduler>, next: Arc<Thread>, park: fn(&mut Scheduler, Arc<Thread>)) {
+    charge(&mut scheduler);
+    let current = scheduler.current.replace(next.clone()).unwrap();
+    let current_cx = current.task_context_ptr();
+    let next_cx = next.task_context_ptr();
+    // the FP registers of the app stay on the hart until switched away from
+    if current.trap_context().fp_dirty() {
+        current.save_fp();
+    }
+    next.restore_fp();
+    // both stay alive in the scheduler
+    park(&mut scheduler, current);
+    drop(next);
+    drop(scheduler);
+    unsafe {
+        switch(current_cx, next_cx);
+    }
+    reap();
+}
+
+/// Run the first thread of the app, which returns to it with `cx`
+pub fn start(cx: TrapContext) -> ! {
+    let thread = Thread::new(cx, thread_start as usize).expect("no memory for the first thread");
+    let cx = thread.trap_context() as *mut TrapContext as usize;
+    let mut scheduler = SCHEDULER.exclusive_access();
+    scheduler.current = Some(Arc::new(thread));
+    scheduler.count = 1;
+    scheduler.since = time::monotonic_ns();
+    drop(scheduler);
+    unsafe {
+        __restore(cx);
+    }
+    unreachable!()
+}
+
+/// The thread running
+pub fn current() -> Arc<Thread> {
+    SCHEDULER.exclusive_access().current.clone().unwrap()
+}
+
+/// The CPU time of the current thread and that of the app, in nanoseconds
+pub fn cpu_time() -> (u64, u64) {
+    let mut scheduler = SCHEDULER.exclusive_access();
+    charge(&mut scheduler);
+    (scheduler.current.as_ref().unwrap().cpu_ns(), scheduler.cpu_ns)
+}
+
+/// The number of threads that have not exited
+pub fn thread_count() -> usize {
+    SCHEDULER.exclusive_access().count
+}
+
+/// The threads that have not exited
+pub fn threads() -> Vec<Arc<Thread>> {
+    let scheduler = SCHEDULER.exclusive_access();
+    scheduler.current.iter()
+        .chain(&scheduler.ready)
+        .chain(&scheduler.waiting)
+        .chain(scheduler.blocked.iter().map(|(thread, _)| thread))
+        .cloned()
+        .collect()
+}
+
+/// The thread with `tid`, if it has not exited
+pub fn find(tid: usize) -> Option<Arc<Thread>> {
+    threads().into_iter().find(|thread| thread.tid() == tid)
+}
+
+/// Make a thread that returns to the app with `cx`, ready to run after the
+/// others
+pub fn spawn(cx: TrapContext) -> Result<Arc<Thread>, Errno> {
+    let thread = Arc::new(Thread::new(cx, thread_start as usize)?);
+    let mut scheduler = SCHEDULER.exclusive_access();
+    scheduler.ready.push_back(thread.clone());
+    scheduler.count += 1;
+    Ok(thread)
+}
+
+/// Let the next ready thread run, if there is one
+pub fn yield_now() {
+    let mut scheduler = SCHEDULER.exclusive_access();
+    if let Some(next) = scheduler.ready.pop_front() {
+        switch_to(scheduler, next, |scheduler, current| scheduler.ready.push_back(current));
+    }
+}
+
+/// Make the threads waiting for an interrupt ready, on an interrupt, and
+/// those blocked whose deadline has passed, after raising SIGALRM if the
+/// timer of setitimer(2) expired
+pub fn wake_waiting() {
+    itimer::check();
+    let mut scheduler = SCHEDULER.exclusive_access();
+    let waiting = core::mem::take(&mut scheduler.waiting);
+    scheduler.ready.extend(waiting);
+    let now = time::monotonic_ns();
+    let mut i = 0;
+    while i < scheduler.blocked.len() {
+        if scheduler.blocked[i].1.is_some_and(|deadline| deadline <= now) {
+            let (thread, _) = scheduler.blocked.swap_remove(i);
+            scheduler.ready.push_back(thread);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Make `thread` ready if it is blocked, returning whether it was
+pub fn wake(thread: &Arc<Thread>) -> bool {
+    let mut scheduler = SCHEDULER.exclusive_access();
+    let Some(i) = scheduler.blocked.iter().position(|(blocked, _)| Arc::ptr_eq(blocked, thread)) else {
+        return false;
+    };
+    let (thread, _) = scheduler.blocked.swap_remove(i);
+    scheduler.ready.push_back(thread);
+    true
+}
+
+/// Stop the current thread until [`wake()`] is called on it or the
+/// monotonic clock reaches `deadline`, and run the others meanwhile
+pub fn block_current(deadline: Option<u64>) {
+    let mut scheduler = SCHEDULER.exclusive_access();
+    let current = scheduler.current.clone().unwrap();
+    scheduler.blocked.push((current.clone(), deadline));
+    loop {
+        if let Some(next) = scheduler.ready.pop_front() {
+            // woken while idling, so still running
+            if Arc::ptr_eq(&next, &current) {
+                return;
+            }
+            // the current thread stays with the blocked
+            switch_to(scheduler, next, |_, _| {});
+            return;
+        }
+        idle(scheduler);
+        scheduler = SCHEDULER.exclusive_access();
+    }
+}
+
+/// Let the other threads run until the next interrupt, after which what
+/// the current one waits for may have come, or idle until then if there
+/// are none ready
+pub fn wait_for_event() {
+    let mut scheduler = SCHEDULER.exclusive_access();
+    match scheduler.ready.pop_front() {
+        Some(next) => switch_to(scheduler, next, |scheduler, current| scheduler.waiting.push(current)),
+        None => idle(scheduler),
+    }
+}
+
+/// End the current thread, which must not be the last, and run the others
+pub fn exit_current() -> ! {
+    let mut scheduler = SCHEDULER.exclusive_access();
+    scheduler.count -= 1;
+    loop {
+        if let Some(next) = scheduler.ready.pop_front() {
+            switch_to(scheduler, next, |scheduler, current| scheduler.exited = Some(current));
+            unreachable!();
+        }
+        idle(scheduler);
+        scheduler = SCHEDULER.exclusive_access();
+    }
+}
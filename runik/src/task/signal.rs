@@ -0,0 +1,295 @@
+//! mod signal: POSIX signals of the app
+//!
+//! The actions are shared by the threads, while each thread has a mask and
+//! an alternate stack of its own. A signal sent to a thread pends on it,
+//! and one sent to the app pends until any thread not blocking it takes
+//! it. Signals are delivered on the way back to the app from a trap: the
+//! registers of the thread are saved in a frame on the user stack, and it
+//! enters the handler, which returns through a trampoline to
+//! rt_sigreturn(2) to load them back.
+//!
+//! A signal for a thread waiting in the kernel wakes it, and the wait fails
+//! with EINTR. There is no job control, so stop signals are ignored.
+
+use super::{ current, threads, wake, Thread };
+use crate::arch::signal::{ SignalFrame, SIGRETURN_TRAMPOLINE };
+use crate::arch::trap::TrapContext;
+use crate::mm::addr_space::KERNEL_SPACE;
+use crate::mm::user_ptr::UserPtr;
+use crate::sbi::shutdown;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::mem::size_of;
+use lazy_static::*;
+use linuxabi::errno::Errno::{ self, * };
+use linuxabi::signal::*;
+
+/// The signals that can not be caught, blocked or ignored
+const UNBLOCKABLE: SigSet = bit(SIGKILL) | bit(SIGSTOP);
+
+const fn bit(signo: u32) -> SigSet {
+    1 << (signo - 1)
+}
+
+struct Signals {
+    actions: [SigAction; NSIG],
+    /// The signals sent to the app that no thread has taken
+    pending: BTreeMap<u32, SigInfo>,
+    /// Where handlers return to, for rt_sigreturn(2)
+    trampoline: usize,
+}
+
+lazy_static! {
+    static ref SIGNALS: UPSafeCell<Signals> = unsafe {
+        UPSafeCell::new(Signals {
+            actions: [SigAction::default(); NSIG],
+            pending: BTreeMap::new(),
+            trampoline: 0,
+        })
+    };
+}
+
+/// The signal state of a thread
+#[derive(Default)]
+pub struct ThreadSignals {
+    /// The signals blocked
+    pub mask: SigSet,
+    /// The signals sent to the thread, which do not queue
+    pending: BTreeMap<u32, SigInfo>,
+    /// The alternate signal stack, of sigaltstack(2)
+    pub altstack: SignalStack,
+}
+
+impl ThreadSignals {
+    /// Block the signals of `mask` but those that can not be
+    pub fn set_mask(&mut self, mask: SigSet) {
+        self.mask = mask & !UNBLOCKABLE;
+    }
+
+    /// The ss_flags of the alternate stack when the thread is at `sp`
+    pub fn altstack_flags(&self, sp: usize) -> i32 {
+        if self.is_on_altstack(sp) {
+            SS_ONSTACK
+        } else {
+            self.altstack.ss_flags
+        }
+    }
+
+    pub fn is_on_altstack(&self, sp: usize) -> bool {
+        let stack = &self.altstack;
+        stack.ss_flags & SS_DISABLE == 0 && stack.ss_sp < sp && sp <= stack.ss_sp + stack.ss_size
+    }
+}
+
+/// Map the trampoline handlers return through
+pub fn init() {
+    let trampoline = KERNEL_SPACE.exclusive_access().map_code(&SIGRETURN_TRAMPOLINE);
+    SIGNALS.exclusive_access().trampoline = trampoline;
+}
+
+pub fn is_valid(signo: u32) -> bool {
+    (1..=NSIG as u32).contains(&signo)
+}
+
+pub fn is_unblockable(signo: u32) -> bool {
+    UNBLOCKABLE & bit(signo) != 0
+}
+
+fn ignored_by_default(signo: u32) -> bool {
+    matches!(signo, SIGCHLD | SIGCONT | SIGURG | SIGWINCH | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU)
+}
+
+fn is_ignored(action: &SigAction, signo: u32) -> bool {
+    action.sa_handler == SIG_IGN || (action.sa_handler == SIG_DFL && ignored_by_default(signo))
+}
+
+/// The action of `signo`
+pub fn action(signo: u32) -> SigAction {
+    SIGNALS.exclusive_access().actions[signo as usize - 1]
+}
+
+/// Set the action of `signo`, discarding the signal where it pends if it
+/// is now ignored
+pub fn set_action(signo: u32, mut action: SigAction) {
+    action.sa_mask &= !UNBLOCKABLE;
+    let mut signals = SIGNALS.exclusive_access();
+    signals.actions[signo as usize - 1] = action;
+    if is_ignored(&action, signo) {
+        signals.pending.remove(&signo);
+        drop(signals);
+        for thread in threads() {
+            thread.signals().pending.remove(&signo);
+        }
+    }
+}
+
+/// Send `info` to `thread`
+pub fn send_to_thread(thread: &Arc<Thread>, info: SigInfo) {
+    let signo = info.si_signo as u32;
+    let action = action(signo);
+    let mut signals = thread.signals();
+    let blocked = signals.mask & bit(signo) != 0;
+    // a blocked signal pends, as the action may change before it is taken
+    if !blocked && is_ignored(&action, signo) {
+        return;
+    }
+    signals.pending.entry(signo).or_insert(info);
+    drop(signals);
+    if !blocked {
+        wake(thread);
+    }
+}
+
+/// Send `info` to the app, for any thread not blocking it to take
+pub fn send_to_app(info: SigInfo) {
+    let signo = info.si_signo as u32;
+    let action = action(signo);
+    let mut takers = threads();
+    takers.retain(|thread| thread.signals().mask & bit(signo) == 0);
+    if !takers.is_empty() && is_ignored(&action, signo) {
+        return;
+    }
+    SIGNALS.exclusive_access().pending.entry(signo).or_insert(info);
+    // in case all that could take it are blocked in the kernel
+    for thread in takers {
+        if wake(&thread) {
+            break;
+        }
+    }
+}
+
+/// Send `signo` to the current thread, as for a syscall it made
+pub fn raise(signo: u32) {
+    send_to_thread(&current(), SigInfo::new(signo, SI_USER));
+}
+
+/// Send `signo` for a fault at `addr` to the current thread, which takes
+/// the default action if it blocks or ignores the signal
+pub fn force(signo: u32, code: i32, addr: usize) {
+    let mut info = SigInfo::new(signo, code);
+    info.si_fields[0] = addr;
+    let thread = current();
+    let mut thread_signals = thread.signals();
+    let mut signals = SIGNALS.exclusive_access();
+    let action = &mut signals.actions[signo as usize - 1];
+    if thread_signals.mask & bit(signo) != 0 || action.sa_handler == SIG_IGN {
+        *action = SigAction::default();
+        thread_signals.mask &= !bit(signo);
+    }
+    thread_signals.pending.insert(signo, info);
+}
+
+/// Whether the current thread has a signal to take, for which it stops
+/// waiting in the kernel
+pub fn interrupted() -> bool {
+    let thread = current();
+    let thread_signals = thread.signals();
+    let signals = SIGNALS.exclusive_access();
+    thread_signals.pending.keys().chain(signals.pending.keys()).any(|&signo| {
+        thread_signals.mask & bit(signo) == 0 && !is_ignored(&signals.actions[signo as usize - 1], signo)
+    })
+}
+
+/// Take the lowest signal `thread` does not block, first of those sent to
+/// it and then of those sent to the app
+fn take(thread: &Thread) -> Option<SigInfo> {
+    let mut thread_signals = thread.signals();
+    let mask = thread_signals.mask;
+    let takes = |signo: &&u32| mask & bit(**signo) == 0;
+    if let Some(&signo) = thread_signals.pending.keys().find(takes) {
+        return thread_signals.pending.remove(&signo);
+    }
+    let mut signals = SIGNALS.exclusive_access();
+    let signo = *signals.pending.keys().find(takes)?;
+    signals.pending.remove(&signo)
+}
+
+/// End the app for `signo`, as its default action
+fn terminate(signo: u32) -> ! {
+    println!("[kernel] Application killed by signal {}", signo);
+    shutdown(true);
+}
+
+/// Save `cx` in a frame for the handler of `action`, on the alternate stack
+/// if it asks for it, and have `cx` enter the handler
+fn enter_handler(thread: &Thread, cx: &mut TrapContext, info: SigInfo, action: &SigAction) -> Result<(), Errno> {
+    let signo = info.si_signo as u32;
+    let thread_signals = thread.signals();
+    let sp = cx.x[2];
+    let mut stack = thread_signals.altstack;
+    let top = if action.sa_flags & SA_ONSTACK != 0
+        && stack.ss_flags & SS_DISABLE == 0
+        && !thread_signals.is_on_altstack(sp)
+    {
+        stack.ss_sp + stack.ss_size
+    } else {
+        sp
+    };
+    stack.ss_flags = thread_signals.altstack_flags(sp);
+    let frame = SignalFrame::new(info, cx, thread_signals.mask, stack);
+    drop(thread_signals);
+    let frame_addr = top.checked_sub(size_of::<SignalFrame>()).ok_or(EFAULT)? & !0xf;
+    UserPtr::<SignalFrame>::new(frame_addr).write(frame)?;
+    let trampoline = SIGNALS.exclusive_access().trampoline;
+    SignalFrame::enter(cx, action.sa_handler, signo, frame_addr, trampoline);
+    Ok(())
+}
+
+/// Deliver the signals the current thread takes on its way back to the app
+/// with `cx`. `restart` has the first argument of a syscall that failed with
+/// EINTR, which is made again unless a handler without SA_RESTART runs.
+pub fn deliver(cx: &mut TrapContext, mut restart: Option<usize>) {
+    let thread = current();
+    while let Some(info) = take(&thread) {
+        let signo = info.si_signo as u32;
+        let action = action(signo);
+        if is_ignored(&action, signo) {
+            continue;
+        }
+        if action.sa_handler == SIG_DFL {
+            terminate(signo);
+        }
+        if let Some(a0) = restart.take() {
+            if action.sa_flags & SA_RESTART != 0 {
+                // back to the ecall
+                cx.sepc -= 4;
+                cx.x[10] = a0;
+            }
+        }
+        if enter_handler(&thread, cx, info, &action).is_err() {
+            // no room for the frame
+            terminate(SIGSEGV);
+        }
+        let mut thread_signals = thread.signals();
+        let mut mask = thread_signals.mask | action.sa_mask;
+        if action.sa_flags & SA_NODEFER == 0 {
+            mask |= bit(signo);
+        }
+        thread_signals.set_mask(mask);
+        drop(thread_signals);
+        if action.sa_flags & SA_RESETHAND != 0 {
+            SIGNALS.exclusive_access().actions[signo as usize - 1] = SigAction::default();
+        }
+    }
+    if let Some(a0) = restart {
+        cx.sepc -= 4;
+        cx.x[10] = a0;
+    }
+}
+
+/// Load back `cx`, with the mask and alternate stack, from the frame of
+/// the handler returning, and give the a0 to return with
+pub fn sigreturn(cx: &mut TrapContext) -> Result<usize, Errno> {
+    let frame = UserPtr::<SignalFrame>::new(cx.x[2]).read()?;
+    let (mask, stack) = frame.restore(cx);
+    let thread = current();
+    let mut thread_signals = thread.signals();
+    thread_signals.set_mask(mask);
+    thread_signals.altstack = if stack.ss_flags & SS_DISABLE != 0 {
+        SignalStack::default()
+    } else {
+        SignalStack { ss_sp: stack.ss_sp, ss_flags: 0, ss_size: stack.ss_size }
+    };
+    Ok(cx.x[10])
+}
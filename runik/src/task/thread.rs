@@ -0,0 +1,101 @@
+//! mod thread: the threads of the app
+
+use super::signal::ThreadSignals;
+use crate::arch::task::{ FpContext, TaskContext };
+use crate::arch::trap::TrapContext;
+use crate::kernel_stack::KernelStack;
+use crate::sync::UPSafeCell;
+use core::cell::RefMut;
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use linuxabi::errno::Errno::{ self, * };
+
+/// The pid of the app, which is the tid of its first thread
+pub const PID: usize = 1;
+
+/// The tid of the next thread
+static NEXT_TID: AtomicUsize = AtomicUsize::new(PID);
+
+pub struct Thread {
+    tid: usize,
+    kstack: KernelStack,
+    inner: UPSafeCell<ThreadInner>,
+    signals: UPSafeCell<ThreadSignals>,
+}
+
+struct ThreadInner {
+    /// Where the thread is stopped in the kernel while not running
+    task_cx: TaskContext,
+    /// The address of the tid to clear when the thread exits, from
+    /// CLONE_CHILD_CLEARTID or set_tid_address(2)
+    clear_child_tid: usize,
+    /// The FP registers of the thread in the app, while not on the hart
+    fp_cx: FpContext,
+    /// The nanoseconds the thread has run, up to when it was last charged
+    cpu_ns: u64,
+}
+
+impl Thread {
+    /// A thread that returns to the app with `cx`, first running `entry` on
+    /// its kernel stack
+    pub fn new(cx: TrapContext, entry: usize) -> Result<Self, Errno> {
+        let kstack = KernelStack::new().ok_or(ENOMEM)?;
+        let cx_ptr = kstack.push_context(cx) as *mut TrapContext as usize;
+        Ok(Self {
+            tid: NEXT_TID.fetch_add(1, Ordering::Relaxed),
+            kstack,
+            inner: unsafe {
+                UPSafeCell::new(ThreadInner {
+                    task_cx: TaskContext::new(entry, cx_ptr),
+                    clear_child_tid: 0,
+                    fp_cx: FpContext::default(),
+                    cpu_ns: 0,
+                })
+            },
+            signals: unsafe { UPSafeCell::new(ThreadSignals::default()) },
+        })
+    }
+
+    pub fn tid(&self) -> usize {
+        self.tid
+    }
+
+    /// The registers of the thread in the app, saved while in the kernel
+    pub fn trap_context(&self) -> &'static mut TrapContext {
+        self.kstack.trap_context()
+    }
+
+    pub fn task_context_ptr(&self) -> *mut TaskContext {
+        &mut self.inner.exclusive_access().task_cx as *mut TaskContext
+    }
+
+    /// Save the FP registers on the hart as those of the thread
+    pub fn save_fp(&self) {
+        self.inner.exclusive_access().fp_cx.save();
+        self.trap_context().set_fp_clean();
+    }
+
+    /// Load the FP registers of the thread on the hart
+    pub fn restore_fp(&self) {
+        self.inner.exclusive_access().fp_cx.restore();
+    }
+
+    pub fn cpu_ns(&self) -> u64 {
+        self.inner.exclusive_access().cpu_ns
+    }
+
+    pub fn add_cpu_ns(&self, ns: u64) {
+        self.inner.exclusive_access().cpu_ns += ns;
+    }
+
+    pub fn clear_child_tid(&self) -> usize {
+        self.inner.exclusive_access().clear_child_tid
+    }
+
+    pub fn set_clear_child_tid(&self, addr: usize) {
+        self.inner.exclusive_access().clear_child_tid = addr;
+    }
+
+    pub fn signals(&self) -> RefMut<'_, ThreadSignals> {
+        self.signals.exclusive_access()
+    }
+}
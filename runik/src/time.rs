@@ -0,0 +1,42 @@
+//! The clocks the app reads, over the machine clock
+//!
+//! The monotonic clock counts from boot. The realtime clock is the
+//! monotonic one plus an offset, which the RTC sets at boot and
+//! clock_settime changes, so that it never drifts from the monotonic one.
+
+use crate::arch::time::get_clock;
+use crate::plat::qemu::CLOCK_FREQ;
+use core::sync::atomic::{ AtomicI64, Ordering };
+use linuxabi::errno::Errno;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// The realtime clock less the monotonic clock, in nanoseconds
+static REALTIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// Nanoseconds since boot
+pub fn monotonic_ns() -> u64 {
+    (get_clock() as u128 * NSEC_PER_SEC as u128 / CLOCK_FREQ as u128) as u64
+}
+
+/// Nanoseconds since the epoch
+pub fn realtime_ns() -> i64 {
+    monotonic_ns() as i64 + REALTIME_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Set the realtime clock to `ns` nanoseconds since the epoch
+pub fn set_realtime_ns(ns: i64) {
+    REALTIME_OFFSET.store(ns - monotonic_ns() as i64, Ordering::Relaxed);
+}
+
+/// The smallest step of the clocks, in nanoseconds
+pub fn resolution_ns() -> u64 {
+    NSEC_PER_SEC.div_ceil(CLOCK_FREQ as u64)
+}
+
+/// Wait until the monotonic clock reaches `deadline` nanoseconds, letting
+/// other threads run, or fail with EINTR if a signal comes first
+pub fn sleep_until(deadline: u64) -> Result<(), Errno> {
+    let clock = (deadline as u128 * CLOCK_FREQ as u128).div_ceil(NSEC_PER_SEC as u128);
+    crate::arch::time::sleep_until(clock.min(usize::MAX as u128) as usize)
+}